@@ -0,0 +1,334 @@
+use serde::Deserialize;
+use std::{error::Error, fmt::Display, fs, path::Path, str::FromStr, sync::OnceLock};
+
+/// Read when `CAH_CONFIG` does not name another file. A missing file just
+/// means the defaults and environment apply.
+pub const DEFAULT_CONFIG_PATH: &str = "./cah.toml";
+
+/// Deployment settings, read from a TOML file and then overridden by the
+/// environment, so the same build runs anywhere.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// `CAH_MONGO_URI`. Credentials belong here, not in the source.
+    pub mongo_uri: String,
+    /// `CAH_DATABASE`, the library this deployment serves.
+    pub database: String,
+    /// `CAH_DATABASES`, comma separated: other libraries, such as a staging
+    /// one, that admins may point privileged routes at with `X-Database`.
+    pub databases: Vec<String>,
+    /// `CAH_BIND_ADDR`
+    pub bind_addr: String,
+    /// `CAH_PORT`
+    pub port: u16,
+    /// `CAH_WORKERS`
+    pub workers: usize,
+    /// `CAH_TEMP_DIR`, where uploads and downloads are spooled.
+    pub temp_dir: String,
+    /// `CAH_IMPORT_DIR`, where the sheets of import jobs are kept until the
+    /// job is done.
+    pub import_dir: String,
+    /// `CAH_EXPORT_DIR`, where export snapshots are written.
+    pub export_dir: String,
+    /// `CAH_TEMP_RETENTION_HOURS`, how long files may sit in `temp_dir`
+    /// before the retention job removes them.
+    pub temp_retention_hours: u64,
+    /// `CAH_IMPORT_RETENTION_HOURS`, how long the sheet of a failed import
+    /// is kept so the job can be resumed.
+    pub import_retention_hours: u64,
+    /// `CAH_EXPORT_RETENTION_HOURS`
+    pub export_retention_hours: u64,
+    /// `CAH_SYNC_URL`, a sheet to re-import on a schedule. Unset turns
+    /// syncing off.
+    pub sync_url: Option<String>,
+    /// `CAH_SYNC_INTERVAL_SECS`
+    pub sync_interval_secs: u64,
+    /// `CAH_BOOTSTRAP_SOURCE`, the starter deck `bootstrap` imports: a URL,
+    /// or a local sheet or JSON deck.
+    pub bootstrap_source: String,
+    /// `CAH_TYPOGRAPHIC_QUOTES`: imports turn straight quotes and dashes in
+    /// card text into curly quotes and em dashes.
+    pub typographic_quotes: bool,
+    /// `CAH_RATING_WORDLIST`, a file of words that rate imported cards,
+    /// see `parser::RatingWordlist`. Unset leaves imported cards unrated.
+    pub rating_wordlist: Option<String>,
+    /// `CAH_SCREEN_LIST`, a file of banned terms that imports hold cards
+    /// back for, see `parser::ScreenList`. Unset screens nothing.
+    pub screen_list: Option<String>,
+    /// `CAH_UPLOAD_CONCURRENCY`, how many uploaded sheets are parsed and
+    /// stored at once across all requests.
+    pub upload_concurrency: usize,
+    /// `CAH_GAME_TOKEN`, which the game server sends as `X-Game-Token` when
+    /// it reports plays. Unset leaves reporting to admins.
+    pub game_token: Option<String>,
+    /// `CAH_ADMIN_TOKEN`, which operators send as `X-Admin-Token`. Unset
+    /// makes no request an admin.
+    pub admin_token: Option<String>,
+    /// `CAH_MAX_DOWNLOAD_BYTES`, the largest sheet an import fetches by URL.
+    pub max_download_bytes: u64,
+    /// `CAH_MAX_ARCHIVE_ENTRIES`, the most sheets one uploaded archive may
+    /// hold.
+    pub max_archive_entries: usize,
+    /// `CAH_MAX_ARCHIVE_BYTES`, how much one uploaded archive may unpack to.
+    pub max_archive_bytes: u64,
+    /// `CAH_MAX_ROWS`, the most rows one sheet may have. This and the other
+    /// sheet bounds make up `parser::ParseLimits`.
+    pub max_rows: usize,
+    /// `CAH_MAX_COLUMNS`
+    pub max_columns: usize,
+    /// `CAH_MAX_CELL_BYTES`
+    pub max_cell_bytes: usize,
+    /// `CAH_MAX_SETS`, the most sets one sheet or deck may hold.
+    pub max_sets: usize,
+    /// `CAH_MONGO_MAX_POOL_SIZE`. This and the other `mongo_*` settings
+    /// leave the driver default in place when unset.
+    pub mongo_max_pool_size: Option<u32>,
+    /// `CAH_MONGO_MIN_POOL_SIZE`
+    pub mongo_min_pool_size: Option<u32>,
+    /// `CAH_MONGO_CONNECT_TIMEOUT_MS`
+    pub mongo_connect_timeout_ms: Option<u64>,
+    /// `CAH_MONGO_SERVER_SELECTION_TIMEOUT_MS`
+    pub mongo_server_selection_timeout_ms: Option<u64>,
+    /// `CAH_MONGO_READ_CONCERN`, such as `majority`.
+    pub mongo_read_concern: Option<String>,
+    /// `CAH_MONGO_WRITE_CONCERN`: a number of nodes, `majority` or a
+    /// replica set tag.
+    pub mongo_write_concern: Option<String>,
+    /// `CAH_RETRY_ATTEMPTS`, how many times a write that failed in a way
+    /// that may clear up is tried in all, see `storage::RetryPolicy`.
+    pub retry_attempts: u32,
+    /// `CAH_RETRY_BASE_MS`, the longest wait before the first retry. It
+    /// doubles with each retry up to `retry_max_ms`.
+    pub retry_base_ms: u64,
+    /// `CAH_RETRY_MAX_MS`
+    pub retry_max_ms: u64,
+    /// `CAH_MAX_IN_FLIGHT`, how many requests are worked on at once before
+    /// more are turned away with a 503.
+    pub max_in_flight: usize,
+    /// `CAH_MAX_CONNECTIONS`, how many connections each worker keeps open
+    /// before it stops accepting more.
+    pub max_connections: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mongo_uri: "mongodb://localhost:27017".to_string(),
+            database: "controversy".to_string(),
+            databases: Vec::new(),
+            bind_addr: "127.0.0.1".to_string(),
+            port: 12001,
+            workers: 2,
+            temp_dir: "./tmp".to_string(),
+            import_dir: "./imports".to_string(),
+            export_dir: "./exports".to_string(),
+            temp_retention_hours: 24,
+            import_retention_hours: 30 * 24,
+            export_retention_hours: 24,
+            sync_url: None,
+            sync_interval_secs: 24 * 60 * 60,
+            bootstrap_source: "./data/Cards Against Humanity - CAH Main Deck.csv".to_string(),
+            typographic_quotes: false,
+            rating_wordlist: None,
+            screen_list: None,
+            upload_concurrency: 4,
+            game_token: None,
+            admin_token: None,
+            max_download_bytes: 64 << 20,
+            max_archive_entries: 64,
+            max_archive_bytes: 256 << 20,
+            max_rows: 200_000,
+            max_columns: 1_024,
+            max_cell_bytes: 4_096,
+            max_sets: 256,
+            mongo_max_pool_size: None,
+            mongo_min_pool_size: None,
+            mongo_connect_timeout_ms: None,
+            mongo_server_selection_timeout_ms: None,
+            mongo_read_concern: None,
+            mongo_write_concern: None,
+            retry_attempts: 4,
+            retry_base_ms: 100,
+            retry_max_ms: 5_000,
+            max_in_flight: 512,
+            max_connections: 25_000,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the file named by `CAH_CONFIG`, or `DEFAULT_CONFIG_PATH` if it
+    /// exists, then applies the `CAH_*` overrides. A named file that is
+    /// missing, or any file that does not parse, is an error.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let mut config = match std::env::var("CAH_CONFIG") {
+            Ok(path) => Config::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            Err(_) => Config::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("invalid {}: {e}", path.display()).into())
+    }
+
+    fn apply_env(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(uri) = env_value("CAH_MONGO_URI")? {
+            self.mongo_uri = uri;
+        }
+        if let Some(database) = env_value("CAH_DATABASE")? {
+            self.database = database;
+        }
+        if let Some(databases) = env_value::<String>("CAH_DATABASES")? {
+            self.databases = databases
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(addr) = env_value("CAH_BIND_ADDR")? {
+            self.bind_addr = addr;
+        }
+        if let Some(port) = env_value("CAH_PORT")? {
+            self.port = port;
+        }
+        if let Some(workers) = env_value("CAH_WORKERS")? {
+            self.workers = workers;
+        }
+        if let Some(dir) = env_value("CAH_TEMP_DIR")? {
+            self.temp_dir = dir;
+        }
+        if let Some(dir) = env_value("CAH_IMPORT_DIR")? {
+            self.import_dir = dir;
+        }
+        if let Some(dir) = env_value("CAH_EXPORT_DIR")? {
+            self.export_dir = dir;
+        }
+        if let Some(hours) = env_value("CAH_TEMP_RETENTION_HOURS")? {
+            self.temp_retention_hours = hours;
+        }
+        if let Some(hours) = env_value("CAH_IMPORT_RETENTION_HOURS")? {
+            self.import_retention_hours = hours;
+        }
+        if let Some(hours) = env_value("CAH_EXPORT_RETENTION_HOURS")? {
+            self.export_retention_hours = hours;
+        }
+        if let Some(url) = env_value("CAH_SYNC_URL")? {
+            self.sync_url = Some(url);
+        }
+        if let Some(secs) = env_value("CAH_SYNC_INTERVAL_SECS")? {
+            self.sync_interval_secs = secs;
+        }
+        if let Some(source) = env_value("CAH_BOOTSTRAP_SOURCE")? {
+            self.bootstrap_source = source;
+        }
+        if let Some(flag) = env_value("CAH_TYPOGRAPHIC_QUOTES")? {
+            self.typographic_quotes = flag;
+        }
+        if let Some(path) = env_value("CAH_RATING_WORDLIST")? {
+            self.rating_wordlist = Some(path);
+        }
+        if let Some(path) = env_value("CAH_SCREEN_LIST")? {
+            self.screen_list = Some(path);
+        }
+        if let Some(permits) = env_value("CAH_UPLOAD_CONCURRENCY")? {
+            self.upload_concurrency = permits;
+        }
+        if let Some(token) = env_value("CAH_GAME_TOKEN")? {
+            self.game_token = Some(token);
+        }
+        if let Some(token) = env_value("CAH_ADMIN_TOKEN")? {
+            self.admin_token = Some(token);
+        }
+        if let Some(bytes) = env_value("CAH_MAX_DOWNLOAD_BYTES")? {
+            self.max_download_bytes = bytes;
+        }
+        if let Some(entries) = env_value("CAH_MAX_ARCHIVE_ENTRIES")? {
+            self.max_archive_entries = entries;
+        }
+        if let Some(bytes) = env_value("CAH_MAX_ARCHIVE_BYTES")? {
+            self.max_archive_bytes = bytes;
+        }
+        if let Some(rows) = env_value("CAH_MAX_ROWS")? {
+            self.max_rows = rows;
+        }
+        if let Some(columns) = env_value("CAH_MAX_COLUMNS")? {
+            self.max_columns = columns;
+        }
+        if let Some(bytes) = env_value("CAH_MAX_CELL_BYTES")? {
+            self.max_cell_bytes = bytes;
+        }
+        if let Some(sets) = env_value("CAH_MAX_SETS")? {
+            self.max_sets = sets;
+        }
+        if let Some(size) = env_value("CAH_MONGO_MAX_POOL_SIZE")? {
+            self.mongo_max_pool_size = Some(size);
+        }
+        if let Some(size) = env_value("CAH_MONGO_MIN_POOL_SIZE")? {
+            self.mongo_min_pool_size = Some(size);
+        }
+        if let Some(ms) = env_value("CAH_MONGO_CONNECT_TIMEOUT_MS")? {
+            self.mongo_connect_timeout_ms = Some(ms);
+        }
+        if let Some(ms) = env_value("CAH_MONGO_SERVER_SELECTION_TIMEOUT_MS")? {
+            self.mongo_server_selection_timeout_ms = Some(ms);
+        }
+        if let Some(level) = env_value("CAH_MONGO_READ_CONCERN")? {
+            self.mongo_read_concern = Some(level);
+        }
+        if let Some(w) = env_value("CAH_MONGO_WRITE_CONCERN")? {
+            self.mongo_write_concern = Some(w);
+        }
+        if let Some(attempts) = env_value("CAH_RETRY_ATTEMPTS")? {
+            self.retry_attempts = attempts;
+        }
+        if let Some(ms) = env_value("CAH_RETRY_BASE_MS")? {
+            self.retry_base_ms = ms;
+        }
+        if let Some(ms) = env_value("CAH_RETRY_MAX_MS")? {
+            self.retry_max_ms = ms;
+        }
+        if let Some(requests) = env_value("CAH_MAX_IN_FLIGHT")? {
+            self.max_in_flight = requests;
+        }
+        if let Some(connections) = env_value("CAH_MAX_CONNECTIONS")? {
+            self.max_connections = connections;
+        }
+        Ok(())
+    }
+}
+
+/// Reads and parses the variable `name`, treating an empty one as unset.
+fn env_value<T: FromStr>(name: &str) -> Result<Option<T>, String>
+where
+    T::Err: Display,
+{
+    match std::env::var(name).ok().filter(|v| !v.is_empty()) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("invalid {name} {value:?}: {e}")),
+        None => Ok(None),
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Loads the configuration for the rest of the process. Binaries call this
+/// first so a bad file is reported instead of panicking later in `get`.
+pub fn init() -> Result<&'static Config, Box<dyn Error>> {
+    let config = Config::load()?;
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+/// The process configuration, loaded on first use if `init` was not called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load().expect("invalid configuration"))
+}
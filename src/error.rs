@@ -0,0 +1,85 @@
+use actix_web::{http::StatusCode, ResponseError};
+use thiserror::Error;
+
+use crate::parser::LimitExceeded;
+use crate::storage::CardWriteFailure;
+
+/// Failures the library surfaces to callers, each with the status a
+/// handler should answer with.
+#[derive(Debug, Error)]
+pub enum AppError {
+    /// The sheet is not valid CSV.
+    #[error("invalid sheet at {location}: {message}")]
+    Csv { location: String, message: String },
+    /// The deck is not valid JSON or not in the expected shape.
+    #[error("invalid deck at {location}: {message}")]
+    Json { location: String, message: String },
+    /// The file is not a workbook that can be read.
+    #[error("invalid workbook: {0}")]
+    Workbook(String),
+    /// The sheet is valid but larger than this instance accepts.
+    #[error(transparent)]
+    Limit(#[from] LimitExceeded),
+    /// The database refused some cards of a set, which was not stored.
+    #[error("{0}")]
+    Cards(CardWriteFailure),
+    #[error("database unavailable: {0}")]
+    Database(mongodb::error::Error),
+    /// The upload itself is unusable, before any parsing starts.
+    #[error("{0}")]
+    Upload(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<csv::Error> for AppError {
+    fn from(err: csv::Error) -> Self {
+        let line = err.position().map(|p| p.line());
+        let column = match err.kind() {
+            csv::ErrorKind::Utf8 { err, .. } => Some(err.field() + 1),
+            _ => None,
+        };
+        let location = match (line, column) {
+            (Some(line), Some(column)) => format!("line {line}, column {column}"),
+            (Some(line), None) => format!("line {line}"),
+            _ => "an unknown position".to_string(),
+        };
+        AppError::Csv {
+            location,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(err: mongodb::error::Error) -> Self {
+        match err.get_custom::<CardWriteFailure>() {
+            Some(failure) => AppError::Cards(failure.clone()),
+            None => AppError::Database(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        AppError::Json {
+            location: format!("line {}, column {}", err.line(), err.column()),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Csv { .. }
+            | AppError::Json { .. }
+            | AppError::Workbook(_)
+            | AppError::Limit(_)
+            | AppError::Cards(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Database(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Upload(_) => StatusCode::BAD_REQUEST,
+            AppError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
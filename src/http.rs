@@ -0,0 +1,5899 @@
+use actix_multipart::form::{
+    tempfile::{TempFile, TempFileConfig},
+    text::Text,
+    MultipartForm,
+};
+use serde::Serialize;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    error::Error,
+    fs::{self, File},
+    future::{ready, Future, Ready},
+    io::{self, Read, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use futures::{future::try_join_all, stream::LocalBoxStream, Stream, StreamExt, TryStreamExt};
+use mongodb::{
+    bson::{doc, from_document, to_bson, Bson, DateTime, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{
+        FindOneAndReplaceOptions, FindOneAndUpdateOptions, FindOptions, IndexOptions,
+        ReplaceOptions, ReturnDocument, UpdateOptions,
+    },
+    ClientSession, Collection, Cursor, Database, IndexModel,
+};
+
+use actix_web::{
+    body::SizedStream,
+    dev::{Payload, Service},
+    error::{
+        ErrorBadRequest, ErrorConflict, ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+        ErrorUnauthorized, ErrorUnprocessableEntity, InternalError,
+    },
+    http::Method,
+    web::{self, Bytes},
+    App, Error as ActixError, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter},
+    sync::{mpsc, OnceCell, Semaphore},
+};
+use uuid::Uuid;
+
+use crate::config::{self, Config};
+use crate::error::AppError;
+use crate::migrations::migrate;
+use crate::model::{
+    normalize_text, Card, CardRevision, CardSnapshot, CardType, CardTypes, ColumnProfile, Edition,
+    IdMode, Rating, RoundRules, Set, SetChanges, SetStatus, SetVersion, Suite, Vote, VoteTally,
+};
+use crate::parser::{
+    detect_delimiter, encoding_warning, looks_like_json, parse_csv_file, parse_field,
+    parse_json_deck, read_workbook, transcode_to_utf8, CardRules, ParseLimits, ParseMode,
+    ParseReport, ParseWarning, SetReader, BLANK,
+};
+use crate::repository::{CardRepository, SetRepository};
+use crate::storage::{
+    allowed_databases, database, ensure_indexes, index_model, index_name, match_editions,
+    preview_set, record_revision, snapshot_set, special_fields, uuid_bson, Store, INDEXES,
+};
+
+mod analytics;
+mod exports;
+mod imports;
+mod tags;
+mod workspaces;
+
+use analytics::*;
+use exports::*;
+use imports::*;
+use tags::*;
+use workspaces::*;
+
+fn bson_value<T: Serialize>(value: &T) -> Result<Bson, ActixError> {
+    to_bson(value).map_err(ErrorInternalServerError)
+}
+
+/// A row out of a `$group` stage keyed by a string with a `count` total.
+#[derive(Debug, Deserialize)]
+struct GroupCount {
+    #[serde(rename = "_id")]
+    key: String,
+    count: i64,
+}
+
+async fn group_counts(
+    database: &Database,
+    collection: &str,
+    pipeline: Vec<Document>,
+) -> Result<Vec<GroupCount>, ActixError> {
+    let documents: Vec<Document> = database
+        .collection::<Document>(collection)
+        .aggregate(pipeline, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    documents
+        .into_iter()
+        .map(|d| from_document(d).map_err(ErrorInternalServerError))
+        .collect()
+}
+
+impl RoundRules {
+    /// Checks a submission against the rules, as a round would before
+    /// accepting it.
+    fn check_submission(&self, responses: usize) -> Result<(), ActixError> {
+        if responses != self.pick as usize {
+            return Err(ErrorBadRequest(format!(
+                "this prompt takes {} responses, got {responses}",
+                self.pick
+            )));
+        }
+        Ok(())
+    }
+}
+
+async fn find_card(cards: &Collection<Card>, id: &Uuid) -> Result<Card, ActixError> {
+    cards
+        .find_one(doc! { "uuid": uuid_bson(id), "deleted_at": null }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("card {id} not found")))
+}
+
+/// Cards of a translation group the public may see, see `visible_cards`.
+async fn card_group_members(
+    database: &Database,
+    group: &Uuid,
+    safe: bool,
+) -> Result<Vec<Card>, ActixError> {
+    let filter = visible_cards(database, doc! { "card_group": uuid_bson(group) }, safe).await?;
+    database
+        .collection::<Card>("cards")
+        .find(filter, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(|e| AppError::from(e).into())
+}
+
+#[derive(Debug, Serialize)]
+struct Translations {
+    card_group: Option<Uuid>,
+    cards: Vec<Card>,
+}
+
+async fn get_translations(
+    path: web::Path<Uuid>,
+    safe_mode: SafeMode,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let card = find_card(&cards, &path).await?;
+    let translations = match card.card_group {
+        Some(group) => card_group_members(database, &group, safe_mode.0)
+            .await?
+            .into_iter()
+            .filter(|c| c.uuid != card.uuid)
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(web::Json(Translations {
+        card_group: card.card_group,
+        cards: translations,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct LinkRequest {
+    card: Uuid,
+}
+
+/// Puts both cards in one group, folding any group either already belongs to.
+async fn link_translation(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    body: web::Json<LinkRequest>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let source = find_card(&cards, &path).await?;
+    let target = find_card(&cards, &body.card).await?;
+
+    let group = source
+        .card_group
+        .or(target.card_group)
+        .unwrap_or_else(Uuid::new_v4);
+    let groups: Vec<Bson> = [source.card_group, target.card_group]
+        .iter()
+        .flatten()
+        .map(uuid_bson)
+        .collect();
+    let filter = doc! {
+        "$or": [
+            { "uuid": { "$in": [uuid_bson(&source.uuid), uuid_bson(&target.uuid)] } },
+            { "card_group": { "$in": groups } },
+        ]
+    };
+    cards
+        .update_many(
+            filter,
+            doc! { "$set": { "card_group": uuid_bson(&group) } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?;
+    audit(
+        database,
+        user.as_ref(),
+        "link",
+        "card",
+        Some(source.uuid),
+        doc! {
+            "card_group": {
+                "before": source.card_group.as_ref().map(uuid_bson),
+                "after": uuid_bson(&group),
+            }
+        },
+        doc! { "card": uuid_bson(&target.uuid) },
+    )
+    .await;
+
+    Ok(web::Json(Translations {
+        card_group: Some(group),
+        cards: card_group_members(database, &group, false).await?,
+    }))
+}
+
+/// Detaches the card from its group, dissolving the group if one card is left.
+async fn unlink_translation(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let card = find_card(&cards, &path).await?;
+    let Some(group) = card.card_group else {
+        return Ok(HttpResponse::NoContent().finish());
+    };
+
+    cards
+        .update_one(
+            doc! { "uuid": uuid_bson(&card.uuid) },
+            doc! { "$set": { "card_group": Bson::Null } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?;
+    // Trashed and held cards still belong to the group, so they count too.
+    let remaining = cards
+        .count_documents(doc! { "card_group": uuid_bson(&group) }, None)
+        .await
+        .map_err(AppError::from)?;
+    if remaining == 1 {
+        cards
+            .update_many(
+                doc! { "card_group": uuid_bson(&group) },
+                doc! { "$set": { "card_group": Bson::Null } },
+                None,
+            )
+            .await
+            .map_err(AppError::from)?;
+    }
+    audit(
+        database,
+        user.as_ref(),
+        "unlink",
+        "card",
+        Some(card.uuid),
+        doc! { "card_group": { "before": uuid_bson(&group), "after": Bson::Null } },
+        doc! { "dissolved": remaining == 1 },
+    )
+    .await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn find_set(sets: &Collection<Set>, id: &Uuid) -> Result<Set, ActixError> {
+    sets.find_one(doc! { "uuid": uuid_bson(id), "deleted_at": null }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("set {id} not found")))
+}
+
+async fn set_cards(cards: &Collection<Card>, set: &Uuid) -> Result<Vec<Card>, ActixError> {
+    cards
+        .find(
+            doc! { "set_uuid": uuid_bson(set), "deleted_at": null },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(|e| AppError::from(e).into())
+}
+
+const HAND_SIZE: usize = 10;
+const MIN_PLAYERS: usize = 3;
+const MAX_PLAYERS: usize = 20;
+
+#[derive(Debug, Serialize)]
+struct Balance {
+    set_uuid: Uuid,
+    prompts: usize,
+    responses: usize,
+    responses_per_prompt: f64,
+    pick_2: usize,
+    pick_3: usize,
+    pick_2_share: f64,
+    pick_3_share: f64,
+    min_players: Option<usize>,
+    max_players: Option<usize>,
+    warnings: Vec<String>,
+}
+
+impl Balance {
+    fn analyze(set_uuid: Uuid, cards: &[Card]) -> Self {
+        let prompts: Vec<&Card> = cards.iter().filter(|c| c.suite == Suite::Prompt).collect();
+        let responses = cards.len() - prompts.len();
+        let pick_2 = prompts.iter().filter(|c| c.pick() == 2).count();
+        let pick_3 = prompts.iter().filter(|c| c.pick() >= 3).count();
+        let share = |n: usize| match prompts.len() {
+            0 => 0.0,
+            total => n as f64 / total as f64,
+        };
+        let picks: usize = prompts.iter().map(|c| c.pick() as usize).sum();
+        let average_pick = match prompts.len() {
+            0 => 1.0,
+            total => picks as f64 / total as f64,
+        };
+
+        // A player count is supported when, after dealing every hand, the
+        // prompts and refills last for each player to be czar once.
+        let supported: Vec<usize> = (MIN_PLAYERS..=MAX_PLAYERS)
+            .filter(|&players| {
+                let Some(spare) = responses.checked_sub(players * HAND_SIZE) else {
+                    return false;
+                };
+                let per_round = (players - 1) as f64 * average_pick;
+                prompts.len() >= players && spare as f64 / per_round >= players as f64
+            })
+            .collect();
+
+        let mut warnings = Vec::new();
+        if prompts.is_empty() {
+            warnings.push("set has no prompt cards".to_string());
+        }
+        if responses == 0 {
+            warnings.push("set has no response cards".to_string());
+        }
+        if supported.is_empty() {
+            warnings.push(format!(
+                "set cannot support a game of {MIN_PLAYERS} players on its own"
+            ));
+        }
+        let responses_per_prompt = match prompts.len() {
+            0 => 0.0,
+            total => responses as f64 / total as f64,
+        };
+        if !prompts.is_empty() && responses_per_prompt < 3.0 {
+            warnings.push(format!(
+                "only {responses_per_prompt:.1} responses per prompt, official decks have about 5"
+            ));
+        }
+
+        Balance {
+            set_uuid,
+            prompts: prompts.len(),
+            responses,
+            responses_per_prompt,
+            pick_2,
+            pick_3,
+            pick_2_share: share(pick_2),
+            pick_3_share: share(pick_3),
+            min_players: supported.first().copied(),
+            max_players: supported.last().copied(),
+            warnings,
+        }
+    }
+}
+
+async fn set_balance<R: SetRepository + CardRepository>(
+    path: web::Path<Uuid>,
+    repository: web::Data<R>,
+) -> Result<impl Responder, ActixError> {
+    let set = repository
+        .find_set(&path)
+        .await?
+        .ok_or_else(|| ErrorNotFound(format!("set {path} not found")))?;
+    let cards = repository.set_cards(&set.uuid).await?;
+    Ok(web::Json(Balance::analyze(set.uuid, &cards)))
+}
+
+#[derive(Debug, Serialize)]
+struct EditionCoverage {
+    edition: Edition,
+    prompts: usize,
+    responses: usize,
+    /// Share of the set's cards printed in this edition.
+    share: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct SetStats {
+    set_uuid: Uuid,
+    prompts: usize,
+    responses: usize,
+    pick_2: usize,
+    pick_3: usize,
+    /// Mean characters of card text, by suite.
+    average_prompt_length: f64,
+    average_response_length: f64,
+    /// Prompts by how many blanks their text has.
+    blanks: BTreeMap<usize, usize>,
+    editions: Vec<EditionCoverage>,
+}
+
+impl SetStats {
+    fn analyze(set_uuid: Uuid, cards: &[Card], editions: Vec<Edition>) -> Self {
+        let (prompts, responses): (Vec<&Card>, Vec<&Card>) =
+            cards.iter().partition(|c| c.suite == Suite::Prompt);
+        let average_length = |cards: &[&Card]| match cards.len() {
+            0 => 0.0,
+            total => {
+                cards.iter().map(|c| c.text.chars().count()).sum::<usize>() as f64 / total as f64
+            }
+        };
+        let mut blanks = BTreeMap::new();
+        for prompt in &prompts {
+            *blanks
+                .entry(prompt.text.matches(BLANK).count())
+                .or_default() += 1;
+        }
+        let editions = editions
+            .into_iter()
+            .map(|edition| {
+                let printed = |cards: &[&Card]| {
+                    cards
+                        .iter()
+                        .filter(|c| c.editions.contains(&edition.uuid))
+                        .count()
+                };
+                let (prompts, responses) = (printed(&prompts), printed(&responses));
+                let share = match cards.len() {
+                    0 => 0.0,
+                    total => (prompts + responses) as f64 / total as f64,
+                };
+                EditionCoverage {
+                    edition,
+                    prompts,
+                    responses,
+                    share,
+                }
+            })
+            .collect();
+        SetStats {
+            set_uuid,
+            prompts: prompts.len(),
+            responses: responses.len(),
+            pick_2: prompts.iter().filter(|c| c.pick() == 2).count(),
+            pick_3: prompts.iter().filter(|c| c.pick() >= 3).count(),
+            average_prompt_length: average_length(&prompts),
+            average_response_length: average_length(&responses),
+            blanks,
+            editions,
+        }
+    }
+}
+
+/// Counts for balancing a deck before printing it.
+async fn set_stats<R: SetRepository + CardRepository>(
+    path: web::Path<Uuid>,
+    repository: web::Data<R>,
+) -> Result<impl Responder, ActixError> {
+    let set = repository
+        .find_set(&path)
+        .await?
+        .ok_or_else(|| ErrorNotFound(format!("set {path} not found")))?;
+    let cards = repository.set_cards(&set.uuid).await?;
+    let editions = repository.set_editions(&set.uuid).await?;
+    Ok(web::Json(SetStats::analyze(set.uuid, &cards, editions)))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Deck {
+    uuid: Uuid,
+    name: String,
+    sets: Vec<Uuid>,
+    #[serde(default)]
+    preset: Option<Uuid>,
+    cards: Vec<Uuid>,
+    /// Who built it, when they said who they are.
+    #[serde(default)]
+    owner: Option<String>,
+    /// The deck this one was cloned from.
+    #[serde(default)]
+    cloned_from: Option<Uuid>,
+    created_at: DateTime,
+}
+
+/// A named selection of sets, editions and filters, such as "Official EN
+/// full" or "Family night", to start decks and listings from in one go.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DeckPreset {
+    uuid: Uuid,
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    sets: Vec<Uuid>,
+    /// Only cards printed in one of these editions, when not empty.
+    #[serde(default)]
+    editions: Vec<Uuid>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    exclude_tags: Vec<String>,
+    #[serde(default)]
+    nsfw: bool,
+    created_at: DateTime,
+}
+
+impl DeckPreset {
+    /// The preset's filters applied to cards of `sets`, usually its own.
+    fn filter(&self, sets: &[Uuid]) -> Document {
+        let mut filter = card_filter(sets, &self.tags, &self.exclude_tags);
+        if !self.editions.is_empty() {
+            let editions: Vec<Bson> = self.editions.iter().map(uuid_bson).collect();
+            filter.insert("editions", doc! { "$in": editions });
+        }
+        filter
+    }
+}
+
+async fn find_preset(database: &Database, id: &Uuid) -> Result<DeckPreset, ActixError> {
+    database
+        .collection::<DeckPreset>("deck_presets")
+        .find_one(doc! { "uuid": uuid_bson(id) }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("preset {id} not found")))
+}
+
+async fn list_presets(store: web::Data<Store>) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+    let presets: Vec<DeckPreset> = database
+        .collection::<DeckPreset>("deck_presets")
+        .find(None, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(presets))
+}
+
+async fn get_preset(
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    Ok(web::Json(find_preset(database, &path).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct PresetRequest {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    sets: Vec<Uuid>,
+    #[serde(default)]
+    editions: Vec<Uuid>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    exclude_tags: Vec<String>,
+    #[serde(default)]
+    nsfw: bool,
+}
+
+async fn create_preset(
+    _: Admin,
+    user: Option<User>,
+    body: web::Json<PresetRequest>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let body = body.into_inner();
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return Err(ErrorBadRequest("a preset needs a name"));
+    }
+    let preset = DeckPreset {
+        uuid: Uuid::new_v4(),
+        name,
+        description: body.description,
+        sets: body.sets,
+        editions: body.editions,
+        tags: normalize_tags(&body.tags)?,
+        exclude_tags: normalize_tags(&body.exclude_tags)?,
+        nsfw: body.nsfw,
+        created_at: DateTime::now(),
+    };
+    let database = store.database();
+    database
+        .collection::<DeckPreset>("deck_presets")
+        .insert_one(&preset, None)
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(None, Some(&preset))?;
+    audit(
+        database,
+        user.as_ref(),
+        "create",
+        "preset",
+        Some(preset.uuid),
+        changes,
+        doc! {},
+    )
+    .await;
+    Ok(HttpResponse::Created().json(preset))
+}
+
+async fn delete_preset(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let deleted = database
+        .collection::<DeckPreset>("deck_presets")
+        .find_one_and_delete(doc! { "uuid": uuid_bson(&path) }, None)
+        .await
+        .map_err(AppError::from)?;
+    if let Some(preset) = deleted {
+        let changes = field_changes(Some(&preset), None)?;
+        audit(
+            database,
+            user.as_ref(),
+            "delete",
+            "preset",
+            Some(preset.uuid),
+            changes,
+            doc! {},
+        )
+        .await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Restricts cards to the given sets, required tags and excluded tags.
+fn card_filter(sets: &[Uuid], tags: &[String], exclude_tags: &[String]) -> Document {
+    let mut filter = doc! {};
+    if !sets.is_empty() {
+        let sets: Vec<Bson> = sets.iter().map(uuid_bson).collect();
+        filter.insert("set_uuid", doc! { "$in": sets });
+    }
+    if let Some(tags) = tag_filter(tags, exclude_tags) {
+        filter.insert("tags", tags);
+    }
+    filter
+}
+
+/// Sets the public may see: published ones and, when `safe`, only those not
+/// flagged NSFW.
+fn visible_sets(safe: bool) -> Document {
+    let mut filter = doc! { "status": { "$ne": "draft" }, "deleted_at": null };
+    if safe {
+        filter.insert("nsfw", doc! { "$ne": true });
+    }
+    filter
+}
+
+/// Narrows `filter` to cards of sets the public may see, leaving out those
+/// held back by screening and, when `safe`, those flagged NSFW themselves.
+async fn visible_cards(
+    database: &Database,
+    filter: Document,
+    safe: bool,
+) -> Result<Document, ActixError> {
+    let hidden_sets = database
+        .collection::<Set>("sets")
+        .distinct("uuid", doc! { "$nor": [visible_sets(safe)] }, None)
+        .await
+        .map_err(AppError::from)?;
+    let mut clauses = vec![
+        filter,
+        doc! { "set_uuid": { "$nin": hidden_sets } },
+        doc! { "screened.0": { "$exists": false } },
+        doc! { "deleted_at": null },
+    ];
+    if safe {
+        clauses.push(doc! { "nsfw": { "$ne": true } });
+    }
+    Ok(doc! { "$and": clauses })
+}
+
+#[derive(Debug, Deserialize)]
+struct SafeModeQuery {
+    safe_mode: Option<bool>,
+}
+
+/// Set when the caller asked for stream and workplace friendly content only,
+/// with `?safe_mode=true` or an `X-Safe-Mode: true` header.
+#[derive(Debug, Clone, Copy)]
+struct SafeMode(bool);
+
+impl FromRequest for SafeMode {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let header = req
+            .headers()
+            .get("X-Safe-Mode")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| matches!(v, "1" | "true"));
+        let query = web::Query::<SafeModeQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|q| q.safe_mode);
+        ready(Ok(SafeMode(query.or(header).unwrap_or(false))))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MaxRatingQuery {
+    max_rating: Option<Rating>,
+}
+
+/// The strongest rating the caller wants to see, from `?max_rating=` or an
+/// `X-Max-Rating` header. Unset lets every card through.
+#[derive(Debug, Clone, Copy)]
+struct MaxRating(Option<Rating>);
+
+impl FromRequest for MaxRating {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let invalid = || ErrorBadRequest("max_rating must be family, mature or explicit");
+        let header = match req.headers().get("X-Max-Rating") {
+            Some(value) => {
+                let rating = value
+                    .to_str()
+                    .ok()
+                    .and_then(|v| serde_json::from_value(v.to_ascii_lowercase().into()).ok());
+                match rating {
+                    Some(rating) => Some(rating),
+                    None => return ready(Err(invalid())),
+                }
+            }
+            None => None,
+        };
+        let query = match web::Query::<MaxRatingQuery>::from_query(req.query_string()) {
+            Ok(query) => query.max_rating,
+            Err(_) => return ready(Err(invalid())),
+        };
+        ready(Ok(MaxRating(query.or(header))))
+    }
+}
+
+/// Narrows `filter` to cards rated no higher than the caller asked for.
+/// Unrated cards count as mature, or explicit when flagged NSFW.
+fn rated_cards(filter: Document, MaxRating(max): MaxRating) -> Document {
+    let ceiling = match max {
+        None | Some(Rating::Explicit) => return filter,
+        Some(Rating::Mature) => doc! { "$or": [
+            { "rating": { "$in": ["family", "mature"] } },
+            { "rating": null, "nsfw": { "$ne": true } },
+        ] },
+        Some(Rating::Family) => doc! { "rating": "family" },
+    };
+    doc! { "$and": [filter, ceiling] }
+}
+
+/// Condition on a `tags` array requiring all of `tags` and none of
+/// `exclude_tags`, or `None` when neither constrains anything.
+fn tag_filter(tags: &[String], exclude_tags: &[String]) -> Option<Document> {
+    let mut filter = doc! {};
+    if !tags.is_empty() {
+        filter.insert("$all", tags);
+    }
+    if !exclude_tags.is_empty() {
+        filter.insert("$nin", exclude_tags);
+    }
+    (!filter.is_empty()).then_some(filter)
+}
+
+fn normalize_tags(tags: &[String]) -> Result<Vec<String>, ActixError> {
+    tags.iter().map(|t| normalize_tag(t)).collect()
+}
+
+/// Parses a comma separated tag list such as `?tags=holiday,meta`.
+fn parse_tag_list(value: Option<&str>) -> Result<Vec<String>, ActixError> {
+    value
+        .into_iter()
+        .flat_map(|v| v.split(','))
+        .filter(|v| !v.trim().is_empty())
+        .map(normalize_tag)
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateDeck {
+    name: Option<String>,
+    #[serde(default)]
+    sets: Vec<Uuid>,
+    prompts: usize,
+    responses: usize,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    exclude_tags: Vec<String>,
+    #[serde(default)]
+    nsfw: bool,
+    /// Largest fraction of the prompts that may ask for two or more responses.
+    max_pick_2_share: Option<f64>,
+    /// Starts from a preset's selection. Sets given above replace the
+    /// preset's, tags narrow it further, and the preset decides whether NSFW
+    /// cards are allowed.
+    preset: Option<Uuid>,
+    /// Adds the sets the chosen ones require; otherwise the response warns
+    /// about them.
+    #[serde(default = "include_dependencies_default")]
+    include_dependencies: bool,
+}
+
+fn include_dependencies_default() -> bool {
+    true
+}
+
+/// Prompts enough for every player at the largest table to judge 25 times,
+/// more than any one game gets through.
+const MAX_DECK_PROMPTS: usize = MAX_PLAYERS * 25;
+/// Responses enough to deal a full hand for every prompt.
+const MAX_DECK_RESPONSES: usize = MAX_DECK_PROMPTS * HAND_SIZE;
+
+impl GenerateDeck {
+    fn validate(&self) -> Result<(), ActixError> {
+        if self.prompts > MAX_DECK_PROMPTS || self.responses > MAX_DECK_RESPONSES {
+            return Err(ErrorBadRequest(format!(
+                "a deck has at most {MAX_DECK_PROMPTS} prompts and {MAX_DECK_RESPONSES} responses"
+            )));
+        }
+        if let Some(share) = self.max_pick_2_share {
+            if !(0.0..=1.0).contains(&share) {
+                return Err(ErrorBadRequest("max_pick_2_share must be between 0 and 1"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct GeneratedDeck {
+    #[serde(flatten)]
+    deck: Deck,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// Sets that `sets` require but do not include, following the requirements
+/// of requirements.
+async fn missing_dependencies(database: &Database, sets: &[Uuid]) -> Result<Vec<Uuid>, ActixError> {
+    let collection = database.collection::<Set>("sets");
+    let mut known = sets.to_vec();
+    let mut missing = Vec::new();
+    let mut frontier = sets.to_vec();
+    while !frontier.is_empty() {
+        let ids: Vec<Bson> = frontier.drain(..).map(|id| uuid_bson(&id)).collect();
+        let found: Vec<Set> = collection
+            .find(doc! { "uuid": { "$in": ids } }, None)
+            .await
+            .map_err(AppError::from)?
+            .try_collect()
+            .await
+            .map_err(AppError::from)?;
+        for required in found.into_iter().flat_map(|set| set.requires) {
+            if !known.contains(&required) {
+                known.push(required);
+                missing.push(required);
+                frontier.push(required);
+            }
+        }
+    }
+    Ok(missing)
+}
+
+async fn generate_deck(
+    body: web::Json<GenerateDeck>,
+    user: Option<User>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    body.validate()?;
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let tags = normalize_tags(&body.tags)?;
+    let exclude_tags = normalize_tags(&body.exclude_tags)?;
+    let preset = match &body.preset {
+        Some(id) => Some(find_preset(database, id).await?),
+        None => None,
+    };
+    let mut sets = body.sets.clone();
+    if let Some(preset) = preset.as_ref().filter(|_| sets.is_empty()) {
+        sets.clone_from(&preset.sets);
+    }
+    let mut warnings = Vec::new();
+    for required in missing_dependencies(database, &sets).await? {
+        if body.include_dependencies {
+            sets.push(required);
+        } else {
+            warnings.push(format!(
+                "set {required} is required by the chosen sets but not included"
+            ));
+        }
+    }
+    let mut filter = card_filter(&sets, &tags, &exclude_tags);
+    let mut nsfw = body.nsfw;
+    if let Some(preset) = &preset {
+        filter = doc! { "$and": [preset.filter(&[]), filter] };
+        nsfw = preset.nsfw;
+    }
+    let filter = visible_cards(database, filter, !nsfw || safe_mode.0).await?;
+    let filter = rated_cards(filter, max_rating);
+    let candidates: Vec<Card> = cards
+        .find(filter, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+
+    let (mut prompts, mut responses): (Vec<Card>, Vec<Card>) = candidates
+        .into_iter()
+        .partition(|c| c.suite == Suite::Prompt);
+    let mut rng = rand::thread_rng();
+    prompts.shuffle(&mut rng);
+    responses.shuffle(&mut rng);
+
+    let max_multi_pick = body
+        .max_pick_2_share
+        .map_or(body.prompts, |share| (share * body.prompts as f64) as usize);
+    let mut multi_pick = 0;
+    let mut chosen: Vec<Uuid> = Vec::with_capacity(body.prompts + body.responses);
+    for prompt in &prompts {
+        if chosen.len() == body.prompts {
+            break;
+        }
+        if prompt.pick() >= 2 {
+            if multi_pick == max_multi_pick {
+                continue;
+            }
+            multi_pick += 1;
+        }
+        chosen.push(prompt.uuid);
+    }
+    if chosen.len() < body.prompts || responses.len() < body.responses {
+        return Err(ErrorUnprocessableEntity(format!(
+            "only {} prompts and {} responses match the constraints",
+            chosen.len(),
+            responses.len()
+        )));
+    }
+    chosen.extend(responses.iter().take(body.responses).map(|c| c.uuid));
+
+    let deck = Deck {
+        uuid: Uuid::new_v4(),
+        name: body
+            .name
+            .clone()
+            .unwrap_or_else(|| "Generated deck".to_string()),
+        sets,
+        preset: preset.map(|p| p.uuid),
+        cards: chosen,
+        owner: user.map(|u| u.0),
+        cloned_from: None,
+        created_at: DateTime::now(),
+    };
+    database
+        .collection::<Deck>("decks")
+        .insert_one(&deck, None)
+        .await
+        .map_err(AppError::from)?;
+    for set in &deck.sets {
+        record_set_activity(database, *set, SetActivityKind::Deck).await?;
+    }
+    Ok(HttpResponse::Created().json(GeneratedDeck { deck, warnings }))
+}
+
+#[derive(Debug, Deserialize)]
+struct NewDeck {
+    name: String,
+    /// Sets whose cards all go in, less the exclusions below.
+    #[serde(default)]
+    sets: Vec<Uuid>,
+    /// Single cards added on top, whatever set they come from.
+    #[serde(default)]
+    cards: Vec<Uuid>,
+    /// Cards of the chosen sets tagged with any of these are left out.
+    #[serde(default)]
+    exclude_tags: Vec<String>,
+    #[serde(default)]
+    exclude_cards: Vec<Uuid>,
+    #[serde(default)]
+    nsfw: bool,
+    #[serde(default = "include_dependencies_default")]
+    include_dependencies: bool,
+}
+
+/// Builds a deck from whole sets and single cards, less what the
+/// exclusions take out, and stores the resulting card list so games and
+/// exports can refer to it by id.
+async fn create_deck(
+    body: web::Json<NewDeck>,
+    user: Option<User>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(ErrorBadRequest("deck name must not be empty"));
+    }
+    let database = store.database();
+    let exclude_tags = normalize_tags(&body.exclude_tags)?;
+    let mut sets = body.sets.clone();
+    let mut warnings = Vec::new();
+    for required in missing_dependencies(database, &sets).await? {
+        if body.include_dependencies {
+            sets.push(required);
+        } else {
+            warnings.push(format!(
+                "set {required} is required by the chosen sets but not included"
+            ));
+        }
+    }
+    let mut sources = Vec::new();
+    if !sets.is_empty() {
+        sources.push(card_filter(&sets, &[], &exclude_tags));
+    }
+    if !body.cards.is_empty() {
+        let cards: Vec<Bson> = body.cards.iter().map(uuid_bson).collect();
+        sources.push(doc! { "uuid": { "$in": cards } });
+    }
+    if sources.is_empty() {
+        return Err(ErrorBadRequest("a deck needs sets or cards"));
+    }
+    let mut filter = doc! { "$or": sources };
+    if !body.exclude_cards.is_empty() {
+        let excluded: Vec<Bson> = body.exclude_cards.iter().map(uuid_bson).collect();
+        filter.insert("uuid", doc! { "$nin": excluded });
+    }
+    let filter = visible_cards(database, filter, !body.nsfw || safe_mode.0).await?;
+    let filter = rated_cards(filter, max_rating);
+    let options = FindOptions::builder()
+        .sort(doc! { "set_uuid": 1, "suite": 1, "text": 1 })
+        .build();
+    let chosen: Vec<Uuid> = store
+        .cards()
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?
+        .map_ok(|card| card.uuid)
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    for card in &body.cards {
+        if !chosen.contains(card) && !body.exclude_cards.contains(card) {
+            warnings.push(format!("card {card} is not available"));
+        }
+    }
+    if chosen.is_empty() {
+        return Err(ErrorUnprocessableEntity("no cards match the deck"));
+    }
+
+    let deck = Deck {
+        uuid: Uuid::new_v4(),
+        name: name.to_string(),
+        sets,
+        preset: None,
+        cards: chosen,
+        owner: user.map(|u| u.0),
+        cloned_from: None,
+        created_at: DateTime::now(),
+    };
+    database
+        .collection::<Deck>("decks")
+        .insert_one(&deck, None)
+        .await
+        .map_err(AppError::from)?;
+    for set in &deck.sets {
+        record_set_activity(database, *set, SetActivityKind::Deck).await?;
+    }
+    Ok(HttpResponse::Created().json(GeneratedDeck { deck, warnings }))
+}
+
+async fn get_deck(
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let deck = store
+        .database()
+        .collection::<Deck>("decks")
+        .find_one(doc! { "uuid": uuid_bson(&path) }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("deck {path} not found")))?;
+    Ok(web::Json(deck))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CloneDeck {
+    /// Defaults to the original's name with " (copy)".
+    name: Option<String>,
+}
+
+/// Copies a deck, its cards and selection, for the caller to change
+/// without touching the original.
+async fn clone_deck(
+    path: web::Path<Uuid>,
+    body: Option<web::Json<CloneDeck>>,
+    user: User,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let decks = store.database().collection::<Deck>("decks");
+    let original = decks
+        .find_one(doc! { "uuid": uuid_bson(&path) }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("deck {path} not found")))?;
+    let name = body
+        .and_then(|b| b.into_inner().name)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("{} (copy)", original.name));
+    let deck = Deck {
+        uuid: Uuid::new_v4(),
+        name,
+        owner: Some(user.0),
+        cloned_from: Some(original.uuid),
+        created_at: DateTime::now(),
+        ..original
+    };
+    decks
+        .insert_one(&deck, None)
+        .await
+        .map_err(AppError::from)?;
+    Ok(HttpResponse::Created().json(deck))
+}
+
+async fn sample_cards(
+    cards: &Collection<Card>,
+    filter: Document,
+    size: usize,
+) -> Result<Vec<Card>, ActixError> {
+    let pipeline = [
+        doc! { "$match": filter },
+        doc! { "$sample": { "size": size as i64 } },
+    ];
+    let documents: Vec<Document> = cards
+        .aggregate(pipeline, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    documents
+        .into_iter()
+        .map(|d| from_document(d).map_err(ErrorInternalServerError))
+        .collect()
+}
+
+/// Parses a comma separated list of UUIDs such as `?sets=a,b`.
+fn parse_uuid_list(value: Option<&str>) -> Result<Vec<Uuid>, ActixError> {
+    value
+        .into_iter()
+        .flat_map(|v| v.split(','))
+        .filter(|v| !v.is_empty())
+        .map(|v| Uuid::parse_str(v.trim()).map_err(ErrorBadRequest))
+        .collect()
+}
+
+/// Writes the responses into the prompt's blanks, appending any that have no
+/// blank left to fill, as with "Why can't I sleep at night?" style prompts.
+fn fill_blanks(prompt: &str, responses: &[&str]) -> String {
+    let mut text = String::with_capacity(prompt.len());
+    let mut responses = responses.iter();
+    let mut rest = prompt;
+    while let Some(start) = rest.find('_') {
+        let end = rest[start..]
+            .find(|c| c != '_')
+            .map_or(rest.len(), |len| start + len);
+        text.push_str(&rest[..start]);
+        match responses.next() {
+            Some(response) => text.push_str(response.trim_end_matches('.')),
+            None => text.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    text.push_str(rest);
+    for response in responses {
+        text.push(' ');
+        text.push_str(response);
+    }
+    text
+}
+
+/// The pick and draw a prompt imposes on the round it is dealt in, for game
+/// servers to apply.
+async fn card_rules(
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let card = find_card(&database.collection("cards"), &path).await?;
+    if card.suite != Suite::Prompt {
+        return Err(ErrorBadRequest("only prompts set round rules"));
+    }
+    Ok(web::Json(card.rules()))
+}
+
+/// Most cards one random draw may return.
+const MAX_RANDOM_DRAW: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct RandomQuery {
+    suite: Option<Suite>,
+    count: Option<usize>,
+    sets: Option<String>,
+    /// Makes the draw repeatable for as long as the matching cards stay
+    /// the same.
+    seed: Option<u64>,
+}
+
+/// Random cards for tools and bots that don't want whole decks.
+async fn random_cards(
+    query: web::Query<RandomQuery>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let sets = parse_uuid_list(query.sets.as_deref())?;
+    let count = query.count.unwrap_or(1).clamp(1, MAX_RANDOM_DRAW);
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let filter = visible_cards(database, card_filter(&sets, &[], &[]), safe_mode.0).await?;
+    let mut filter = rated_cards(filter, max_rating);
+    if let Some(suite) = query.suite {
+        filter.insert("suite", bson_value(&suite)?);
+    }
+    let Some(seed) = query.seed else {
+        return Ok(web::Json(sample_cards(&cards, filter, count).await?));
+    };
+    // `$sample` cannot be seeded, so the ids are shuffled here instead, from
+    // a fixed order.
+    let options = FindOptions::builder()
+        .projection(doc! { "uuid": 1 })
+        .sort(doc! { "uuid": 1 })
+        .build();
+    let mut ids: Vec<Bson> = database
+        .collection::<Document>("cards")
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect::<Vec<Document>>()
+        .await
+        .map_err(AppError::from)?
+        .into_iter()
+        .filter_map(|d| d.get("uuid").cloned())
+        .collect();
+    let (drawn, _) = ids.partial_shuffle(&mut StdRng::seed_from_u64(seed), count);
+    let drawn = drawn.to_vec();
+    let mut found: Vec<Card> = cards
+        .find(doc! { "uuid": { "$in": &drawn } }, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    found.sort_by_key(|card| drawn.iter().position(|id| *id == uuid_bson(&card.uuid)));
+    Ok(web::Json(found))
+}
+
+#[derive(Debug, Deserialize)]
+struct ComboQuery {
+    sets: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Combo {
+    uuid: Uuid,
+    prompt: Card,
+    responses: Vec<Card>,
+    text: String,
+    /// Voters are tracked in the document's `voters` array, never exposed.
+    votes: i64,
+    created_at: DateTime,
+}
+
+impl Combo {
+    fn new(prompt: Card, responses: Vec<Card>) -> Self {
+        let texts: Vec<&str> = responses.iter().map(|c| c.text.as_str()).collect();
+        let text = fill_blanks(&prompt.text, &texts);
+        Combo {
+            uuid: Uuid::new_v4(),
+            prompt,
+            responses,
+            text,
+            votes: 0,
+            created_at: DateTime::now(),
+        }
+    }
+}
+
+/// Caller identity, asserted by the fronting proxy in the `X-User-Id` header.
+#[derive(Debug, Clone)]
+struct User(String);
+
+impl FromRequest for User {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = req
+            .headers()
+            .get("X-User-Id")
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(|v| User(v.to_string()))
+            .ok_or_else(|| ErrorUnauthorized("missing X-User-Id header"));
+        ready(user)
+    }
+}
+
+/// Operator access, granted when the `X-Admin-Token` header matches
+/// `Config::admin_token`. Without one no request is an admin.
+#[derive(Debug, Clone, Copy)]
+struct Admin;
+
+impl FromRequest for Admin {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let config = req.app_data::<web::Data<Config>>();
+        let expected = config
+            .and_then(|c| c.admin_token.as_deref())
+            .filter(|t| !t.is_empty());
+        let given = req
+            .headers()
+            .get("X-Admin-Token")
+            .and_then(|v| v.to_str().ok());
+        let admin = match (expected, given) {
+            (Some(expected), Some(given)) if expected == given => Ok(Admin),
+            _ => Err(ErrorForbidden("admin token required")),
+        };
+        ready(admin)
+    }
+}
+
+/// A game server reporting what happened at its tables, granted when the
+/// `X-Game-Token` header matches `Config::game_token`, or to an admin.
+#[derive(Debug, Clone, Copy)]
+struct GameServer;
+
+impl FromRequest for GameServer {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let config = req.app_data::<web::Data<Config>>();
+        let expected = config
+            .and_then(|c| c.game_token.as_deref())
+            .filter(|t| !t.is_empty());
+        let given = req
+            .headers()
+            .get("X-Game-Token")
+            .and_then(|v| v.to_str().ok());
+        let server = match (expected, given) {
+            (Some(expected), Some(given)) if expected == given => Ok(GameServer),
+            _ => Admin::from_request(req, payload)
+                .into_inner()
+                .map(|_| GameServer)
+                .map_err(|_| ErrorForbidden("game token required")),
+        };
+        ready(server)
+    }
+}
+
+/// The library a privileged request works on: the default one or, named in
+/// an `X-Database` header by an admin, another allowed one such as
+/// `controversy_staging` for trying risky imports before promoting them.
+struct TargetDatabase(Database);
+
+impl FromRequest for TargetDatabase {
+    type Error = ActixError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let requested = req
+            .headers()
+            .get("X-Database")
+            .map(|v| v.to_str().map(str::to_string));
+        let admin = Admin::from_request(req, payload).into_inner();
+        let store = req.app_data::<web::Data<Store>>().cloned();
+        let config = req.app_data::<web::Data<Config>>().cloned();
+        Box::pin(async move {
+            let config =
+                config.ok_or_else(|| ErrorInternalServerError("no configuration loaded"))?;
+            let name = match requested {
+                None => config.database.clone(),
+                Some(Err(_)) => return Err(ErrorBadRequest("invalid X-Database header")),
+                Some(Ok(name)) => {
+                    admin?;
+                    if !allowed_databases(&config).contains(&name) {
+                        return Err(ErrorForbidden(format!("database {name} is not allowed")));
+                    }
+                    name
+                }
+            };
+            let client = store
+                .ok_or_else(|| ErrorInternalServerError("no database client configured"))?
+                .client()
+                .clone();
+            Ok(TargetDatabase(client.database(&name)))
+        })
+    }
+}
+
+async fn save_combo(database: &Database, combo: &Combo) -> Result<(), ActixError> {
+    database
+        .collection::<Combo>("combos")
+        .insert_one(combo, None)
+        .await
+        .map_err(AppError::from)?;
+    Ok(())
+}
+
+async fn combo(
+    query: web::Query<ComboQuery>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let sets = parse_uuid_list(query.sets.as_deref())?;
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let base = visible_cards(database, card_filter(&sets, &[], &[]), safe_mode.0).await?;
+    let base = rated_cards(base, max_rating);
+
+    let mut filter = base.clone();
+    filter.insert("suite", "prompt");
+    let prompt = sample_cards(&cards, filter, 1)
+        .await?
+        .pop()
+        .ok_or_else(|| ErrorNotFound("no prompt cards available"))?;
+
+    let mut filter = base;
+    filter.insert("suite", "response");
+    let responses = sample_cards(&cards, filter, prompt.pick() as usize).await?;
+    if responses.len() < prompt.pick() as usize {
+        return Err(ErrorNotFound("not enough response cards available"));
+    }
+
+    let combo = Combo::new(prompt, responses);
+    save_combo(database, &combo).await?;
+    Ok(web::Json(combo))
+}
+
+#[derive(Debug, Deserialize)]
+struct RoundQuery {
+    sets: Option<String>,
+    /// Responses to deal, never fewer than the prompt picks.
+    responses: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct Round {
+    prompt: Card,
+    rules: RoundRules,
+    responses: Vec<Card>,
+}
+
+/// One prompt and enough responses to answer it, for bots and party apps
+/// that keep no game state. Unlike `combo` nothing is stored.
+async fn random_round(
+    query: web::Query<RoundQuery>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let sets = parse_uuid_list(query.sets.as_deref())?;
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let base = visible_cards(database, card_filter(&sets, &[], &[]), safe_mode.0).await?;
+    let base = rated_cards(base, max_rating);
+
+    let mut filter = base.clone();
+    filter.insert("suite", "prompt");
+    let prompt = sample_cards(&cards, filter, 1)
+        .await?
+        .pop()
+        .ok_or_else(|| ErrorNotFound("no prompt cards available"))?;
+
+    let rules = prompt.rules();
+    let wanted = query
+        .responses
+        .unwrap_or(0)
+        .clamp(rules.pick as usize, MAX_RANDOM_DRAW);
+    let mut filter = base;
+    filter.insert("suite", "response");
+    let responses = sample_cards(&cards, filter, wanted).await?;
+    if responses.len() < rules.pick as usize {
+        return Err(ErrorNotFound("not enough response cards available"));
+    }
+    Ok(web::Json(Round {
+        prompt,
+        rules,
+        responses,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayedCombo {
+    prompt: Uuid,
+    responses: Vec<Uuid>,
+}
+
+/// Records a combination played in a game so it can be voted on. Only the
+/// game server reports what was played, so it takes the server's token.
+async fn submit_combo(
+    _: GameServer,
+    body: web::Json<PlayedCombo>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let prompt = find_card(&cards, &body.prompt).await?;
+    let mut responses = Vec::with_capacity(body.responses.len());
+    for id in &body.responses {
+        responses.push(find_card(&cards, id).await?);
+    }
+    if prompt.suite != Suite::Prompt || responses.iter().any(|c| c.suite != Suite::Response) {
+        return Err(ErrorBadRequest("a combo is one prompt and its responses"));
+    }
+    prompt.rules().check_submission(responses.len())?;
+
+    let combo = Combo::new(prompt, responses);
+    save_combo(database, &combo).await?;
+    Ok(HttpResponse::Created().json(combo))
+}
+
+/// Counts the caller's vote, at most once per combo.
+async fn upvote_combo(
+    path: web::Path<Uuid>,
+    user: User,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let combos: Collection<Combo> = database.collection("combos");
+    let id = uuid_bson(&path);
+    combos
+        .update_one(
+            doc! { "uuid": &id, "voters": { "$ne": &user.0 } },
+            doc! { "$addToSet": { "voters": &user.0 }, "$inc": { "votes": 1 } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?;
+    let combo = combos
+        .find_one(doc! { "uuid": id }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("combo {path} not found")))?;
+    Ok(web::Json(combo))
+}
+
+#[derive(Debug, Deserialize)]
+struct LimitQuery {
+    limit: Option<i64>,
+}
+
+async fn combo_leaderboard(
+    query: web::Query<LimitQuery>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let options = FindOptions::builder()
+        .sort(doc! { "votes": -1, "created_at": -1 })
+        .limit(query.limit.unwrap_or(20).clamp(1, 100))
+        .build();
+    let combos: Vec<Combo> = database
+        .collection::<Combo>("combos")
+        .find(doc! { "votes": { "$gt": 0 } }, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(combos))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CardVote {
+    card_uuid: Uuid,
+    user: String,
+    vote: Vote,
+    updated_at: DateTime,
+}
+
+#[derive(Debug, Deserialize)]
+struct VoteRequest {
+    vote: Vote,
+}
+
+/// Records the caller's vote on a card, replacing any earlier one, and
+/// moves the card's tally accordingly.
+async fn vote_card(
+    path: web::Path<Uuid>,
+    body: web::Json<VoteRequest>,
+    user: User,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let cards: Collection<Card> = database.collection("cards");
+    let card = find_card(&cards, &path).await?;
+
+    let vote = CardVote {
+        card_uuid: card.uuid,
+        user: user.0,
+        vote: body.vote,
+        updated_at: DateTime::now(),
+    };
+    let votes = database.collection::<CardVote>("card_votes");
+    let filter = doc! { "card_uuid": uuid_bson(&card.uuid), "user": &vote.user };
+    let upsert = FindOneAndReplaceOptions::builder().upsert(true).build();
+    // Of two concurrent first votes the unique index lets one in. The other
+    // fails and, tried again, replaces it like any later vote would.
+    let previous = match votes
+        .find_one_and_replace(filter.clone(), &vote, upsert.clone())
+        .await
+    {
+        Err(e) if is_duplicate_key(&e) => votes.find_one_and_replace(filter, &vote, upsert).await,
+        result => result,
+    }
+    .map_err(AppError::from)?;
+
+    let mut change = doc! {
+        format!("votes.{}", vote.vote.field()): 1,
+        "votes.score": vote.vote.score(),
+    };
+    if let Some(previous) = previous {
+        if previous.vote == vote.vote {
+            return Ok(web::Json(card));
+        }
+        change.insert(format!("votes.{}", previous.vote.field()), -1);
+        change.insert("votes.score", vote.vote.score() - previous.vote.score());
+    }
+    let card = cards
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&card.uuid) },
+            doc! { "$inc": change },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("card {path} not found")))?;
+    Ok(web::Json(card))
+}
+
+/// One change to the card database: who made it, to what, and how the
+/// stored fields moved.
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditEntry {
+    at: DateTime,
+    /// The `X-User-Id` of the request, when it had one.
+    actor: Option<String>,
+    action: String,
+    /// What kind of document was changed, such as `card` or `set`.
+    entity: String,
+    entity_uuid: Option<Uuid>,
+    /// Changed fields, each as `{ "before": .., "after": .. }`.
+    #[serde(default)]
+    changes: Document,
+    /// Anything else worth keeping, such as the cards a merge folded in.
+    #[serde(default)]
+    details: Document,
+}
+
+/// The fields that differ between two versions of a document, either of
+/// which may be missing for a creation or a removal.
+fn field_changes<T: Serialize>(
+    before: Option<&T>,
+    after: Option<&T>,
+) -> Result<Document, ActixError> {
+    let document = |value: Option<&T>| match value {
+        Some(value) => bson::to_document(value).map_err(ErrorInternalServerError),
+        None => Ok(Document::new()),
+    };
+    let (before, after) = (document(before)?, document(after)?);
+    let mut changes = Document::new();
+    for key in before.keys().chain(after.keys()) {
+        let (old, new) = (before.get(key), after.get(key));
+        if old != new && !changes.contains_key(key) {
+            changes.insert(
+                key,
+                doc! {
+                    "before": old.cloned().unwrap_or(Bson::Null),
+                    "after": new.cloned().unwrap_or(Bson::Null),
+                },
+            );
+        }
+    }
+    Ok(changes)
+}
+
+/// Adds an entry to the audit log. Like the import history, failing to
+/// write it only logs, since the change itself has already been made.
+async fn audit(
+    database: &Database,
+    actor: Option<&User>,
+    action: &str,
+    entity: &str,
+    entity_uuid: Option<Uuid>,
+    changes: Document,
+    details: Document,
+) {
+    let entry = AuditEntry {
+        at: DateTime::now(),
+        actor: actor.map(|u| u.0.clone()),
+        action: action.to_string(),
+        entity: entity.to_string(),
+        entity_uuid,
+        changes,
+        details,
+    };
+    let result = database
+        .collection::<AuditEntry>("audit_log")
+        .insert_one(&entry, None)
+        .await;
+    if let Err(e) = result {
+        eprintln!("could not audit {action} of {entity}: {e}");
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditQuery {
+    actor: Option<String>,
+    action: Option<String>,
+    entity: Option<String>,
+    entity_uuid: Option<Uuid>,
+    since: Option<DateTime>,
+    until: Option<DateTime>,
+    limit: Option<i64>,
+    skip: Option<u64>,
+}
+
+/// The audit log, newest first.
+async fn list_audit(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+    query: web::Query<AuditQuery>,
+) -> Result<impl Responder, ActixError> {
+    let mut filter = doc! {};
+    if let Some(actor) = &query.actor {
+        filter.insert("actor", actor);
+    }
+    if let Some(action) = &query.action {
+        filter.insert("action", action);
+    }
+    if let Some(entity) = &query.entity {
+        filter.insert("entity", entity);
+    }
+    if let Some(uuid) = &query.entity_uuid {
+        filter.insert("entity_uuid", uuid_bson(uuid));
+    }
+    let mut at = doc! {};
+    if let Some(since) = query.since {
+        at.insert("$gte", since);
+    }
+    if let Some(until) = query.until {
+        at.insert("$lt", until);
+    }
+    if !at.is_empty() {
+        filter.insert("at", at);
+    }
+    let options = FindOptions::builder()
+        .sort(doc! { "at": -1 })
+        .skip(query.skip)
+        .limit(query.limit.unwrap_or(100).clamp(1, 500))
+        .build();
+    let entries: Vec<AuditEntry> = database
+        .collection::<AuditEntry>("audit_log")
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(entries))
+}
+
+#[derive(Debug, Deserialize)]
+struct NewCard {
+    suite: Suite,
+    text: String,
+    #[serde(default)]
+    special: String,
+    /// Editions of the set the card is printed in.
+    #[serde(default)]
+    editions: Vec<Uuid>,
+}
+
+/// Fields a card edit may touch. Editions are not among them, so fixing a
+/// typo leaves the card's printings as they were.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CardPatch {
+    suite: Option<Suite>,
+    text: Option<String>,
+    special: Option<String>,
+}
+
+fn card_text(text: &str) -> Result<String, ActixError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(ErrorBadRequest("card text cannot be empty"));
+    }
+    Ok(text.to_string())
+}
+
+/// The `$set` storing an edit of a card's suite, text or special, with the
+/// card run through the same step as an imported one. Only new text is
+/// screened again, so editing the special of an approved card keeps it
+/// approved.
+fn edit_card(
+    card: &Card,
+    suite: Option<Suite>,
+    text: Option<&str>,
+    special: Option<&str>,
+    rules: &CardRules,
+) -> Result<Document, ActixError> {
+    let text = match text {
+        Some(text) => card_text(text)?,
+        None => card.text.clone(),
+    };
+    let special = special.unwrap_or(&card.special).to_string();
+    let mut edited = Card::new(card.set_uuid, suite.unwrap_or(card.suite), text, special);
+    edited.rating = card.rating;
+    edited.nsfw = card.nsfw;
+    rules.prepare(&mut edited);
+    let mut update = special_fields(&edited.special);
+    update.insert("suite", bson_value(&edited.suite)?);
+    update.insert("text", &edited.text);
+    update.insert("pick", i32::from(edited.pick));
+    update.insert("rating", bson_value(&edited.rating)?);
+    update.insert("nsfw", edited.nsfw);
+    if edited.text != card.text {
+        update.insert("screened", edited.screened);
+    }
+    Ok(update)
+}
+
+/// Adds one card to a set, for fixes that don't warrant a re-import. Sets
+/// in the trash take no new cards.
+async fn create_card(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    body: web::Json<NewCard>,
+    rules: web::Data<CardRules>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let set = find_set(&database.collection("sets"), &path).await?;
+    let body = body.into_inner();
+    if !body.editions.is_empty() {
+        let editions: Vec<Bson> = body.editions.iter().map(uuid_bson).collect();
+        let known = database
+            .collection::<Edition>("editions")
+            .count_documents(
+                doc! { "uuid": { "$in": editions }, "set_uuid": uuid_bson(&set.uuid) },
+                None,
+            )
+            .await
+            .map_err(AppError::from)?;
+        if known as usize != body.editions.len() {
+            return Err(ErrorBadRequest(format!(
+                "editions must belong to set {}",
+                set.uuid
+            )));
+        }
+    }
+    let mut card = Card::new(set.uuid, body.suite, card_text(&body.text)?, body.special);
+    card.editions = body.editions;
+    // A card typed in is normalized, rated and screened like an imported one.
+    rules.prepare(&mut card);
+    store
+        .cards()
+        .insert_one(&card, None)
+        .await
+        .map_err(AppError::from)?;
+    snapshot_set(database, set.uuid, "edit")
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(None, Some(&card))?;
+    audit(
+        database,
+        user.as_ref(),
+        "create",
+        "card",
+        Some(card.uuid),
+        changes,
+        doc! {},
+    )
+    .await;
+    Ok(HttpResponse::Created().json(card))
+}
+
+async fn update_card(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    body: web::Json<CardPatch>,
+    rules: web::Data<CardRules>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let body = body.into_inner();
+    if body.suite.is_none() && body.text.is_none() && body.special.is_none() {
+        return Err(ErrorBadRequest("nothing to update"));
+    }
+    let before = find_card(&store.cards(), &path).await?;
+    let update = edit_card(
+        &before,
+        body.suite,
+        body.text.as_deref(),
+        body.special.as_deref(),
+        &rules,
+    )?;
+    let card = store
+        .cards()
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&path) },
+            doc! { "$set": update },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("card {path} not found")))?;
+    if before.suite != card.suite || before.text != card.text || before.special != card.special {
+        let editor = user.as_ref().map(|u| u.0.as_str());
+        record_revision(store.database(), &before, "edit", editor)
+            .await
+            .map_err(AppError::from)?;
+    }
+    snapshot_set(store.database(), card.set_uuid, "edit")
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(Some(&before), Some(&card))?;
+    audit(
+        store.database(),
+        user.as_ref(),
+        "update",
+        "card",
+        Some(card.uuid),
+        changes,
+        doc! {},
+    )
+    .await;
+    Ok(web::Json(card))
+}
+
+/// Moves a card to the trash, see `restore_card`.
+async fn delete_card(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let card = store
+        .cards()
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&path), "deleted_at": null },
+            doc! { "$set": { "deleted_at": DateTime::now() } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("card {path} not found")))?;
+    snapshot_set(store.database(), card.set_uuid, "edit")
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(Some(&card), None)?;
+    audit(
+        store.database(),
+        user.as_ref(),
+        "delete",
+        "card",
+        Some(card.uuid),
+        changes,
+        doc! {},
+    )
+    .await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Takes a card out of the trash. One trashed with its set comes back
+/// with the set instead.
+async fn restore_card(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let card = store
+        .cards()
+        .find_one(
+            doc! { "uuid": uuid_bson(&path), "deleted_at": { "$ne": null } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("card {path} is not in the trash")))?;
+    let set_trashed = store
+        .sets()
+        .count_documents(
+            doc! { "uuid": uuid_bson(&card.set_uuid), "deleted_at": { "$ne": null } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?;
+    if set_trashed > 0 {
+        return Err(ErrorConflict(format!(
+            "set {} is in the trash, restore it first",
+            card.set_uuid
+        )));
+    }
+    let card = store
+        .cards()
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&path) },
+            doc! { "$unset": { "deleted_at": "" } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("card {path} not found")))?;
+    snapshot_set(store.database(), card.set_uuid, "edit")
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(None, Some(&card))?;
+    audit(
+        store.database(),
+        user.as_ref(),
+        "restore",
+        "card",
+        Some(card.uuid),
+        changes,
+        doc! {},
+    )
+    .await;
+    Ok(web::Json(card))
+}
+
+/// Cards the import screening held back, oldest first, for review. Each
+/// either gets approved or deleted.
+async fn list_screened_cards(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<impl Responder, ActixError> {
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": 1 })
+        .build();
+    let held: Vec<Card> = database
+        .collection::<Card>("cards")
+        .find(doc! { "screened.0": { "$exists": true } }, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(held))
+}
+
+/// Releases a held card to public queries.
+async fn approve_card(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let card = store
+        .cards()
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&path) },
+            doc! { "$unset": { "screened": "" } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("card {path} not found")))?;
+    audit(
+        store.database(),
+        user.as_ref(),
+        "approve",
+        "card",
+        Some(card.uuid),
+        doc! {},
+        doc! {},
+    )
+    .await;
+    Ok(web::Json(card))
+}
+
+#[derive(Debug, Serialize)]
+struct RevisionEntry {
+    #[serde(flatten)]
+    revision: CardRevision,
+    /// How the wording changed from this revision to the next, or to the
+    /// card as it is now for the latest one.
+    changes: Document,
+}
+
+fn revision_snapshot(revision: &CardRevision) -> CardSnapshot {
+    CardSnapshot {
+        uuid: revision.card_uuid,
+        suite: revision.suite,
+        text: revision.text.clone(),
+        special: revision.special.clone(),
+    }
+}
+
+/// Earlier wordings of a card, newest first.
+async fn list_card_revisions(
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let card = find_card(&store.cards(), &path).await?;
+    let options = FindOptions::builder().sort(doc! { "revision": 1 }).build();
+    let revisions: Vec<CardRevision> = store
+        .database()
+        .collection::<CardRevision>("card_revisions")
+        .find(doc! { "card_uuid": uuid_bson(&path) }, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let mut next = CardSnapshot::from(card);
+    let mut entries = Vec::with_capacity(revisions.len());
+    for revision in revisions.into_iter().rev() {
+        let snapshot = revision_snapshot(&revision);
+        let changes = field_changes(Some(&snapshot), Some(&next))?;
+        entries.push(RevisionEntry { revision, changes });
+        next = snapshot;
+    }
+    Ok(web::Json(entries))
+}
+
+/// Puts back the wording of an earlier revision. The wording it replaces
+/// becomes a revision itself, so a revert can be undone the same way.
+async fn revert_card(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(Uuid, i64)>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let (id, number) = path.into_inner();
+    let database = store.database();
+    let before = find_card(&store.cards(), &id).await?;
+    let revision = database
+        .collection::<CardRevision>("card_revisions")
+        .find_one(
+            doc! { "card_uuid": uuid_bson(&id), "revision": number },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("revision {number} of card {id} not found")))?;
+    if before.suite == revision.suite
+        && before.text == revision.text
+        && before.special == revision.special
+    {
+        return Ok(web::Json(before));
+    }
+    let mut update = special_fields(&revision.special);
+    update.insert("suite", bson_value(&revision.suite)?);
+    update.insert("text", &revision.text);
+    let card = store
+        .cards()
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&id) },
+            doc! { "$set": update },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("card {id} not found")))?;
+    let editor = user.as_ref().map(|u| u.0.as_str());
+    record_revision(database, &before, "revert", editor)
+        .await
+        .map_err(AppError::from)?;
+    snapshot_set(database, card.set_uuid, "edit")
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(Some(&before), Some(&card))?;
+    audit(
+        database,
+        user.as_ref(),
+        "revert",
+        "card",
+        Some(id),
+        changes,
+        doc! { "revision": number },
+    )
+    .await;
+    Ok(web::Json(card))
+}
+
+#[derive(Debug, Deserialize)]
+struct CardMerge {
+    duplicates: Vec<Uuid>,
+}
+
+/// Folds `duplicates` into the card at `{keep}`: the survivor takes their
+/// editions, tags and group, their votes (one per user, the survivor's own
+/// first, then the latest), play log and stats, and their places in decks
+/// and collections. The duplicates are then deleted, all in one
+/// transaction, or on a standalone server with `MergeUndo` to put things
+/// back if a write fails. A card belongs to a single set, so only cards of
+/// the same set are merged; a reprint in another set stays its own card.
+async fn merge_cards(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    body: web::Json<CardMerge>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let keep_id = *path;
+    let database = store.database();
+    let cards = store.cards();
+    let mut ids = body.duplicates.clone();
+    ids.sort();
+    ids.dedup();
+    if ids.is_empty() {
+        return Err(ErrorBadRequest("duplicates must not be empty"));
+    }
+    if ids.contains(&keep_id) {
+        return Err(ErrorBadRequest("a card cannot be merged into itself"));
+    }
+    let mut keep = find_card(&cards, &keep_id).await?;
+    let before = keep.clone();
+    let duplicate_ids: Vec<Bson> = ids.iter().map(uuid_bson).collect();
+    let duplicates: Vec<Card> = cards
+        .find(doc! { "uuid": { "$in": &duplicate_ids } }, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    if let Some(missing) = ids
+        .iter()
+        .find(|id| !duplicates.iter().any(|card| card.uuid == **id))
+    {
+        return Err(ErrorNotFound(format!("card {missing} not found")));
+    }
+    if let Some(other) = duplicates.iter().find(|card| card.suite != keep.suite) {
+        return Err(ErrorBadRequest(format!(
+            "card {} is not of the same suite as {keep_id}",
+            other.uuid
+        )));
+    }
+    if let Some(other) = duplicates
+        .iter()
+        .find(|card| card.set_uuid != keep.set_uuid)
+    {
+        return Err(ErrorConflict(format!(
+            "card {} is in set {}, not {}; only cards of one set are merged",
+            other.uuid, other.set_uuid, keep.set_uuid
+        )));
+    }
+    for duplicate in &duplicates {
+        for edition in &duplicate.editions {
+            if !keep.editions.contains(edition) {
+                keep.editions.push(*edition);
+            }
+        }
+        for tag in &duplicate.tags {
+            if !keep.tags.contains(tag) {
+                keep.tags.push(tag.clone());
+            }
+        }
+        if keep.card_group.is_none() {
+            keep.card_group = duplicate.card_group;
+        }
+        keep.nsfw |= duplicate.nsfw;
+    }
+
+    let mut merged_ids = duplicate_ids.clone();
+    merged_ids.push(uuid_bson(&keep_id));
+    let card_votes = database.collection::<CardVote>("card_votes");
+    let mut votes: HashMap<String, CardVote> = HashMap::new();
+    let mut cursor = card_votes
+        .find(doc! { "card_uuid": { "$in": &merged_ids } }, None)
+        .await
+        .map_err(AppError::from)?;
+    while let Some(vote) = cursor.try_next().await.map_err(AppError::from)? {
+        match votes.entry(vote.user.clone()) {
+            Entry::Occupied(mut kept) => {
+                let kept = kept.get_mut();
+                if kept.card_uuid != keep_id
+                    && (vote.card_uuid == keep_id || vote.updated_at > kept.updated_at)
+                {
+                    *kept = vote;
+                }
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(vote);
+            }
+        }
+    }
+    keep.votes = VoteTally::default();
+    for vote in votes.values_mut() {
+        vote.card_uuid = keep_id;
+        match vote.vote {
+            Vote::Funny => keep.votes.funny += 1,
+            Vote::Meh => keep.votes.meh += 1,
+            Vote::Remove => keep.votes.remove += 1,
+        }
+        keep.votes.score += vote.vote.score();
+    }
+
+    let card_stats = database.collection::<CardStats>("card_stats");
+    let merged_stats: Vec<CardStats> = card_stats
+        .find(doc! { "card_uuid": { "$in": &duplicate_ids } }, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+
+    let mut session = store
+        .client()
+        .start_session(None)
+        .await
+        .map_err(AppError::from)?;
+    let undo = match try_transaction(&mut session).await? {
+        true => None,
+        false => Some(
+            MergeUndo::read(database, &before, &duplicates)
+                .await
+                .map_err(AppError::from)?,
+        ),
+    };
+    let merged = async {
+        let keep_filter = doc! { "uuid": uuid_bson(&keep_id) };
+        let duplicate_filter = doc! { "uuid": { "$in": &duplicate_ids } };
+        cards
+            .replace_one_with_session(keep_filter, &keep, None, &mut session)
+            .await?;
+        cards
+            .delete_many_with_session(duplicate_filter, None, &mut session)
+            .await?;
+        card_votes
+            .delete_many_with_session(
+                doc! { "card_uuid": { "$in": &merged_ids } },
+                None,
+                &mut session,
+            )
+            .await?;
+        if !votes.is_empty() {
+            card_votes
+                .insert_many_with_session(votes.values(), None, &mut session)
+                .await?;
+        }
+        database
+            .collection::<CardPlay>("card_plays")
+            .update_many_with_session(
+                doc! { "card_uuid": { "$in": &duplicate_ids } },
+                doc! { "$set": { "card_uuid": uuid_bson(&keep_id) } },
+                None,
+                &mut session,
+            )
+            .await?;
+        if !merged_stats.is_empty() {
+            let sum = |field: fn(&CardStats) -> i64| merged_stats.iter().map(field).sum::<i64>();
+            card_stats
+                .update_one_with_session(
+                    doc! { "card_uuid": uuid_bson(&keep_id) },
+                    doc! { "$inc": {
+                        "dealt": sum(|s| s.dealt),
+                        "submitted": sum(|s| s.submitted),
+                        "wins": sum(|s| s.wins),
+                    } },
+                    UpdateOptions::builder().upsert(true).build(),
+                    &mut session,
+                )
+                .await?;
+            card_stats
+                .delete_many_with_session(
+                    doc! { "card_uuid": { "$in": &duplicate_ids } },
+                    None,
+                    &mut session,
+                )
+                .await?;
+        }
+        // Lists of card ids point at the survivor instead.
+        for collection in ["decks", "card_collections"] {
+            database
+                .collection::<Document>(collection)
+                .update_many_with_session(
+                    doc! { "cards": { "$in": &duplicate_ids } },
+                    doc! { "$set": { "cards.$[merged]": uuid_bson(&keep_id) } },
+                    UpdateOptions::builder()
+                        .array_filters(vec![doc! { "merged": { "$in": &duplicate_ids } }])
+                        .build(),
+                    &mut session,
+                )
+                .await?;
+        }
+        Ok::<_, mongodb::error::Error>(())
+    }
+    .await;
+    match (merged, undo) {
+        (Ok(()), None) => session.commit_transaction().await.map_err(AppError::from)?,
+        (Ok(()), Some(_)) => {}
+        (Err(err), None) => {
+            session.abort_transaction().await.map_err(AppError::from)?;
+            return Err(AppError::from(err).into());
+        }
+        (Err(err), Some(undo)) => {
+            if let Err(e) = undo.restore(database).await {
+                eprintln!("could not undo the partial merge into {keep_id}: {e}");
+            }
+            return Err(AppError::from(err).into());
+        }
+    }
+
+    snapshot_set(database, keep.set_uuid, "edit")
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(Some(&before), Some(&keep))?;
+    audit(
+        database,
+        user.as_ref(),
+        "merge",
+        "card",
+        Some(keep_id),
+        changes,
+        doc! { "duplicates": duplicate_ids },
+    )
+    .await;
+    Ok(web::Json(keep))
+}
+
+/// Everything `merge_cards` overwrites, read before it starts so that
+/// without a transaction a failed merge can be undone, as
+/// `write_set_compensated` does for imports.
+struct MergeUndo {
+    /// The survivor as it was, then the duplicates.
+    cards: Vec<Card>,
+    votes: Vec<Document>,
+    stats: Vec<Document>,
+    /// The duplicates' plays by card, as ids into `card_plays`.
+    plays: Vec<(Uuid, Vec<Bson>)>,
+    /// Decks and collections listing a duplicate, with their card lists.
+    lists: Vec<(&'static str, Bson, Bson)>,
+}
+
+impl MergeUndo {
+    async fn read(
+        database: &Database,
+        keep: &Card,
+        duplicates: &[Card],
+    ) -> Result<Self, mongodb::error::Error> {
+        let mut cards = vec![keep.clone()];
+        cards.extend_from_slice(duplicates);
+        let ids: Vec<Bson> = cards.iter().map(|card| uuid_bson(&card.uuid)).collect();
+        let duplicate_ids = &ids[1..];
+        let read = |collection: &str, filter: Document| {
+            let collection = database.collection::<Document>(collection);
+            async move {
+                collection
+                    .find(filter, None)
+                    .await?
+                    .try_collect::<Vec<Document>>()
+                    .await
+            }
+        };
+        let votes = read("card_votes", doc! { "card_uuid": { "$in": &ids } }).await?;
+        let stats = read("card_stats", doc! { "card_uuid": { "$in": &ids } }).await?;
+        let mut plays = Vec::new();
+        for duplicate in duplicates {
+            let ids: Vec<Bson> = database
+                .collection::<Document>("card_plays")
+                .distinct(
+                    "_id",
+                    doc! { "card_uuid": uuid_bson(&duplicate.uuid) },
+                    None,
+                )
+                .await?;
+            plays.push((duplicate.uuid, ids));
+        }
+        let mut lists = Vec::new();
+        for collection in ["decks", "card_collections"] {
+            let listing = read(collection, doc! { "cards": { "$in": duplicate_ids } }).await?;
+            for list in listing {
+                if let (Some(id), Some(cards)) = (list.get("_id"), list.get("cards")) {
+                    lists.push((collection, id.clone(), cards.clone()));
+                }
+            }
+        }
+        Ok(MergeUndo {
+            cards,
+            votes,
+            stats,
+            plays,
+            lists,
+        })
+    }
+
+    async fn restore(&self, database: &Database) -> Result<(), mongodb::error::Error> {
+        let ids: Vec<Bson> = self
+            .cards
+            .iter()
+            .map(|card| uuid_bson(&card.uuid))
+            .collect();
+        let cards = database.collection::<Card>("cards");
+        cards
+            .delete_many(doc! { "uuid": { "$in": &ids } }, None)
+            .await?;
+        cards.insert_many(&self.cards, None).await?;
+        for (collection, documents) in [("card_votes", &self.votes), ("card_stats", &self.stats)] {
+            let collection = database.collection::<Document>(collection);
+            collection
+                .delete_many(doc! { "card_uuid": { "$in": &ids } }, None)
+                .await?;
+            if !documents.is_empty() {
+                collection.insert_many(documents, None).await?;
+            }
+        }
+        for (card, plays) in &self.plays {
+            database
+                .collection::<Document>("card_plays")
+                .update_many(
+                    doc! { "_id": { "$in": plays } },
+                    doc! { "$set": { "card_uuid": uuid_bson(card) } },
+                    None,
+                )
+                .await?;
+        }
+        for (collection, id, cards) in &self.lists {
+            database
+                .collection::<Document>(collection)
+                .update_one(
+                    doc! { "_id": id },
+                    doc! { "$set": { "cards": cards } },
+                    None,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CardQuery {
+    set: Option<Uuid>,
+    preset: Option<Uuid>,
+    suite: Option<Suite>,
+    card_type: Option<String>,
+    edition: Option<Uuid>,
+    pick: Option<u8>,
+    /// Only cards with (`true`) or without (`false`) a special value.
+    has_special: Option<bool>,
+    #[serde(alias = "tag")]
+    tags: Option<String>,
+    exclude_tags: Option<String>,
+    min_score: Option<i64>,
+    sort: Option<String>,
+    limit: Option<i64>,
+    skip: Option<u64>,
+}
+
+async fn list_cards(
+    query: web::Query<CardQuery>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let mut filter = doc! {};
+    if let Some(set) = &query.set {
+        filter.insert("set_uuid", uuid_bson(set));
+    }
+    if let Some(suite) = query.suite {
+        filter.insert("suite", bson_value(&suite)?);
+    }
+    if let Some(card_type) = &query.card_type {
+        filter.insert("card_type", normalize_tag(card_type)?);
+    }
+    if let Some(edition) = &query.edition {
+        filter.insert("editions", uuid_bson(edition));
+    }
+    if let Some(pick) = query.pick {
+        filter.insert("pick", i32::from(pick));
+    }
+    match query.has_special {
+        Some(true) => filter.insert("special", doc! { "$ne": "" }),
+        Some(false) => filter.insert("special", ""),
+        None => None,
+    };
+    if let Some(min_score) = query.min_score {
+        filter.insert("votes.score", doc! { "$gte": min_score });
+    }
+    let tags = parse_tag_list(query.tags.as_deref())?;
+    let exclude_tags = parse_tag_list(query.exclude_tags.as_deref())?;
+    if let Some(tags) = tag_filter(&tags, &exclude_tags) {
+        filter.insert("tags", tags);
+    }
+    let sort = match query.sort.as_deref() {
+        None | Some("text") => doc! { "text": 1 },
+        Some("score") => doc! { "votes.score": -1, "text": 1 },
+        Some(other) => return Err(ErrorBadRequest(format!("cannot sort by {other}"))),
+    };
+    let mut safe = safe_mode.0;
+    if let Some(id) = &query.preset {
+        let preset = find_preset(database, id).await?;
+        filter = doc! { "$and": [preset.filter(&preset.sets), filter] };
+        safe |= !preset.nsfw;
+    }
+    let filter = visible_cards(database, filter, safe).await?;
+    let filter = rated_cards(filter, max_rating);
+    let options = FindOptions::builder()
+        .sort(sort)
+        .skip(query.skip)
+        .limit(query.limit.unwrap_or(50).clamp(1, 500))
+        .build();
+    let found: Vec<Card> = database
+        .collection::<Card>("cards")
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(found))
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    q: String,
+    suite: Option<Suite>,
+    set: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchHit {
+    #[serde(flatten)]
+    card: Card,
+    /// Mongo's text score, higher for closer matches.
+    score: f64,
+}
+
+/// Cards whose text matches the words of `q`, best first, so curators can
+/// check whether a card exists before adding it again.
+async fn search_cards(
+    query: web::Query<SearchQuery>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let q = query.q.trim();
+    if q.is_empty() {
+        return Err(ErrorBadRequest("q must not be empty"));
+    }
+    let database = store.database();
+    let mut filter = doc! { "$text": { "$search": q } };
+    if let Some(set) = &query.set {
+        filter.insert("set_uuid", uuid_bson(set));
+    }
+    if let Some(suite) = query.suite {
+        filter.insert("suite", bson_value(&suite)?);
+    }
+    let filter = visible_cards(database, filter, safe_mode.0).await?;
+    let filter = rated_cards(filter, max_rating);
+    let score = doc! { "score": { "$meta": "textScore" } };
+    let options = FindOptions::builder()
+        .projection(score.clone())
+        .sort(score)
+        .limit(query.limit.unwrap_or(20).clamp(1, 100))
+        .build();
+    let mut cursor = database
+        .collection::<Document>("cards")
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?;
+    let mut hits = Vec::new();
+    while let Some(mut found) = cursor.try_next().await.map_err(AppError::from)? {
+        let score = found
+            .remove("score")
+            .and_then(|s| s.as_f64())
+            .unwrap_or(0.0);
+        let card = from_document(found).map_err(ErrorInternalServerError)?;
+        hits.push(SearchHit { card, score });
+    }
+    Ok(web::Json(hits))
+}
+
+/// Most cards a single lookup may ask for.
+const MAX_LOOKUP: usize = 500;
+
+#[derive(Debug, Deserialize)]
+struct CardLookup {
+    uuids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+struct LookupResult {
+    cards: Vec<Card>,
+    missing: Vec<Uuid>,
+}
+
+/// Resolves a batch of card ids in one query, returning the cards in the
+/// order they were asked for and the ids that matched nothing.
+async fn lookup_cards(
+    body: web::Json<CardLookup>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    if body.uuids.len() > MAX_LOOKUP {
+        return Err(ErrorBadRequest(format!(
+            "at most {MAX_LOOKUP} cards may be looked up at once"
+        )));
+    }
+    let database = store.database();
+    let ids: Vec<Bson> = body.uuids.iter().map(uuid_bson).collect();
+    let filter = visible_cards(database, doc! { "uuid": { "$in": ids } }, false).await?;
+    let found: HashMap<Uuid, Card> = database
+        .collection::<Card>("cards")
+        .find(filter, None)
+        .await
+        .map_err(AppError::from)?
+        .map_ok(|card| (card.uuid, card))
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let mut result = LookupResult {
+        cards: Vec::with_capacity(found.len()),
+        missing: Vec::new(),
+    };
+    for id in &body.uuids {
+        match found.get(id) {
+            Some(card) => result.cards.push(card.clone()),
+            None => result.missing.push(*id),
+        }
+    }
+    Ok(web::Json(result))
+}
+
+const LEADERBOARD_SIZE: i32 = 25;
+const LEADERBOARD_REFRESH: Duration = Duration::from_secs(60 * 60);
+/// Cards submitted fewer times than this are left off win rate boards.
+const MIN_SUBMISSIONS: i64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LeaderboardKind {
+    Score,
+    WinRate,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LeaderboardEntry {
+    uuid: Uuid,
+    set_uuid: Uuid,
+    suite: Suite,
+    text: String,
+    value: f64,
+}
+
+/// Precomputed ranking for one suite, across all sets or within one set.
+#[derive(Debug, Serialize, Deserialize)]
+struct Leaderboard {
+    kind: LeaderboardKind,
+    suite: Suite,
+    #[serde(default)]
+    set_uuid: Option<Uuid>,
+    /// Ranks only cards shown in safe mode, see `visible_cards`.
+    #[serde(default)]
+    safe: bool,
+    entries: Vec<LeaderboardEntry>,
+    refreshed_at: DateTime,
+}
+
+/// Ranks the documents produced by `source` (which must expose the card
+/// fields and a `value`) into one leaderboard per suite, or per set and suite.
+fn leaderboard_pipeline(
+    mut source: Vec<Document>,
+    kind: LeaderboardKind,
+    per_set: bool,
+) -> Result<Vec<Document>, ActixError> {
+    let group = match per_set {
+        true => doc! { "suite": "$suite", "set_uuid": "$set_uuid" },
+        false => doc! { "suite": "$suite" },
+    };
+    source.extend([
+        doc! { "$sort": { "value": -1 } },
+        doc! { "$group": {
+            "_id": group,
+            "entries": { "$push": {
+                "uuid": "$uuid",
+                "set_uuid": "$set_uuid",
+                "suite": "$suite",
+                "text": "$text",
+                "value": { "$toDouble": "$value" },
+            } },
+        } },
+        doc! { "$project": {
+            "_id": 0,
+            "kind": bson_value(&kind)?,
+            "suite": "$_id.suite",
+            "set_uuid": "$_id.set_uuid",
+            "entries": { "$slice": ["$entries", LEADERBOARD_SIZE] },
+            "refreshed_at": "$$NOW",
+        } },
+    ]);
+    Ok(source)
+}
+
+/// Rebuilds every leaderboard from card votes and in-game play statistics,
+/// once over the cards the public may see and once over those safe mode
+/// shows.
+async fn refresh_leaderboards() -> Result<(), ActixError> {
+    let database = database().await.map_err(AppError::from)?;
+    let mut boards = Vec::new();
+    for safe in [false, true] {
+        let voted = doc! { "votes.score": { "$gt": 0 } };
+        let by_score = vec![
+            doc! { "$match": visible_cards(&database, voted, safe).await? },
+            doc! { "$addFields": { "value": "$votes.score" } },
+        ];
+        let by_win_rate = vec![
+            doc! { "$match": { "submitted": { "$gte": MIN_SUBMISSIONS } } },
+            doc! { "$lookup": {
+                "from": "cards",
+                "localField": "card_uuid",
+                "foreignField": "uuid",
+                "as": "card",
+            } },
+            doc! { "$unwind": "$card" },
+            doc! { "$project": {
+                "uuid": "$card.uuid",
+                "set_uuid": "$card.set_uuid",
+                "suite": "$card.suite",
+                "text": "$card.text",
+                "deleted_at": "$card.deleted_at",
+                "screened": "$card.screened",
+                "nsfw": "$card.nsfw",
+                "value": { "$divide": ["$wins", "$submitted"] },
+            } },
+            doc! { "$match": visible_cards(&database, doc! {}, safe).await? },
+        ];
+
+        for (collection, kind, source) in [
+            ("cards", LeaderboardKind::Score, by_score),
+            ("card_stats", LeaderboardKind::WinRate, by_win_rate),
+        ] {
+            for per_set in [false, true] {
+                let pipeline = leaderboard_pipeline(source.clone(), kind, per_set)?;
+                let documents: Vec<Document> = database
+                    .collection::<Document>(collection)
+                    .aggregate(pipeline, None)
+                    .await
+                    .map_err(AppError::from)?
+                    .try_collect()
+                    .await
+                    .map_err(AppError::from)?;
+                for document in documents {
+                    let mut board =
+                        from_document::<Leaderboard>(document).map_err(ErrorInternalServerError)?;
+                    board.safe = safe;
+                    boards.push(board);
+                }
+            }
+        }
+    }
+
+    let leaderboards: Collection<Leaderboard> = database.collection("leaderboards");
+    leaderboards
+        .delete_many(doc! {}, None)
+        .await
+        .map_err(AppError::from)?;
+    if !boards.is_empty() {
+        leaderboards
+            .insert_many(boards, None)
+            .await
+            .map_err(AppError::from)?;
+    }
+    Ok(())
+}
+
+/// Names this process when it holds a lease: `CAH_INSTANCE_ID`, or a
+/// random id drawn at startup.
+fn instance_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| {
+        std::env::var("CAH_INSTANCE_ID")
+            .ok()
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| Uuid::new_v4().to_string())
+    })
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    match &*error.kind {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == 11000,
+        ErrorKind::Command(e) => e.code == 11000,
+        _ => false,
+    }
+}
+
+/// Starts a transaction on `session`, or returns false on a standalone
+/// server, which has none. Writes then go through the session one by one
+/// and the caller undoes them itself if a later one fails.
+async fn try_transaction(session: &mut ClientSession) -> Result<bool, AppError> {
+    match session.start_transaction(None).await {
+        Ok(()) => Ok(true),
+        Err(err) if matches!(*err.kind, ErrorKind::Transaction { .. }) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+static LEASE_INDEX: OnceCell<()> = OnceCell::const_new();
+
+/// Takes or renews the lease called `name` for `ttl`. At most one instance
+/// holds a lease at a time, so work guarded by one runs on a single node
+/// even when several share the database. A holder that dies loses the lease
+/// once it expires.
+async fn acquire_lease(name: &str, ttl: Duration) -> Result<bool, ActixError> {
+    let database = database().await.map_err(AppError::from)?;
+    let leases = database.collection::<Document>("leases");
+    LEASE_INDEX
+        .get_or_try_init(|| async {
+            let index = IndexModel::builder()
+                .keys(doc! { "name": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build();
+            leases.create_index(index, None).await.map(|_| ())
+        })
+        .await
+        .map_err(AppError::from)?;
+    let now = DateTime::now();
+    let expires_at = DateTime::from_millis(now.timestamp_millis() + ttl.as_millis() as i64);
+    let result = leases
+        .update_one(
+            doc! {
+                "name": name,
+                "$or": [{ "holder": instance_id() }, { "expires_at": { "$lte": now } }],
+            },
+            doc! { "$set": { "holder": instance_id(), "expires_at": expires_at } },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await;
+    match result {
+        Ok(_) => Ok(true),
+        // Someone else holds it, so the upsert collided with their lease.
+        Err(e) if is_duplicate_key(&e) => Ok(false),
+        Err(e) => Err(ErrorInternalServerError(e)),
+    }
+}
+
+/// Runs `job` every `period` for as long as the server is up, logging
+/// failures. Only the instance holding the job's lease runs it.
+fn schedule<F, Fut>(name: &'static str, period: Duration, job: F)
+where
+    F: Fn() -> Fut + 'static,
+    Fut: Future<Output = Result<(), ActixError>> + 'static,
+{
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(period);
+        loop {
+            interval.tick().await;
+            match acquire_lease(name, period * 2).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    eprintln!("Scheduled job {name} could not take its lease: {err}");
+                    continue;
+                }
+            }
+            if let Err(err) = job().await {
+                eprintln!("Scheduled job {name} failed: {err}");
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+struct LeaderboardRequest {
+    kind: LeaderboardKind,
+    suite: Suite,
+    set: Option<Uuid>,
+}
+
+async fn get_leaderboard(
+    query: web::Query<LeaderboardRequest>,
+    safe_mode: SafeMode,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let set = match &query.set {
+        Some(set) => uuid_bson(set),
+        None => Bson::Null,
+    };
+    let filter = doc! {
+        "kind": bson_value(&query.kind)?,
+        "suite": bson_value(&query.suite)?,
+        "set_uuid": set,
+        "safe": safe_mode.0,
+    };
+    let board = database
+        .collection::<Leaderboard>("leaderboards")
+        .find_one(filter, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound("no leaderboard computed yet"))?;
+    Ok(web::Json(board))
+}
+
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CardOfTheDay {
+    /// Days since the Unix epoch, in UTC.
+    day: i64,
+    date: DateTime,
+    card: Card,
+}
+
+/// Cards fit to be featured: not NSFW nor voted down.
+async fn featurable_cards(database: &Database) -> Result<Document, ActixError> {
+    visible_cards(database, doc! { "votes.score": { "$gte": 0 } }, true).await
+}
+
+/// Returns today's card, picking it on the first request of the day. The pick
+/// is seeded by the date and then stored, so it stays put for the whole day
+/// and remains in the history afterwards.
+async fn card_of_the_day(store: web::Data<Store>) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let history: Collection<CardOfTheDay> = database.collection("card_of_the_day");
+    let day = DateTime::now().timestamp_millis() / DAY_MILLIS;
+    if let Some(today) = history
+        .find_one(doc! { "day": day }, None)
+        .await
+        .map_err(AppError::from)?
+    {
+        return Ok(web::Json(today));
+    }
+
+    let cards: Collection<Card> = database.collection("cards");
+    let featurable = featurable_cards(database).await?;
+    let count = cards
+        .count_documents(featurable.clone(), None)
+        .await
+        .map_err(AppError::from)?;
+    if count == 0 {
+        return Err(ErrorNotFound("no cards available"));
+    }
+    let index = StdRng::seed_from_u64(day as u64).gen_range(0..count);
+    let options = FindOptions::builder()
+        .sort(doc! { "uuid": 1 })
+        .skip(index)
+        .limit(1)
+        .build();
+    let card = cards
+        .find(featurable, options)
+        .await
+        .map_err(AppError::from)?
+        .try_next()
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound("no cards available"))?;
+
+    let today = CardOfTheDay {
+        day,
+        date: DateTime::from_millis(day * DAY_MILLIS),
+        card,
+    };
+    // Another request may have picked concurrently; keep whichever landed first.
+    history
+        .update_one(
+            doc! { "day": day },
+            doc! { "$setOnInsert": bson_value(&today)? },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map_err(AppError::from)?;
+    let today = history
+        .find_one(doc! { "day": day }, None)
+        .await
+        .map_err(AppError::from)?
+        .unwrap_or(today);
+    Ok(web::Json(today))
+}
+
+async fn card_of_the_day_history(
+    query: web::Query<LimitQuery>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let options = FindOptions::builder()
+        .sort(doc! { "day": -1 })
+        .limit(query.limit.unwrap_or(30).clamp(1, 365))
+        .build();
+    let history: Vec<CardOfTheDay> = database
+        .collection::<CardOfTheDay>("card_of_the_day")
+        .find(doc! {}, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(history))
+}
+
+/// How often `list_visible` runs.
+const LISTING_INTERVAL: Duration = Duration::from_secs(10);
+/// How old a `listed_at` stamp must be before the feeds serve it, so a page
+/// never ends part way through a listing pass and skips the rest of it.
+const FEED_SETTLE: Duration = Duration::from_secs(30);
+
+/// Keeps `listed_at` in step with what the public may see: cards and sets
+/// that became visible, by being stored, published, approved or restored,
+/// are stamped with the time, and those hidden again lose their stamp so
+/// they are listed anew when they come back. The feeds page on this stamp,
+/// so a mirror never misses a document that showed up after its cursor
+/// moved on.
+async fn list_visible() -> Result<(), ActixError> {
+    let database = database().await.map_err(AppError::from)?;
+    let now = DateTime::now();
+    for (collection, visible) in [
+        ("cards", visible_cards(&database, doc! {}, false).await?),
+        ("sets", visible_sets(false)),
+    ] {
+        let collection = database.collection::<Document>(collection);
+        collection
+            .update_many(
+                doc! { "listed_at": { "$ne": null }, "$nor": [visible.clone()] },
+                doc! { "$unset": { "listed_at": "" } },
+                None,
+            )
+            .await
+            .map_err(AppError::from)?;
+        collection
+            .update_many(
+                doc! { "$and": [visible, { "listed_at": null }] },
+                doc! { "$set": { "listed_at": now } },
+                None,
+            )
+            .await
+            .map_err(AppError::from)?;
+    }
+    Ok(())
+}
+
+/// Position in a recently-added feed: the listing time and UUID of the last
+/// item seen, rendered as `<millis>_<uuid>`.
+#[derive(Debug, Clone, Copy)]
+struct FeedCursor {
+    listed_at: DateTime,
+    uuid: Option<Uuid>,
+}
+
+impl FeedCursor {
+    /// Accepts an RFC 3339 timestamp or a cursor returned by an earlier page.
+    fn parse(value: &str) -> Result<Self, ActixError> {
+        if let Ok(listed_at) = DateTime::parse_rfc3339_str(value) {
+            return Ok(FeedCursor {
+                listed_at,
+                uuid: None,
+            });
+        }
+        let (millis, uuid) = value
+            .split_once('_')
+            .ok_or_else(|| ErrorBadRequest("since must be a timestamp or a cursor"))?;
+        Ok(FeedCursor {
+            listed_at: DateTime::from_millis(millis.parse().map_err(ErrorBadRequest)?),
+            uuid: match uuid {
+                "" => None,
+                uuid => Some(Uuid::parse_str(uuid).map_err(ErrorBadRequest)?),
+            },
+        })
+    }
+
+    fn filter(&self) -> Document {
+        match &self.uuid {
+            Some(uuid) => doc! { "$or": [
+                { "listed_at": { "$gt": self.listed_at } },
+                { "listed_at": self.listed_at, "uuid": { "$gt": uuid_bson(uuid) } },
+            ] },
+            None => doc! { "listed_at": { "$gt": self.listed_at } },
+        }
+    }
+}
+
+impl std::fmt::Display for FeedCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_", self.listed_at.timestamp_millis())?;
+        match &self.uuid {
+            Some(uuid) => write!(f, "{uuid}"),
+            None => Ok(()),
+        }
+    }
+}
+
+trait FeedItem {
+    fn cursor(&self) -> FeedCursor;
+}
+
+impl FeedItem for Card {
+    fn cursor(&self) -> FeedCursor {
+        FeedCursor {
+            listed_at: self.listed_at.unwrap_or(self.created_at),
+            uuid: Some(self.uuid),
+        }
+    }
+}
+
+impl FeedItem for Set {
+    fn cursor(&self) -> FeedCursor {
+        FeedCursor {
+            listed_at: self.listed_at.unwrap_or(self.created_at),
+            uuid: Some(self.uuid),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedQuery {
+    since: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct FeedPage<T> {
+    items: Vec<T>,
+    /// Pass back as `since` to continue; unchanged when nothing new arrived.
+    next: Option<String>,
+}
+
+/// Pages through a collection in the order the public got to see it, see
+/// `list_visible`, so mirrors can poll with the returned cursor and only
+/// ever receive what they have not seen.
+async fn recent<T>(
+    database: &Database,
+    collection: &str,
+    base: Document,
+    query: &FeedQuery,
+) -> Result<FeedPage<T>, ActixError>
+where
+    T: FeedItem + serde::de::DeserializeOwned + Unpin + Send + Sync,
+{
+    let cursor = query.since.as_deref().map(FeedCursor::parse).transpose()?;
+    let settled =
+        DateTime::from_millis(DateTime::now().timestamp_millis() - FEED_SETTLE.as_millis() as i64);
+    let mut clauses = vec![base, doc! { "listed_at": { "$lt": settled } }];
+    clauses.extend(cursor.map(|cursor| cursor.filter()));
+    let filter = doc! { "$and": clauses };
+    let options = FindOptions::builder()
+        .sort(doc! { "listed_at": 1, "uuid": 1 })
+        .limit(query.limit.unwrap_or(100).clamp(1, 1000))
+        .build();
+    let items: Vec<T> = database
+        .collection::<T>(collection)
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let next = items
+        .last()
+        .map(FeedItem::cursor)
+        .or(cursor)
+        .map(|c| c.to_string());
+    Ok(FeedPage { items, next })
+}
+
+async fn recent_cards(
+    query: web::Query<FeedQuery>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let base = visible_cards(database, doc! {}, safe_mode.0).await?;
+    let base = rated_cards(base, max_rating);
+    Ok(web::Json(
+        recent::<Card>(database, "cards", base, &query).await?,
+    ))
+}
+
+async fn recent_sets(
+    query: web::Query<FeedQuery>,
+    safe_mode: SafeMode,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let base = visible_sets(safe_mode.0);
+    Ok(web::Json(
+        recent::<Set>(database, "sets", base, &query).await?,
+    ))
+}
+
+async fn list_card_types(store: web::Data<Store>) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let mut types: Vec<CardType> = CardTypes::load(database)
+        .await
+        .map_err(AppError::from)?
+        .0
+        .into_values()
+        .collect();
+    types.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(web::Json(types))
+}
+
+#[derive(Debug, Deserialize)]
+struct DefineCardType {
+    label: String,
+    suite: Suite,
+    #[serde(default)]
+    special: String,
+}
+
+/// Creates or redefines a card type. Its label may not shadow a built-in
+/// suite or another type's label.
+async fn define_card_type(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<String>,
+    body: web::Json<DefineCardType>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let body = body.into_inner();
+    let card_type = CardType {
+        name: normalize_tag(&path)?,
+        label: body.label.trim().to_string(),
+        suite: body.suite,
+        special: body.special.trim().to_string(),
+    };
+    if card_type.label.is_empty() || Suite::from_str(&card_type.label).is_some() {
+        return Err(ErrorBadRequest(format!(
+            "{:?} cannot be used as a card type label",
+            card_type.label
+        )));
+    }
+    let database = store.database();
+    let types = database.collection::<CardType>("card_types");
+    let taken = types
+        .find_one(
+            doc! { "label": &card_type.label, "name": { "$ne": &card_type.name } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?;
+    if let Some(other) = taken {
+        return Err(ErrorConflict(format!(
+            "label {:?} is already used by {}",
+            other.label, other.name
+        )));
+    }
+    let before = types
+        .find_one_and_replace(
+            doc! { "name": &card_type.name },
+            &card_type,
+            FindOneAndReplaceOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(before.as_ref(), Some(&card_type))?;
+    audit(
+        database,
+        user.as_ref(),
+        "define",
+        "card_type",
+        None,
+        changes,
+        doc! { "name": &card_type.name },
+    )
+    .await;
+    Ok(web::Json(card_type))
+}
+
+/// Removes a card type. Cards already imported as it keep playing as their
+/// suite.
+async fn delete_card_type(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<String>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let deleted = database
+        .collection::<CardType>("card_types")
+        .find_one_and_delete(doc! { "name": normalize_tag(&path)? }, None)
+        .await
+        .map_err(AppError::from)?;
+    if let Some(card_type) = deleted {
+        let changes = field_changes(Some(&card_type), None)?;
+        audit(
+            database,
+            user.as_ref(),
+            "delete",
+            "card_type",
+            None,
+            changes,
+            doc! { "name": &card_type.name },
+        )
+        .await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn list_mapping_profiles(store: web::Data<Store>) -> Result<impl Responder, ActixError> {
+    let options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+    let profiles: Vec<ColumnProfile> = store
+        .database()
+        .collection::<ColumnProfile>("mapping_profiles")
+        .find(None, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(profiles))
+}
+
+/// Creates or replaces a mapping profile, which imports then select by
+/// name. The suite and special headers may not be blank, as every column
+/// would match them.
+async fn define_mapping_profile(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<String>,
+    body: web::Json<ColumnProfile>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let body = body.into_inner();
+    let profile = ColumnProfile {
+        name: normalize_tag(&path)?,
+        suite: body.suite.trim().to_string(),
+        text: body.text.filter(|t| !t.trim().is_empty()),
+        special: body.special.trim().to_string(),
+        edition: body.edition.trim().to_string(),
+    };
+    let headers = [&profile.suite, &profile.special, &profile.edition];
+    if headers.iter().any(|h| h.is_empty()) {
+        return Err(ErrorBadRequest(
+            "suite, special and edition headers cannot be empty",
+        ));
+    }
+    if profile.suite == profile.special {
+        return Err(ErrorBadRequest("suite and special headers must differ"));
+    }
+    let database = store.database();
+    let before = database
+        .collection::<ColumnProfile>("mapping_profiles")
+        .find_one_and_replace(
+            doc! { "name": &profile.name },
+            &profile,
+            FindOneAndReplaceOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(before.as_ref(), Some(&profile))?;
+    audit(
+        database,
+        user.as_ref(),
+        "define",
+        "mapping_profile",
+        None,
+        changes,
+        doc! { "name": &profile.name },
+    )
+    .await;
+    Ok(web::Json(profile))
+}
+
+async fn delete_mapping_profile(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<String>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let deleted = database
+        .collection::<ColumnProfile>("mapping_profiles")
+        .find_one_and_delete(doc! { "name": normalize_tag(&path)? }, None)
+        .await
+        .map_err(AppError::from)?;
+    if let Some(profile) = deleted {
+        let changes = field_changes(Some(&profile), None)?;
+        audit(
+            database,
+            user.as_ref(),
+            "delete",
+            "mapping_profile",
+            None,
+            changes,
+            doc! { "name": &profile.name },
+        )
+        .await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Publishes every draft whose `publish_at` has passed.
+async fn publish_due_sets() -> Result<(), ActixError> {
+    let database = database().await.map_err(AppError::from)?;
+    let result = database
+        .collection::<Set>("sets")
+        .update_many(
+            doc! { "status": "draft", "publish_at": { "$lte": DateTime::now() } },
+            doc! { "$set": { "status": "published" } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?;
+    if result.modified_count > 0 {
+        println!("Published {} scheduled sets", result.modified_count);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct SetStatusUpdate {
+    status: SetStatus,
+    /// With a draft status, publish automatically at this time.
+    publish_at: Option<DateTime>,
+}
+
+/// Declares that a set needs `dependency` to be playable.
+async fn add_set_dependency(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(Uuid, Uuid)>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let (id, dependency) = path.into_inner();
+    if id == dependency {
+        return Err(ErrorBadRequest("a set cannot require itself"));
+    }
+    let database = store.database();
+    let sets: Collection<Set> = database.collection("sets");
+    let before = find_set(&sets, &id).await?;
+    find_set(&sets, &dependency).await?;
+    if missing_dependencies(database, &[dependency])
+        .await?
+        .contains(&id)
+    {
+        return Err(ErrorConflict(format!(
+            "set {dependency} already requires {id}"
+        )));
+    }
+    sets.update_one(
+        doc! { "uuid": uuid_bson(&id) },
+        doc! { "$addToSet": { "requires": uuid_bson(&dependency) } },
+        None,
+    )
+    .await
+    .map_err(AppError::from)?;
+    let set = find_set(&sets, &id).await?;
+    let changes = field_changes(Some(&before), Some(&set))?;
+    audit(
+        database,
+        user.as_ref(),
+        "require",
+        "set",
+        Some(id),
+        changes,
+        doc! { "dependency": uuid_bson(&dependency) },
+    )
+    .await;
+    Ok(web::Json(set))
+}
+
+async fn remove_set_dependency(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(Uuid, Uuid)>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let (id, dependency) = path.into_inner();
+    let database = store.database();
+    let sets: Collection<Set> = database.collection("sets");
+    let before = find_set(&sets, &id).await?;
+    sets.update_one(
+        doc! { "uuid": uuid_bson(&id) },
+        doc! { "$pull": { "requires": uuid_bson(&dependency) } },
+        None,
+    )
+    .await
+    .map_err(AppError::from)?;
+    let set = find_set(&sets, &id).await?;
+    let changes = field_changes(Some(&before), Some(&set))?;
+    audit(
+        database,
+        user.as_ref(),
+        "unrequire",
+        "set",
+        Some(id),
+        changes,
+        doc! { "dependency": uuid_bson(&dependency) },
+    )
+    .await;
+    Ok(web::Json(set))
+}
+
+async fn update_set_status(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    body: web::Json<SetStatusUpdate>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let publish_at = match (body.status, body.publish_at) {
+        (SetStatus::Draft, Some(at)) => Bson::DateTime(at),
+        (SetStatus::Published, Some(_)) => {
+            return Err(ErrorBadRequest("only drafts can be scheduled"));
+        }
+        (_, None) => Bson::Null,
+    };
+    let database = store.database();
+    let before = find_set(&store.sets(), &path).await?;
+    let set = database
+        .collection::<Set>("sets")
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&path) },
+            doc! { "$set": { "status": bson_value(&body.status)?, "publish_at": publish_at } },
+            FindOneAndUpdateOptions::builder()
+                .return_document(ReturnDocument::After)
+                .build(),
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("set {path} not found")))?;
+    let changes = field_changes(Some(&before), Some(&set))?;
+    audit(
+        database,
+        user.as_ref(),
+        "update",
+        "set",
+        Some(set.uuid),
+        changes,
+        doc! {},
+    )
+    .await;
+    Ok(web::Json(set))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetVersionSummary {
+    version: i64,
+    reason: String,
+    created_at: DateTime,
+    card_count: i64,
+}
+
+async fn list_set_versions(
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let pipeline = [
+        doc! { "$match": { "set_uuid": uuid_bson(&path) } },
+        doc! { "$sort": { "version": -1 } },
+        doc! { "$project": {
+            "_id": 0,
+            "version": 1,
+            "reason": 1,
+            "created_at": 1,
+            "card_count": { "$size": "$cards" },
+        } },
+    ];
+    let documents: Vec<Document> = database
+        .collection::<SetVersion>("set_versions")
+        .aggregate(pipeline, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let versions = documents
+        .into_iter()
+        .map(|d| from_document::<SetVersionSummary>(d).map_err(ErrorInternalServerError))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(web::Json(versions))
+}
+
+async fn find_set_version(
+    database: &Database,
+    set: &Uuid,
+    version: i64,
+) -> Result<SetVersion, ActixError> {
+    database
+        .collection::<SetVersion>("set_versions")
+        .find_one(
+            doc! { "set_uuid": uuid_bson(set), "version": version },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("set {set} has no version {version}")))
+}
+
+async fn get_set_version(
+    path: web::Path<(Uuid, i64)>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    Ok(web::Json(
+        find_set_version(database, &path.0, path.1).await?,
+    ))
+}
+
+#[derive(Debug, Serialize)]
+struct ChangedCard {
+    before: CardSnapshot,
+    after: CardSnapshot,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct CardDiff {
+    added: Vec<CardSnapshot>,
+    removed: Vec<CardSnapshot>,
+    changed: Vec<ChangedCard>,
+}
+
+impl CardDiff {
+    /// Compares two card lists, pairing cards by UUID.
+    fn between(before: &[CardSnapshot], after: &[CardSnapshot]) -> Self {
+        let old: HashMap<Uuid, &CardSnapshot> = before.iter().map(|c| (c.uuid, c)).collect();
+        let new: HashMap<Uuid, &CardSnapshot> = after.iter().map(|c| (c.uuid, c)).collect();
+        let mut diff = CardDiff::default();
+        for card in after {
+            match old.get(&card.uuid) {
+                None => diff.added.push(card.clone()),
+                Some(&previous) if previous != card => diff.changed.push(ChangedCard {
+                    before: previous.clone(),
+                    after: card.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        diff.removed = before
+            .iter()
+            .filter(|c| !new.contains_key(&c.uuid))
+            .cloned()
+            .collect();
+        diff
+    }
+}
+
+impl CardDiff {
+    /// Compares the cards of two editions. A card is paired with the same
+    /// card, else one of its card group, else one with the same suite and
+    /// normalized text, so a reprint under a new id only counts as changed
+    /// when it was reworded.
+    fn between_editions(before: Vec<Card>, after: Vec<Card>) -> Self {
+        let mut by_uuid: HashMap<Uuid, usize> = HashMap::new();
+        let mut by_group: HashMap<Uuid, Vec<usize>> = HashMap::new();
+        let mut by_text: HashMap<(Suite, String), Vec<usize>> = HashMap::new();
+        for (i, card) in before.iter().enumerate() {
+            by_uuid.insert(card.uuid, i);
+            if let Some(group) = card.card_group {
+                by_group.entry(group).or_default().push(i);
+            }
+            by_text
+                .entry((card.suite, normalize_text(&card.text)))
+                .or_default()
+                .push(i);
+        }
+        let mut paired = vec![false; before.len()];
+        let mut diff = CardDiff::default();
+        for card in after {
+            let group = card.card_group.and_then(|g| by_group.get(&g));
+            let text = by_text.get(&(card.suite, normalize_text(&card.text)));
+            let partner = by_uuid
+                .get(&card.uuid)
+                .into_iter()
+                .chain(group.into_iter().flatten())
+                .chain(text.into_iter().flatten())
+                .copied()
+                .find(|&i| !paired[i]);
+            match partner {
+                None => diff.added.push(card.into()),
+                Some(i) => {
+                    paired[i] = true;
+                    let previous = &before[i];
+                    if previous.text != card.text || previous.special != card.special {
+                        diff.changed.push(ChangedCard {
+                            before: previous.clone().into(),
+                            after: card.into(),
+                        });
+                    }
+                }
+            }
+        }
+        diff.removed = before
+            .into_iter()
+            .zip(paired)
+            .filter(|(_, paired)| !paired)
+            .map(|(card, _)| card.into())
+            .collect();
+        diff
+    }
+}
+
+async fn edition_cards(database: &Database, edition: &Uuid) -> Result<Vec<Card>, ActixError> {
+    let cards: Vec<Card> = database
+        .collection::<Card>("cards")
+        .find(doc! { "editions": uuid_bson(edition) }, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    if cards.is_empty() {
+        return Err(ErrorNotFound(format!("edition {edition} not found")));
+    }
+    Ok(cards)
+}
+
+/// What changed from one edition of a set to another: cards added, removed
+/// and reworded.
+async fn compare_editions(
+    path: web::Path<(Uuid, Uuid)>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let (from, to) = path.into_inner();
+    let database = store.database();
+    let before = edition_cards(database, &from).await?;
+    let after = edition_cards(database, &to).await?;
+    Ok(web::Json(CardDiff::between_editions(before, after)))
+}
+
+async fn diff_set_versions(
+    path: web::Path<(Uuid, i64, i64)>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let (set, from, to) = path.into_inner();
+    let database = store.database();
+    let before = find_set_version(database, &set, from).await?;
+    let after = find_set_version(database, &set, to).await?;
+    Ok(web::Json(CardDiff::between(&before.cards, &after.cards)))
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 200;
+
+#[derive(Debug, Deserialize)]
+struct PageQuery {
+    /// 1-based.
+    page: Option<u64>,
+    limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct SetSummary {
+    uuid: Uuid,
+    name: String,
+    card_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct SetPage {
+    page: u64,
+    limit: u64,
+    total: u64,
+    sets: Vec<SetSummary>,
+}
+
+/// Every set by name, a page at a time, with how many cards each holds.
+async fn list_set_pages<R: SetRepository + CardRepository>(
+    query: web::Query<PageQuery>,
+    repository: web::Data<R>,
+) -> Result<impl Responder, ActixError> {
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let total = repository.count_sets().await?;
+    let found = repository.list_sets((page - 1) * limit, limit).await?;
+    let mut sets = Vec::with_capacity(found.len());
+    for set in found {
+        let card_count = repository.count_cards(&set.uuid).await?;
+        sets.push(SetSummary {
+            uuid: set.uuid,
+            name: set.name,
+            card_count,
+        });
+    }
+    Ok(web::Json(SetPage {
+        page,
+        limit,
+        total,
+        sets,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct SetWithCards {
+    #[serde(flatten)]
+    set: Set,
+    cards: Vec<Card>,
+    editions: Vec<Edition>,
+}
+
+/// One set with every card and edition in it, which the stored set
+/// document leaves out.
+async fn get_set<R: SetRepository + CardRepository>(
+    path: web::Path<Uuid>,
+    repository: web::Data<R>,
+) -> Result<impl Responder, ActixError> {
+    let set = repository
+        .find_set(&path)
+        .await?
+        .ok_or_else(|| ErrorNotFound(format!("set {path} not found")))?;
+    let mut cards = repository.set_cards(&set.uuid).await?;
+    // Cards held back by screening wait for review, see `approve_card`.
+    cards.retain(|card| card.screened.is_empty());
+    let editions = repository.set_editions(&set.uuid).await?;
+    Ok(web::Json(SetWithCards {
+        set,
+        cards,
+        editions,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+struct TrashedSet {
+    uuid: Uuid,
+    /// Cards moved to the trash, or back out of it, with the set.
+    cards: u64,
+}
+
+/// Moves a set and its cards to the trash in one transaction. They stay
+/// there, out of every other query, until restored with `restore_set` or
+/// purged with `purge_trash`. On a standalone server a failure part way
+/// takes back out what was trashed, found by its `deleted_at`.
+async fn delete_set(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let now = DateTime::now();
+    let trash = doc! { "$set": { "deleted_at": now } };
+    let mut session = store
+        .client()
+        .start_session(None)
+        .await
+        .map_err(AppError::from)?;
+    let transaction = try_transaction(&mut session).await?;
+    let deleted = async {
+        let set = store
+            .sets()
+            .update_one_with_session(
+                doc! { "uuid": uuid_bson(&path), "deleted_at": null },
+                trash.clone(),
+                None,
+                &mut session,
+            )
+            .await?;
+        if set.matched_count == 0 {
+            return Ok(None);
+        }
+        let cards = store
+            .cards()
+            .update_many_with_session(
+                doc! { "set_uuid": uuid_bson(&path), "deleted_at": null },
+                trash,
+                None,
+                &mut session,
+            )
+            .await?;
+        Ok(Some(TrashedSet {
+            uuid: *path,
+            cards: cards.modified_count,
+        }))
+    }
+    .await;
+    match deleted {
+        Ok(Some(deleted)) => {
+            if transaction {
+                session.commit_transaction().await.map_err(AppError::from)?;
+            }
+            audit(
+                store.database(),
+                user.as_ref(),
+                "delete",
+                "set",
+                Some(deleted.uuid),
+                doc! { "deleted_at": { "before": Bson::Null, "after": now } },
+                doc! { "cards": deleted.cards as i64 },
+            )
+            .await;
+            Ok(web::Json(deleted))
+        }
+        Ok(None) => {
+            if transaction {
+                session.abort_transaction().await.map_err(AppError::from)?;
+            }
+            Err(ErrorNotFound(format!("set {path} not found")))
+        }
+        Err(err) if transaction => {
+            session.abort_transaction().await.map_err(AppError::from)?;
+            Err(AppError::Database(err).into())
+        }
+        Err(err) => {
+            let untrash = doc! { "$unset": { "deleted_at": "" } };
+            let undone = async {
+                store
+                    .cards()
+                    .update_many(
+                        doc! { "set_uuid": uuid_bson(&path), "deleted_at": now },
+                        untrash.clone(),
+                        None,
+                    )
+                    .await?;
+                store
+                    .sets()
+                    .update_one(
+                        doc! { "uuid": uuid_bson(&path), "deleted_at": now },
+                        untrash,
+                        None,
+                    )
+                    .await
+            }
+            .await;
+            if let Err(e) = undone {
+                eprintln!("could not undo the partial deletion of set {path}: {e}");
+            }
+            Err(AppError::Database(err).into())
+        }
+    }
+}
+
+/// Takes a set out of the trash with the cards trashed along with it.
+/// Cards deleted on their own before that stay in the trash. On a
+/// standalone server a failure part way puts the set and the cards already
+/// restored back in the trash.
+async fn restore_set(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let set = store
+        .sets()
+        .find_one(
+            doc! { "uuid": uuid_bson(&path), "deleted_at": { "$ne": null } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("set {path} is not in the trash")))?;
+    let restore = doc! { "$unset": { "deleted_at": "" } };
+    let trashed_cards = doc! { "set_uuid": uuid_bson(&path), "deleted_at": set.deleted_at };
+    let mut session = store
+        .client()
+        .start_session(None)
+        .await
+        .map_err(AppError::from)?;
+    let transaction = try_transaction(&mut session).await?;
+    // Without a transaction, the cards to put back should a write fail.
+    let card_ids: Vec<Bson> = match transaction {
+        true => Vec::new(),
+        false => store
+            .cards()
+            .distinct("uuid", trashed_cards.clone(), None)
+            .await
+            .map_err(AppError::from)?,
+    };
+    let restored = async {
+        store
+            .sets()
+            .update_one_with_session(
+                doc! { "uuid": uuid_bson(&path) },
+                restore.clone(),
+                None,
+                &mut session,
+            )
+            .await?;
+        store
+            .cards()
+            .update_many_with_session(trashed_cards, restore, None, &mut session)
+            .await
+    }
+    .await;
+    match restored {
+        Ok(cards) => {
+            if transaction {
+                session.commit_transaction().await.map_err(AppError::from)?;
+            }
+            audit(
+                store.database(),
+                user.as_ref(),
+                "restore",
+                "set",
+                Some(set.uuid),
+                doc! { "deleted_at": { "before": set.deleted_at, "after": Bson::Null } },
+                doc! { "cards": cards.modified_count as i64 },
+            )
+            .await;
+            Ok(web::Json(TrashedSet {
+                uuid: *path,
+                cards: cards.modified_count,
+            }))
+        }
+        Err(err) if transaction => {
+            session.abort_transaction().await.map_err(AppError::from)?;
+            Err(AppError::Database(err).into())
+        }
+        Err(err) => {
+            let trash = doc! { "$set": { "deleted_at": set.deleted_at } };
+            let undone = async {
+                store
+                    .cards()
+                    .update_many(
+                        doc! { "uuid": { "$in": &card_ids }, "deleted_at": null },
+                        trash.clone(),
+                        None,
+                    )
+                    .await?;
+                store
+                    .sets()
+                    .update_one(
+                        doc! { "uuid": uuid_bson(&path), "deleted_at": null },
+                        trash,
+                        None,
+                    )
+                    .await
+            }
+            .await;
+            if let Err(e) = undone {
+                eprintln!("could not undo the partial restore of set {path}: {e}");
+            }
+            Err(AppError::Database(err).into())
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Trash {
+    sets: Vec<Set>,
+    /// Cards deleted on their own; those of trashed sets go with the set.
+    cards: Vec<Card>,
+}
+
+async fn list_trash(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<impl Responder, ActixError> {
+    let trashed = doc! { "deleted_at": { "$ne": null } };
+    let options = FindOptions::builder()
+        .sort(doc! { "deleted_at": -1 })
+        .build();
+    let sets: Vec<Set> = database
+        .collection::<Set>("sets")
+        .find(trashed.clone(), options.clone())
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let set_ids: Vec<Bson> = sets.iter().map(|set| uuid_bson(&set.uuid)).collect();
+    let mut filter = trashed;
+    filter.insert("set_uuid", doc! { "$nin": set_ids });
+    let cards: Vec<Card> = database
+        .collection::<Card>("cards")
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(Trash { sets, cards }))
+}
+
+#[derive(Debug, Deserialize)]
+struct PurgeQuery {
+    /// Only purges what has been in the trash at least this long.
+    #[serde(default)]
+    older_than_days: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct PurgeReport {
+    sets: u64,
+    cards: u64,
+    editions: u64,
+}
+
+/// Deletes for good what has been in the trash long enough, trashed sets
+/// with all their cards and editions.
+async fn purge_trash(
+    _: Admin,
+    user: Option<User>,
+    TargetDatabase(database): TargetDatabase,
+    query: web::Query<PurgeQuery>,
+) -> Result<impl Responder, ActixError> {
+    let cutoff = DateTime::from_millis(
+        DateTime::now().timestamp_millis() - query.older_than_days.max(0) * 24 * 60 * 60 * 1000,
+    );
+    let expired = doc! { "deleted_at": { "$ne": null, "$lte": cutoff } };
+    let sets = database.collection::<Set>("sets");
+    let set_ids: Vec<Bson> = sets
+        .distinct("uuid", expired.clone(), None)
+        .await
+        .map_err(AppError::from)?;
+    let of_sets = doc! { "set_uuid": { "$in": &set_ids } };
+    let cards = database
+        .collection::<Card>("cards")
+        .delete_many(doc! { "$or": [expired.clone(), of_sets.clone()] }, None)
+        .await
+        .map_err(AppError::from)?;
+    let editions = database
+        .collection::<Edition>("editions")
+        .delete_many(of_sets, None)
+        .await
+        .map_err(AppError::from)?;
+    let purged = sets
+        .delete_many(doc! { "uuid": { "$in": &set_ids } }, None)
+        .await
+        .map_err(AppError::from)?;
+    audit(
+        &database,
+        user.as_ref(),
+        "purge",
+        "trash",
+        None,
+        doc! {},
+        doc! {
+            "older_than_days": query.older_than_days,
+            "sets": set_ids,
+            "cards": cards.deleted_count as i64,
+            "editions": editions.deleted_count as i64,
+        },
+    )
+    .await;
+    Ok(web::Json(PurgeReport {
+        sets: purged.deleted_count,
+        cards: cards.deleted_count,
+        editions: editions.deleted_count,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SetQuery {
+    locale: Option<String>,
+    tags: Option<String>,
+    exclude_tags: Option<String>,
+}
+
+/// Lists sets, narrowed to those playable in `locale` when one is given: the
+/// set's language matches the locale's language, or one of its editions was
+/// printed for the locale's region.
+async fn list_sets(
+    query: web::Query<SetQuery>,
+    safe_mode: SafeMode,
+    admin: Option<Admin>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let sets: Collection<Set> = database.collection("sets");
+
+    let mut filter = match &query.locale {
+        Some(locale) => {
+            let mut parts = locale.split(['-', '_']);
+            let language = parts.next().unwrap_or_default().to_lowercase();
+            let mut clauses = vec![doc! {
+                "language": { "$regex": format!("^{}(-|$)", regex_escape(&language)), "$options": "i" }
+            }];
+            if let Some(region) = parts.next_back() {
+                let editions: Collection<Edition> = database.collection("editions");
+                let set_ids = editions
+                    .distinct(
+                        "set_uuid",
+                        doc! { "country_code": region.to_uppercase() },
+                        None,
+                    )
+                    .await
+                    .map_err(AppError::from)?;
+                clauses.push(doc! { "uuid": { "$in": set_ids } });
+            }
+            doc! { "$or": clauses }
+        }
+        None => doc! {},
+    };
+    let tags = parse_tag_list(query.tags.as_deref())?;
+    let exclude_tags = parse_tag_list(query.exclude_tags.as_deref())?;
+    if let Some(tags) = tag_filter(&tags, &exclude_tags) {
+        filter.insert("tags", tags);
+    }
+    // Admins also see drafts, so they can review them before publishing.
+    // The trash has its own listing.
+    let visible = match admin {
+        Some(_) if !safe_mode.0 => doc! { "deleted_at": null },
+        Some(_) => doc! { "nsfw": { "$ne": true }, "deleted_at": null },
+        None => visible_sets(safe_mode.0),
+    };
+    let filter = doc! { "$and": [filter, visible] };
+
+    let found: Vec<Set> = sets
+        .find(filter, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(featured_first(database, found).await?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FeaturedKind {
+    Set,
+    Collection,
+}
+
+/// A set or card collection an admin pinned to the top of browse pages.
+#[derive(Debug, Serialize, Deserialize)]
+struct Featured {
+    kind: FeaturedKind,
+    uuid: Uuid,
+    /// Lower comes first; ties go to the most recently pinned.
+    position: i32,
+    pinned_at: DateTime,
+}
+
+/// A hand-picked list of cards, such as "Best of the holiday packs".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CardCollection {
+    uuid: Uuid,
+    name: String,
+    #[serde(default)]
+    description: String,
+    cards: Vec<Uuid>,
+    created_at: DateTime,
+}
+
+/// Ids pinned as `kind`, in display order.
+async fn featured_ids(database: &Database, kind: FeaturedKind) -> Result<Vec<Uuid>, ActixError> {
+    let options = FindOptions::builder()
+        .sort(doc! { "position": 1, "pinned_at": -1 })
+        .build();
+    let featured: Vec<Featured> = database
+        .collection::<Featured>("featured")
+        .find(doc! { "kind": bson_value(&kind)? }, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(featured.into_iter().map(|f| f.uuid).collect())
+}
+
+/// Moves featured sets to the front in their pinned order, leaving the rest
+/// as they were.
+async fn featured_first(database: &Database, mut sets: Vec<Set>) -> Result<Vec<Set>, ActixError> {
+    let featured = featured_ids(database, FeaturedKind::Set).await?;
+    sets.sort_by_key(|set| {
+        featured
+            .iter()
+            .position(|id| *id == set.uuid)
+            .unwrap_or(featured.len())
+    });
+    Ok(sets)
+}
+
+async fn collection_cards(
+    database: &Database,
+    collection: &CardCollection,
+    safe: bool,
+) -> Result<Vec<Card>, ActixError> {
+    let ids: Vec<Bson> = collection.cards.iter().map(uuid_bson).collect();
+    let filter = visible_cards(database, doc! { "uuid": { "$in": ids } }, safe).await?;
+    let mut cards: Vec<Card> = database
+        .collection::<Card>("cards")
+        .find(filter, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    cards.sort_by_key(|card| collection.cards.iter().position(|id| *id == card.uuid));
+    Ok(cards)
+}
+
+async fn find_collection(database: &Database, id: &Uuid) -> Result<CardCollection, ActixError> {
+    database
+        .collection::<CardCollection>("card_collections")
+        .find_one(doc! { "uuid": uuid_bson(id) }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("collection {id} not found")))
+}
+
+#[derive(Debug, Serialize)]
+struct CollectionWithCards {
+    #[serde(flatten)]
+    collection: CardCollection,
+    cards: Vec<Card>,
+}
+
+#[derive(Debug, Serialize)]
+struct FeaturedContent {
+    sets: Vec<Set>,
+    collections: Vec<CollectionWithCards>,
+}
+
+/// Everything admins have pinned, in order, for the top of browse pages.
+async fn get_featured(
+    safe_mode: SafeMode,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let set_ids: Vec<Bson> = featured_ids(database, FeaturedKind::Set)
+        .await?
+        .iter()
+        .map(uuid_bson)
+        .collect();
+    let mut filter = visible_sets(safe_mode.0);
+    filter.insert("uuid", doc! { "$in": set_ids });
+    let sets: Vec<Set> = database
+        .collection::<Set>("sets")
+        .find(filter, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let sets = featured_first(database, sets).await?;
+
+    let mut collections = Vec::new();
+    for id in featured_ids(database, FeaturedKind::Collection).await? {
+        let collection = find_collection(database, &id).await?;
+        let cards = collection_cards(database, &collection, safe_mode.0).await?;
+        collections.push(CollectionWithCards { collection, cards });
+    }
+    Ok(web::Json(FeaturedContent { sets, collections }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PinRequest {
+    #[serde(default)]
+    position: i32,
+}
+
+async fn pin_featured(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(FeaturedKind, Uuid)>,
+    body: Option<web::Json<PinRequest>>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let (kind, id) = path.into_inner();
+    let database = store.database();
+    match kind {
+        FeaturedKind::Set => {
+            find_set(&database.collection("sets"), &id).await?;
+        }
+        FeaturedKind::Collection => {
+            find_collection(database, &id).await?;
+        }
+    }
+    let featured = Featured {
+        kind,
+        uuid: id,
+        position: body.map(|b| b.position).unwrap_or_default(),
+        pinned_at: DateTime::now(),
+    };
+    let before = database
+        .collection::<Featured>("featured")
+        .find_one_and_replace(
+            doc! { "kind": bson_value(&kind)?, "uuid": uuid_bson(&id) },
+            &featured,
+            FindOneAndReplaceOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(before.as_ref(), Some(&featured))?;
+    audit(
+        database,
+        user.as_ref(),
+        "pin",
+        "featured",
+        Some(id),
+        changes,
+        doc! {},
+    )
+    .await;
+    Ok(web::Json(featured))
+}
+
+async fn unpin_featured(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(FeaturedKind, Uuid)>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let (kind, id) = path.into_inner();
+    let database = store.database();
+    let deleted = database
+        .collection::<Featured>("featured")
+        .find_one_and_delete(
+            doc! { "kind": bson_value(&kind)?, "uuid": uuid_bson(&id) },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?;
+    if let Some(featured) = deleted {
+        let changes = field_changes(Some(&featured), None)?;
+        audit(
+            database,
+            user.as_ref(),
+            "unpin",
+            "featured",
+            Some(id),
+            changes,
+            doc! {},
+        )
+        .await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn list_collections(store: web::Data<Store>) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let options = FindOptions::builder().sort(doc! { "name": 1 }).build();
+    let collections: Vec<CardCollection> = database
+        .collection::<CardCollection>("card_collections")
+        .find(None, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(collections))
+}
+
+async fn get_collection(
+    path: web::Path<Uuid>,
+    safe_mode: SafeMode,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let collection = find_collection(database, &path).await?;
+    let cards = collection_cards(database, &collection, safe_mode.0).await?;
+    Ok(web::Json(CollectionWithCards { collection, cards }))
+}
+
+#[derive(Debug, Deserialize)]
+struct CollectionRequest {
+    name: String,
+    #[serde(default)]
+    description: String,
+    cards: Vec<Uuid>,
+}
+
+async fn create_collection(
+    _: Admin,
+    user: Option<User>,
+    body: web::Json<CollectionRequest>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let body = body.into_inner();
+    let name = body.name.trim().to_string();
+    if name.is_empty() || body.cards.is_empty() {
+        return Err(ErrorBadRequest("a collection needs a name and cards"));
+    }
+    let collection = CardCollection {
+        uuid: Uuid::new_v4(),
+        name,
+        description: body.description,
+        cards: body.cards,
+        created_at: DateTime::now(),
+    };
+    let database = store.database();
+    database
+        .collection::<CardCollection>("card_collections")
+        .insert_one(&collection, None)
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(None, Some(&collection))?;
+    audit(
+        database,
+        user.as_ref(),
+        "create",
+        "collection",
+        Some(collection.uuid),
+        changes,
+        doc! {},
+    )
+    .await;
+    Ok(HttpResponse::Created().json(collection))
+}
+
+async fn delete_collection(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let id = uuid_bson(&path);
+    let deleted = database
+        .collection::<CardCollection>("card_collections")
+        .find_one_and_delete(doc! { "uuid": &id }, None)
+        .await
+        .map_err(AppError::from)?;
+    database
+        .collection::<Featured>("featured")
+        .delete_one(doc! { "kind": "collection", "uuid": id }, None)
+        .await
+        .map_err(AppError::from)?;
+    if let Some(collection) = deleted {
+        let changes = field_changes(Some(&collection), None)?;
+        audit(
+            database,
+            user.as_ref(),
+            "delete",
+            "collection",
+            Some(collection.uuid),
+            changes,
+            doc! {},
+        )
+        .await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn regex_escape(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| {
+            let escape = !c.is_alphanumeric() && c != '_';
+            escape.then_some('\\').into_iter().chain(std::iter::once(c))
+        })
+        .collect()
+}
+
+/// Caps how many requests are worked on at once across all workers, see
+/// `Config::max_in_flight`. Past the cap requests are turned away straight
+/// away with a 503, so a busy instance slows down predictably instead of
+/// queueing without bound.
+///
+/// This service only holds the card library: games, players and their
+/// WebSockets live in the game server, which has to cap those itself.
+/// Here the HTTP requests in flight and, through `Config::max_connections`,
+/// the open connections are all there is to bound.
+#[derive(Debug)]
+struct LoadShedder {
+    max_in_flight: usize,
+    in_flight: AtomicUsize,
+    shed: AtomicU64,
+}
+
+impl LoadShedder {
+    fn new(max_in_flight: usize) -> Self {
+        LoadShedder {
+            max_in_flight: max_in_flight.max(1),
+            in_flight: AtomicUsize::new(0),
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    /// Admits a request if there is room, counting it until the returned
+    /// guard is dropped.
+    fn admit(self: &Arc<Self>) -> Option<InFlight> {
+        let admitted = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max_in_flight).then_some(n + 1)
+            })
+            .is_ok();
+        if !admitted {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(InFlight(self.clone()))
+    }
+}
+
+struct InFlight(Arc<LoadShedder>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn overloaded() -> ActixError {
+    let response = HttpResponse::ServiceUnavailable()
+        .insert_header(("Retry-After", "1"))
+        .body("server is at capacity, try again shortly");
+    InternalError::from_response("server is at capacity", response).into()
+}
+
+#[derive(Debug, Serialize)]
+struct LoadReport {
+    in_flight: usize,
+    max_in_flight: usize,
+    /// Requests turned away since the server started.
+    shed: u64,
+}
+
+async fn load_report(
+    _: Admin,
+    shedder: web::Data<LoadShedder>,
+) -> Result<impl Responder, ActixError> {
+    Ok(web::Json(LoadReport {
+        in_flight: shedder.in_flight.load(Ordering::Acquire),
+        max_in_flight: shedder.max_in_flight,
+        shed: shedder.shed.load(Ordering::Relaxed),
+    }))
+}
+
+/// Most offending ids listed per problem.
+const CHECK_SAMPLES: usize = 20;
+
+#[derive(Debug, Serialize)]
+struct Problem {
+    check: &'static str,
+    collection: &'static str,
+    count: usize,
+    samples: Vec<String>,
+    /// Whether `--fix` repairs it.
+    fixable: bool,
+    fixed: bool,
+}
+
+impl Problem {
+    fn new(check: &'static str, collection: &'static str, ids: &[Bson], fixable: bool) -> Self {
+        Problem {
+            check,
+            collection,
+            count: ids.len(),
+            samples: ids.iter().take(CHECK_SAMPLES).map(display_id).collect(),
+            fixable,
+            fixed: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub ok: bool,
+    problems: Vec<Problem>,
+}
+
+/// Shows a stored id the way the API does, falling back to its BSON form.
+fn display_id(value: &Bson) -> String {
+    match value {
+        Bson::Binary(binary) => Uuid::from_slice(&binary.bytes)
+            .map(|id| id.to_string())
+            .unwrap_or_else(|_| value.to_string()),
+        Bson::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+async fn matching_ids(
+    database: &Database,
+    collection: &str,
+    filter: Document,
+) -> Result<Vec<Bson>, mongodb::error::Error> {
+    database
+        .collection::<Document>(collection)
+        .distinct("uuid", filter, None)
+        .await
+}
+
+async fn duplicate_ids(
+    database: &Database,
+    collection: &str,
+) -> Result<Vec<Bson>, mongodb::error::Error> {
+    let pipeline = [
+        doc! { "$group": { "_id": "$uuid", "count": { "$sum": 1 } } },
+        doc! { "$match": { "count": { "$gt": 1 } } },
+    ];
+    let groups: Vec<Document> = database
+        .collection::<Document>(collection)
+        .aggregate(pipeline, None)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(groups
+        .into_iter()
+        .filter_map(|mut group| group.remove("_id"))
+        .collect())
+}
+
+/// Audits the library for damage the API would not produce on its own:
+/// missing indexes, unknown suites, empty cards, duplicate ids and cards
+/// pointing at sets or editions that do not exist. With `fix`, the safe
+/// repairs are made: missing indexes are built and dangling edition
+/// references dropped. Everything else needs a person to decide.
+pub async fn check_library(
+    database: &Database,
+    fix: bool,
+) -> Result<CheckReport, mongodb::error::Error> {
+    let mut problems = Vec::new();
+
+    for &(collection, fields, unique) in INDEXES {
+        let names = database
+            .collection::<Document>(collection)
+            .list_index_names()
+            .await
+            .unwrap_or_default();
+        if names.contains(&index_name(fields)) {
+            continue;
+        }
+        let mut problem = Problem::new(
+            "missing_index",
+            collection,
+            &[Bson::from(fields.join(","))],
+            true,
+        );
+        if fix {
+            // A unique index cannot be built over duplicates; those are
+            // reported on their own below.
+            problem.fixed = database
+                .collection::<Document>(collection)
+                .create_index(index_model(fields, unique), None)
+                .await
+                .is_ok();
+        }
+        problems.push(problem);
+    }
+
+    let suites: Vec<Bson> = [Suite::Prompt, Suite::Response]
+        .iter()
+        .filter_map(|suite| to_bson(suite).ok())
+        .collect();
+    let unknown_suite =
+        matching_ids(database, "cards", doc! { "suite": { "$nin": suites } }).await?;
+    if !unknown_suite.is_empty() {
+        problems.push(Problem::new(
+            "unknown_suite",
+            "cards",
+            &unknown_suite,
+            false,
+        ));
+    }
+
+    let empty_text = matching_ids(
+        database,
+        "cards",
+        doc! { "$or": [{ "text": { "$exists": false } }, { "text": { "$regex": "^\\s*$" } }] },
+    )
+    .await?;
+    if !empty_text.is_empty() {
+        problems.push(Problem::new("empty_text", "cards", &empty_text, false));
+    }
+
+    for collection in ["sets", "cards"] {
+        let duplicates = duplicate_ids(database, collection).await?;
+        if !duplicates.is_empty() {
+            problems.push(Problem::new(
+                "duplicate_uuid",
+                collection,
+                &duplicates,
+                false,
+            ));
+        }
+    }
+
+    let sets = database
+        .collection::<Document>("sets")
+        .distinct("uuid", None, None)
+        .await?;
+    let orphaned = matching_ids(database, "cards", doc! { "set_uuid": { "$nin": sets } }).await?;
+    if !orphaned.is_empty() {
+        problems.push(Problem::new("missing_set", "cards", &orphaned, false));
+    }
+
+    let editions = database
+        .collection::<Document>("editions")
+        .distinct("uuid", None, None)
+        .await?;
+    let referenced = database
+        .collection::<Document>("cards")
+        .distinct("editions", None, None)
+        .await?;
+    let dangling: Vec<Bson> = referenced
+        .into_iter()
+        .filter(|edition| !editions.contains(edition))
+        .collect();
+    if !dangling.is_empty() {
+        let mut problem = Problem::new("dangling_edition", "cards", &dangling, true);
+        if fix {
+            database
+                .collection::<Document>("cards")
+                .update_many(
+                    doc! { "editions": { "$in": &dangling } },
+                    doc! { "$pull": { "editions": { "$in": &dangling } } },
+                    None,
+                )
+                .await?;
+            problem.fixed = true;
+        }
+        problems.push(problem);
+    }
+
+    Ok(CheckReport {
+        ok: problems.iter().all(|p| p.fixed),
+        problems,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckQuery {
+    #[serde(default)]
+    fix: bool,
+}
+
+async fn check_database(
+    _: Admin,
+    user: Option<User>,
+    TargetDatabase(database): TargetDatabase,
+    query: web::Query<CheckQuery>,
+) -> Result<impl Responder, ActixError> {
+    let report = check_library(&database, query.fix)
+        .await
+        .map_err(AppError::from)?;
+    let fixed: Vec<Document> = report
+        .problems
+        .iter()
+        .filter(|p| p.fixed)
+        .map(|p| doc! { "check": p.check, "collection": p.collection, "count": p.count as i64 })
+        .collect();
+    if !fixed.is_empty() {
+        audit(
+            &database,
+            user.as_ref(),
+            "fix",
+            "database",
+            None,
+            doc! {},
+            doc! { "database": database.name(), "fixed": fixed },
+        )
+        .await;
+    }
+    Ok(web::Json(report))
+}
+
+/// Loosest similarity `GET /api/admin/duplicates` accepts; below it most
+/// prompts share enough filler words to look alike.
+const MIN_DUPLICATE_SIMILARITY: f64 = 0.5;
+const DEFAULT_DUPLICATE_SIMILARITY: f64 = 0.85;
+
+#[derive(Debug, Deserialize)]
+struct DuplicateQuery {
+    /// Trigram similarity, 0.5 to 1, two cards need to be clustered.
+    threshold: Option<f64>,
+    suite: Option<Suite>,
+}
+
+#[derive(Debug, Serialize)]
+struct DuplicateMember {
+    uuid: Uuid,
+    text: String,
+    set_uuid: Uuid,
+    set: Option<String>,
+    editions: Vec<Edition>,
+}
+
+#[derive(Debug, Serialize)]
+struct DuplicateCluster {
+    suite: Suite,
+    cards: Vec<DuplicateMember>,
+}
+
+/// Card text stripped of case, punctuation and spacing, so reprints that
+/// only differ there compare equal.
+fn duplicate_key(text: &str) -> String {
+    let letters: String = text
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    normalize_text(&letters)
+}
+
+fn trigrams(key: &str) -> HashSet<String> {
+    let padded: Vec<char> = format!("  {key} ").chars().collect();
+    padded
+        .windows(3)
+        .map(|window| window.iter().collect())
+        .collect()
+}
+
+/// Groups cards of the same suite whose trigram sets have a Jaccard
+/// similarity of at least `threshold`, transitively. Only pairs sharing one
+/// of their rarest trigrams are compared (prefix filtering), which finds the
+/// same pairs as comparing all of them.
+fn duplicate_clusters(cards: &[(Suite, &str)], threshold: f64) -> Vec<Vec<usize>> {
+    let grams: Vec<Vec<String>> = cards
+        .iter()
+        .map(|(_, text)| trigrams(&duplicate_key(text)).into_iter().collect())
+        .collect();
+    let mut frequency: HashMap<&str, usize> = HashMap::new();
+    for gram in grams.iter().flatten() {
+        *frequency.entry(gram).or_default() += 1;
+    }
+    let mut parent: Vec<usize> = (0..cards.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut postings: HashMap<(Suite, &str), Vec<usize>> = HashMap::new();
+    for (i, (suite, _)) in cards.iter().enumerate() {
+        let mut own: Vec<&str> = grams[i].iter().map(String::as_str).collect();
+        own.sort_by_key(|gram| (frequency[gram], *gram));
+        let prefix = own.len() - (threshold * own.len() as f64).ceil() as usize + 1;
+        let mut candidates = HashSet::new();
+        for gram in own.iter().take(prefix) {
+            let posting = postings.entry((*suite, gram)).or_default();
+            candidates.extend(posting.iter().copied());
+            posting.push(i);
+        }
+        let mine: HashSet<&str> = own.iter().copied().collect();
+        for j in candidates {
+            let shared = grams[j]
+                .iter()
+                .filter(|gram| mine.contains(gram.as_str()))
+                .count();
+            let union = mine.len() + grams[j].len() - shared;
+            if union > 0 && shared as f64 / union as f64 >= threshold {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+    let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..cards.len() {
+        clusters.entry(root(&mut parent, i)).or_default().push(i);
+    }
+    let mut clusters: Vec<Vec<usize>> = clusters
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .collect();
+    clusters.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+    clusters
+}
+
+/// Clusters of near-identical cards across the library, largest first,
+/// with the sets and editions each copy belongs to, as candidates for
+/// merging reprints.
+async fn find_duplicates(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+    query: web::Query<DuplicateQuery>,
+) -> Result<impl Responder, ActixError> {
+    let threshold = query.threshold.unwrap_or(DEFAULT_DUPLICATE_SIMILARITY);
+    if !(MIN_DUPLICATE_SIMILARITY..=1.0).contains(&threshold) {
+        return Err(ErrorBadRequest(format!(
+            "threshold must be between {MIN_DUPLICATE_SIMILARITY} and 1"
+        )));
+    }
+    let mut filter = doc! { "deleted_at": null };
+    if let Some(suite) = query.suite {
+        filter.insert("suite", bson_value(&suite)?);
+    }
+    let options = FindOptions::builder().sort(doc! { "text": 1 }).build();
+    let cards: Vec<Card> = database
+        .collection::<Card>("cards")
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let texts: Vec<(Suite, &str)> = cards
+        .iter()
+        .map(|card| (card.suite, card.text.as_str()))
+        .collect();
+    let clusters = duplicate_clusters(&texts, threshold);
+    let sets: HashMap<Uuid, String> = database
+        .collection::<Set>("sets")
+        .find(None, None)
+        .await
+        .map_err(AppError::from)?
+        .map_ok(|set| (set.uuid, set.name))
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let editions: HashMap<Uuid, Edition> = database
+        .collection::<Edition>("editions")
+        .find(None, None)
+        .await
+        .map_err(AppError::from)?
+        .map_ok(|edition| (edition.uuid, edition))
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let report: Vec<DuplicateCluster> = clusters
+        .into_iter()
+        .map(|members| DuplicateCluster {
+            suite: cards[members[0]].suite,
+            cards: members
+                .into_iter()
+                .map(|i| {
+                    let card = &cards[i];
+                    DuplicateMember {
+                        uuid: card.uuid,
+                        text: card.text.clone(),
+                        set_uuid: card.set_uuid,
+                        set: sets.get(&card.set_uuid).cloned(),
+                        editions: card
+                            .editions
+                            .iter()
+                            .filter_map(|id| editions.get(id).cloned())
+                            .collect(),
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(web::Json(report))
+}
+
+#[derive(Debug, Serialize)]
+pub struct BootstrapReport {
+    pub source: String,
+    /// Set when the library already had content and nothing was imported.
+    pub skipped: bool,
+    pub sets: usize,
+    pub cards: usize,
+}
+
+/// Imports the starter deck named by `Config::bootstrap_source`, a URL or a
+/// local sheet or JSON deck such as JSON Against Humanity's
+/// `cah-all-compact.json`, so a fresh install has content in one step. A library that
+/// already holds sets is left alone unless `force` is given. Ids are derived
+/// from the content, so every instance bootstrapped from the same sheet
+/// agrees on them.
+pub async fn bootstrap(
+    store: &Store,
+    config: &Config,
+    force: bool,
+) -> Result<BootstrapReport, Box<dyn Error>> {
+    let database = store.database();
+    let source = config.bootstrap_source.clone();
+    let mut report = BootstrapReport {
+        source: source.clone(),
+        skipped: false,
+        sets: 0,
+        cards: 0,
+    };
+    let stored = database
+        .collection::<Set>("sets")
+        .estimated_document_count(None)
+        .await?;
+    if stored > 0 && !force {
+        report.skipped = true;
+        return Ok(report);
+    }
+
+    fs::create_dir_all(&config.temp_dir)?;
+    let path = if source.starts_with("http://") || source.starts_with("https://") {
+        println!("downloading {source}");
+        download(&source, config).await?
+    } else {
+        let path = format!("{}/bootstrap-{}.csv", config.temp_dir, Uuid::new_v4());
+        fs::copy(&source, &path)?;
+        path
+    };
+    let options = ImportOptions {
+        id_mode: IdMode::Deterministic,
+        ..ImportOptions::default()
+    };
+    let source = match source.starts_with("http://") || source.starts_with("https://") {
+        true => ImportSource {
+            url: Some(source),
+            ..ImportSource::default()
+        },
+        false => ImportSource {
+            files: vec![source],
+            ..ImportSource::default()
+        },
+    };
+    let summary = ImportJob::create(
+        database,
+        &config.import_dir,
+        Path::new(&path),
+        options,
+        source,
+    )
+    .await?
+    .run(
+        store,
+        ParseLimits::from_config(config),
+        CardRules::from_config(config),
+    )
+    .await?;
+    report.sets = summary.sets;
+    report.cards = summary.cards;
+    Ok(report)
+}
+
+/// Copies a set tried out in another library, named by `X-Database`, into
+/// the one this deployment serves. Cards already there keep their ids.
+async fn promote_set(
+    _: Admin,
+    user: Option<User>,
+    TargetDatabase(source): TargetDatabase,
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let target = store.database();
+    if source.name() == target.name() {
+        return Err(ErrorBadRequest(
+            "name the library to promote from in X-Database",
+        ));
+    }
+    let mut set = find_set(&source.collection("sets"), &path).await?;
+    set.cards = set_cards(&source.collection("cards"), &set.uuid).await?;
+    set.editions = source
+        .collection::<Edition>("editions")
+        .find(doc! { "set_uuid": uuid_bson(&set.uuid) }, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let changes = store.add_set(&set).await?;
+    let mut summary = ImportSummary::default();
+    summary.record(set.cards.len(), changes);
+    audit(
+        target,
+        user.as_ref(),
+        "promote",
+        "set",
+        Some(set.uuid),
+        doc! {},
+        doc! { "from": source.name(), "summary": bson_value(&summary)? },
+    )
+    .await;
+    Ok(web::Json(summary))
+}
+
+#[derive(Debug, Deserialize)]
+struct BootstrapQuery {
+    #[serde(default)]
+    force: bool,
+}
+
+async fn bootstrap_library(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+    query: web::Query<BootstrapQuery>,
+    config: web::Data<Config>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let report = bootstrap(&store.with_database(database), &config, query.force)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+    Ok(web::Json(report))
+}
+
+async fn index() -> HttpResponse {
+    let html = r#"<html>
+        <head><title>Upload Test</title></head>
+        <body>
+            <form target="/" method="post" enctype="multipart/form-data">
+                <input type="file" multiple name="file"/>
+                <button type="submit">Submit</button>
+            </form>
+        </body>
+    </html>"#;
+
+    HttpResponse::Ok().body(html)
+}
+
+/// Runs the HTTP server along with the background jobs it owns.
+pub async fn serve() -> std::io::Result<()> {
+    let config = config::get();
+    let upload_limit = web::Data::new(UploadLimit::new(config.upload_concurrency));
+    let parse_limits = web::Data::new(ParseLimits::from_config(config));
+    let rules = web::Data::new(CardRules::from_config(config));
+    let shedder = web::Data::new(LoadShedder::new(config.max_in_flight));
+    let store = web::Data::new(
+        Store::connect(config)
+            .await
+            .map_err(std::io::Error::other)?,
+    );
+    // Handlers expect every library in its current shape, so nothing is
+    // served until they are.
+    for name in allowed_databases(config) {
+        let database = store.client().database(&name);
+        migrate(&database).await.map_err(std::io::Error::other)?;
+        actix_web::rt::spawn(async move {
+            if let Err(e) = ensure_indexes(&database).await {
+                eprintln!("could not create indexes in {name}: {e}");
+            }
+        });
+    }
+
+    schedule("leaderboards", LEADERBOARD_REFRESH, refresh_leaderboards);
+    schedule("listing", LISTING_INTERVAL, list_visible);
+    schedule("publisher", PUBLISH_INTERVAL, publish_due_sets);
+    schedule("retention", RETENTION_INTERVAL, enforce_retention);
+    if config.sync_url.is_some() {
+        // Intervals cannot be zero, and a sheet never needs more than a
+        // sync a minute.
+        let period = Duration::from_secs(config.sync_interval_secs.max(60));
+        schedule("sync", period, sync_source);
+    }
+    schedule("resume-imports", IMPORT_RESUME_INTERVAL, resume_import_jobs);
+
+    HttpServer::new(move || {
+        let admission = shedder.clone().into_inner();
+        App::new()
+            .wrap_fn(move |req, srv| {
+                let admitted = admission.admit();
+                let call = admitted.as_ref().map(|_| srv.call(req));
+                async move {
+                    let response = call.ok_or_else(overloaded)?.await;
+                    drop(admitted);
+                    response
+                }
+            })
+            .app_data(TempFileConfig::default().directory(&config.temp_dir))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(upload_limit.clone())
+            .app_data(parse_limits.clone())
+            .app_data(rules.clone())
+            .app_data(shedder.clone())
+            .app_data(store.clone())
+            .service(
+                web::resource("/")
+                    .route(web::get().to(index))
+                    .route(web::post().to(upload_csv)),
+            )
+            .service(web::resource("/sets").route(web::get().to(list_sets)))
+            .service(
+                web::resource("/api/imports")
+                    .route(web::get().to(list_imports))
+                    .route(web::post().to(start_imports)),
+            )
+            .service(web::resource("/api/imports/url").route(web::post().to(import_url)))
+            .service(web::resource("/api/imports/{uuid}").route(web::delete().to(cancel_import)))
+            .service(
+                web::resource("/api/imports/{uuid}/errors").route(web::get().to(import_errors)),
+            )
+            .service(
+                web::resource("/api/imports/{uuid}/events").route(web::get().to(import_events)),
+            )
+            .service(web::resource("/api/sets").route(web::get().to(list_set_pages::<Store>)))
+            .service(
+                web::resource("/api/sets/{uuid}")
+                    .route(web::get().to(get_set::<Store>))
+                    .route(web::delete().to(delete_set)),
+            )
+            .service(web::resource("/api/sets/{uuid}/cards").route(web::post().to(create_card)))
+            .service(web::resource("/api/sets/{uuid}/restore").route(web::post().to(restore_set)))
+            .service(
+                web::resource("/api/trash")
+                    .route(web::get().to(list_trash))
+                    .route(web::delete().to(purge_trash)),
+            )
+            .service(
+                web::resource("/api/sets/{uuid}/stats").route(web::get().to(set_stats::<Store>)),
+            )
+            .service(web::resource("/api/cards/search").route(web::get().to(search_cards)))
+            .service(web::resource("/api/cards/random").route(web::get().to(random_cards)))
+            .service(
+                web::resource("/api/cards/{uuid}")
+                    .route(web::patch().to(update_card))
+                    .route(web::delete().to(delete_card)),
+            )
+            .service(web::resource("/api/cards/{uuid}/merge").route(web::post().to(merge_cards)))
+            .service(web::resource("/api/cards/{uuid}/approve").route(web::post().to(approve_card)))
+            .service(web::resource("/api/cards/{uuid}/restore").route(web::post().to(restore_card)))
+            .service(
+                web::resource("/api/cards/{uuid}/revisions")
+                    .route(web::get().to(list_card_revisions)),
+            )
+            .service(
+                web::resource("/api/cards/{uuid}/revisions/{revision}/revert")
+                    .route(web::post().to(revert_card)),
+            )
+            .service(web::resource("/featured").route(web::get().to(get_featured)))
+            .service(
+                web::resource("/featured/{kind}/{uuid}")
+                    .route(web::put().to(pin_featured))
+                    .route(web::delete().to(unpin_featured)),
+            )
+            .service(
+                web::resource("/collections")
+                    .route(web::get().to(list_collections))
+                    .route(web::post().to(create_collection)),
+            )
+            .service(
+                web::resource("/collections/{uuid}")
+                    .route(web::get().to(get_collection))
+                    .route(web::delete().to(delete_collection)),
+            )
+            .service(web::resource("/tags").route(web::get().to(list_tags)))
+            .service(web::resource("/card-types").route(web::get().to(list_card_types)))
+            .service(
+                web::resource("/card-types/{name}")
+                    .route(web::put().to(define_card_type))
+                    .route(web::delete().to(delete_card_type)),
+            )
+            .service(web::resource("/mapping-profiles").route(web::get().to(list_mapping_profiles)))
+            .service(
+                web::resource("/mapping-profiles/{name}")
+                    .route(web::put().to(define_mapping_profile))
+                    .route(web::delete().to(delete_mapping_profile)),
+            )
+            .service(
+                web::resource("/tags/{tag}")
+                    .route(web::put().to(curate_tag))
+                    .route(web::delete().to(uncurate_tag)),
+            )
+            .service(
+                web::resource("/sets/{uuid}/tags/{tag}")
+                    .route(web::put().to(tag_set))
+                    .route(web::delete().to(untag_set)),
+            )
+            .service(
+                web::resource("/cards/{uuid}/tags/{tag}")
+                    .route(web::put().to(tag_card))
+                    .route(web::delete().to(untag_card)),
+            )
+            .service(web::resource("/sets/recent").route(web::get().to(recent_sets)))
+            .service(
+                web::resource("/editions/{from}/compare/{to}")
+                    .route(web::get().to(compare_editions)),
+            )
+            .service(web::resource("/sets/{uuid}/versions").route(web::get().to(list_set_versions)))
+            .service(
+                web::resource("/sets/{uuid}/versions/{version}")
+                    .route(web::get().to(get_set_version)),
+            )
+            .service(
+                web::resource("/sets/{uuid}/versions/{from}/diff/{to}")
+                    .route(web::get().to(diff_set_versions)),
+            )
+            .service(
+                web::resource("/sets/{uuid}/workspaces").route(web::post().to(create_workspace)),
+            )
+            .service(web::resource("/workspaces/{uuid}").route(web::get().to(get_workspace)))
+            .service(
+                web::resource("/workspaces/{uuid}/changes").route(web::post().to(stage_change)),
+            )
+            .service(
+                web::resource("/workspaces/{uuid}/submit").route(web::post().to(submit_workspace)),
+            )
+            .service(
+                web::resource("/workspaces/{uuid}/approve")
+                    .route(web::post().to(approve_workspace)),
+            )
+            .service(
+                web::resource("/workspaces/{uuid}/reject").route(web::post().to(reject_workspace)),
+            )
+            .service(
+                web::resource("/sets/{uuid}/requires/{dependency}")
+                    .route(web::put().to(add_set_dependency))
+                    .route(web::delete().to(remove_set_dependency)),
+            )
+            .service(web::resource("/sets/{uuid}/append").route(web::post().to(append_fragment)))
+            .service(web::resource("/sets/{uuid}/delta").route(web::post().to(import_delta)))
+            .service(web::resource("/sets/{uuid}/status").route(web::put().to(update_set_status)))
+            .service(web::resource("/sets/trending").route(web::get().to(trending_sets)))
+            .service(
+                web::resource("/sets/{uuid}/favorite")
+                    .route(web::put().to(favorite_set))
+                    .route(web::delete().to(unfavorite_set)),
+            )
+            .service(web::resource("/card-of-the-day").route(web::get().to(card_of_the_day)))
+            .service(
+                web::resource("/card-of-the-day/history")
+                    .route(web::get().to(card_of_the_day_history)),
+            )
+            .service(web::resource("/combo").route(web::get().to(combo)))
+            .service(web::resource("/api/rounds/random").route(web::get().to(random_round)))
+            .service(web::resource("/combos").route(web::post().to(submit_combo)))
+            .service(web::resource("/combos/leaderboard").route(web::get().to(combo_leaderboard)))
+            .service(web::resource("/combos/{uuid}/votes").route(web::post().to(upvote_combo)))
+            .service(
+                web::resource("/presets")
+                    .route(web::get().to(list_presets))
+                    .route(web::post().to(create_preset)),
+            )
+            .service(
+                web::resource("/presets/{uuid}")
+                    .route(web::get().to(get_preset))
+                    .route(web::delete().to(delete_preset)),
+            )
+            .service(web::resource("/decks/generate").route(web::post().to(generate_deck)))
+            .service(web::resource("/api/decks").route(web::post().to(create_deck)))
+            .service(web::resource("/api/decks/{uuid}").route(web::get().to(get_deck)))
+            .service(web::resource("/api/decks/{uuid}/clone").route(web::post().to(clone_deck)))
+            .service(
+                web::resource("/sets/{uuid}/balance").route(web::get().to(set_balance::<Store>)),
+            )
+            .service(web::resource("/cards").route(web::get().to(list_cards)))
+            .service(web::resource("/api/cards").route(web::get().to(list_cards)))
+            .service(web::resource("/export/archive").route(web::post().to(export_archive)))
+            .service(
+                web::resource("/export/{collection}")
+                    .route(web::get().to(export_collection))
+                    .route(web::head().to(export_collection)),
+            )
+            .service(web::resource("/cards/lookup").route(web::post().to(lookup_cards)))
+            .service(web::resource("/cards/tags").route(web::post().to(bulk_tag_cards)))
+            .service(web::resource("/cards/ratings").route(web::post().to(bulk_rate_cards)))
+            .service(web::resource("/cards/recent").route(web::get().to(recent_cards)))
+            .service(web::resource("/cards/unpicked").route(web::get().to(unpicked_cards)))
+            .service(web::resource("/cards/{uuid}/rules").route(web::get().to(card_rules)))
+            .service(web::resource("/cards/{uuid}/stats").route(web::get().to(card_stats)))
+            .service(web::resource("/admin/check").route(web::post().to(check_database)))
+            .service(web::resource("/api/stats").route(web::get().to(corpus_stats)))
+            .service(web::resource("/api/admin/screened").route(web::get().to(list_screened_cards)))
+            .service(web::resource("/api/admin/duplicates").route(web::get().to(find_duplicates)))
+            .service(web::resource("/api/audit").route(web::get().to(list_audit)))
+            .service(web::resource("/admin/load").route(web::get().to(load_report)))
+            .service(web::resource("/admin/storage").route(web::get().to(storage_report)))
+            .service(web::resource("/admin/imports").route(web::get().to(list_import_jobs)))
+            .service(web::resource("/admin/syncs").route(web::get().to(list_syncs)))
+            .service(
+                web::resource("/admin/imports/{uuid}/resume")
+                    .route(web::post().to(resume_import_job)),
+            )
+            .service(web::resource("/admin/sets/{uuid}/promote").route(web::post().to(promote_set)))
+            .service(web::resource("/admin/bootstrap").route(web::post().to(bootstrap_library)))
+            .service(web::resource("/admin/analytics/{metric}").route(web::get().to(daily_metric)))
+            .service(web::resource("/analytics/plays").route(web::post().to(report_plays)))
+            .service(web::resource("/leaderboards").route(web::get().to(get_leaderboard)))
+            .service(web::resource("/cards/{uuid}/votes").route(web::put().to(vote_card)))
+            .service(
+                web::resource("/cards/{uuid}/translations")
+                    .route(web::get().to(get_translations))
+                    .route(web::post().to(link_translation))
+                    .route(web::delete().to(unlink_translation)),
+            )
+    })
+    .bind((config.bind_addr.as_str(), config.port))?
+    .workers(config.workers)
+    .max_connections(config.max_connections)
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test as actix_test, App};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::repository::MemoryRepository;
+
+    fn card(set: &Set, suite: Suite, text: &str) -> Card {
+        Card::new(set.uuid, suite, text.to_string(), String::new())
+    }
+
+    /// A set of two prompts and a response, one prompt printed in its only
+    /// edition.
+    fn base_set() -> Set {
+        let mut set = Set::new("Base Game".to_string());
+        let edition = Edition::from_label(set.uuid, "US");
+        let mut printed = card(&set, Suite::Prompt, "Why can't I sleep at night? ____.");
+        printed.editions.push(edition.uuid);
+        set.cards = vec![
+            printed,
+            Card::new(
+                set.uuid,
+                Suite::Prompt,
+                "____ + ____ = ____.".to_string(),
+                "PICK 3".to_string(),
+            ),
+            card(&set, Suite::Response, "A windmill full of corpses."),
+        ];
+        set.editions.push(edition);
+        set
+    }
+
+    async fn library(sets: Vec<Set>) -> web::Data<MemoryRepository> {
+        let repository = web::Data::new(MemoryRepository::default());
+        for set in &sets {
+            repository.add_set(set).await.unwrap();
+        }
+        repository
+    }
+
+    macro_rules! service {
+        ($repository:expr) => {
+            actix_test::init_service(
+                App::new()
+                    .app_data($repository.clone())
+                    .route(
+                        "/api/sets",
+                        web::get().to(list_set_pages::<MemoryRepository>),
+                    )
+                    .route(
+                        "/api/sets/{uuid}",
+                        web::get().to(get_set::<MemoryRepository>),
+                    )
+                    .route(
+                        "/api/sets/{uuid}/stats",
+                        web::get().to(set_stats::<MemoryRepository>),
+                    ),
+            )
+            .await
+        };
+    }
+
+    #[test]
+    fn archived_csv_reads_back_as_the_same_set() {
+        let mut set = base_set();
+        set.name = "PAX".to_string();
+        set.editions.push(Edition::from_label(set.uuid, "UK v2"));
+        let uk = set.editions[1].uuid;
+        set.cards[2].editions.push(uk);
+        let mut csv = Vec::new();
+        write_set_csv(&set, &mut csv).unwrap();
+
+        let mut reader =
+            SetReader::new(csv.as_slice(), ParseLimits::default(), CardTypes::default());
+        let read = reader.next().unwrap().unwrap();
+        assert!(reader.next().is_none());
+        assert_eq!(read.name, "PAX");
+        let labels: Vec<String> = read.editions.iter().map(Edition::label).collect();
+        assert_eq!(labels, ["US", "UK v2"]);
+        assert_eq!(read.cards.len(), set.cards.len());
+        for (written, read_back) in set.cards.iter().zip(&read.cards) {
+            assert_eq!(read_back.suite, written.suite);
+            assert_eq!(read_back.text, written.text);
+            assert_eq!(read_back.special, written.special);
+            let printed = |card: &Card, editions: &[Edition]| -> Vec<String> {
+                editions
+                    .iter()
+                    .filter(|edition| card.editions.contains(&edition.uuid))
+                    .map(Edition::label)
+                    .collect()
+            };
+            assert_eq!(
+                printed(read_back, &read.editions),
+                printed(written, &set.editions)
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn set_pages_are_ordered_by_name_with_card_counts() {
+        let extra = Set::new("Absurd Box".to_string());
+        let repository = library(vec![base_set(), extra]).await;
+        let app = service!(repository);
+
+        let request = actix_test::TestRequest::get().uri("/api/sets?limit=1&page=2");
+        let page: Value = actix_test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["page"], 2);
+        assert_eq!(page["sets"].as_array().unwrap().len(), 1);
+        assert_eq!(page["sets"][0]["name"], "Base Game");
+        assert_eq!(page["sets"][0]["card_count"], 3);
+    }
+
+    #[actix_web::test]
+    async fn a_set_comes_with_its_cards_and_editions() {
+        let mut set = base_set();
+        set.cards[2].screened.push("corpses".to_string());
+        let repository = library(vec![set.clone()]).await;
+        let app = service!(repository);
+
+        let request = actix_test::TestRequest::get().uri(&format!("/api/sets/{}", set.uuid));
+        let found: Value = actix_test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(found["name"], "Base Game");
+        assert_eq!(found["editions"].as_array().unwrap().len(), 1);
+        // The screened response waits for review.
+        let cards = found["cards"].as_array().unwrap();
+        assert_eq!(cards.len(), 2);
+        assert!(cards.iter().all(|c| c["suite"] == "prompt"));
+
+        let request = actix_test::TestRequest::get().uri(&format!("/api/sets/{}", Uuid::new_v4()));
+        let response = actix_test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn a_reimport_trashes_the_cards_it_dropped() {
+        let mut set = base_set();
+        let repository = library(vec![set.clone()]).await;
+        set.cards.truncate(1);
+        let changes = repository.add_set(&set).await.unwrap();
+        assert_eq!((changes.unchanged, changes.removed), (1, 2));
+        let app = service!(repository);
+
+        let request = actix_test::TestRequest::get().uri(&format!("/api/sets/{}", set.uuid));
+        let found: Value = actix_test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(found["cards"].as_array().unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn set_stats_count_suites_blanks_and_editions() {
+        let set = base_set();
+        let repository = library(vec![set.clone()]).await;
+        let app = service!(repository);
+
+        let request = actix_test::TestRequest::get().uri(&format!("/api/sets/{}/stats", set.uuid));
+        let stats: Value = actix_test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(stats["prompts"], 2);
+        assert_eq!(stats["responses"], 1);
+        assert_eq!(stats["pick_3"], 1);
+        assert_eq!(stats["blanks"]["1"], 1);
+        assert_eq!(stats["blanks"]["3"], 1);
+        assert_eq!(stats["editions"][0]["prompts"], 1);
+        assert_eq!(stats["editions"][0]["responses"], 0);
+    }
+
+    #[test]
+    fn byte_ranges_resolve_against_the_body_length() {
+        let range = |header: &str, len| ByteRange::parse(Some(header), len);
+        assert_eq!(ByteRange::parse(None, 100), ByteRange::Full);
+        assert_eq!(range("bytes=0-0", 100), ByteRange::Partial(0, 0));
+        assert_eq!(range("bytes=10-19", 100), ByteRange::Partial(10, 19));
+        assert_eq!(range("bytes=50-", 100), ByteRange::Partial(50, 99));
+        // An end past the body is cut to its last byte.
+        assert_eq!(range("bytes=50-500", 100), ByteRange::Partial(50, 99));
+        assert_eq!(range("bytes=100-200", 100), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=100-", 100), ByteRange::Unsatisfiable);
+        // Suffix ranges count from the end, the whole body at most.
+        assert_eq!(range("bytes=-10", 100), ByteRange::Partial(90, 99));
+        assert_eq!(range("bytes=-500", 100), ByteRange::Partial(0, 99));
+        assert_eq!(range("bytes=-0", 100), ByteRange::Unsatisfiable);
+        // Several ranges, other units and malformed ones get the full body.
+        assert_eq!(range("bytes=0-1,5-6", 100), ByteRange::Full);
+        assert_eq!(range("items=0-1", 100), ByteRange::Full);
+        assert_eq!(range("bytes=9-2", 100), ByteRange::Full);
+        // An empty body has no byte to serve.
+        assert_eq!(ByteRange::parse(None, 0), ByteRange::Full);
+        assert_eq!(range("bytes=0-0", 0), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-5", 0), ByteRange::Unsatisfiable);
+    }
+
+    /// `duplicate_clusters` the slow way, comparing every pair of cards.
+    fn duplicate_clusters_by_full_scan(cards: &[(Suite, &str)], threshold: f64) -> Vec<Vec<usize>> {
+        let grams: Vec<HashSet<String>> = cards
+            .iter()
+            .map(|(_, text)| trigrams(&duplicate_key(text)))
+            .collect();
+        let mut cluster: Vec<usize> = (0..cards.len()).collect();
+        for i in 0..cards.len() {
+            for j in 0..i {
+                let shared = grams[i].intersection(&grams[j]).count();
+                let union = grams[i].union(&grams[j]).count();
+                let similar = union > 0 && shared as f64 / union as f64 >= threshold;
+                if cards[i].0 == cards[j].0 && similar {
+                    let (from, to) = (cluster[i], cluster[j]);
+                    for label in cluster.iter_mut().filter(|label| **label == from) {
+                        *label = to;
+                    }
+                }
+            }
+        }
+        let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, label) in cluster.into_iter().enumerate() {
+            clusters.entry(label).or_default().push(i);
+        }
+        let mut clusters: Vec<Vec<usize>> = clusters
+            .into_values()
+            .filter(|members| members.len() > 1)
+            .collect();
+        clusters.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+        clusters
+    }
+
+    #[test]
+    fn prefix_filtering_finds_the_same_duplicates_as_a_full_scan() {
+        let texts = [
+            "A windmill full of corpses.",
+            "A windmill full of corpses!",
+            "a WINDMILL full of  corpses",
+            "A windmill full of dead bodies.",
+            "Why can't I sleep at night? ____.",
+            "Why can't I sleep at night?",
+            "Why can I not sleep at night? ____.",
+            "What's that smell?",
+            "What is that smell?",
+            "Being on fire.",
+            "Being on fire and loving it.",
+            "Racism.",
+            "Old-people smell.",
+            "Old people smell.",
+            "",
+            "____ + ____ = ____.",
+        ];
+        let cards: Vec<(Suite, &str)> = texts
+            .iter()
+            .flat_map(|&text| [(Suite::Response, text), (Suite::Prompt, text)])
+            .collect();
+        for threshold in [0.3, 0.5, 0.6, 0.75, 0.9, 1.0] {
+            assert_eq!(
+                duplicate_clusters(&cards, threshold),
+                duplicate_clusters_by_full_scan(&cards, threshold),
+                "threshold {threshold}"
+            );
+        }
+        // The same text under another suite is never a duplicate.
+        let clusters = duplicate_clusters(&cards, 0.75);
+        assert!(!clusters.is_empty());
+        for members in &clusters {
+            assert!(members.iter().all(|&i| cards[i].0 == cards[members[0]].0));
+        }
+    }
+
+    #[test]
+    fn edition_diffs_pair_reprints_by_id_group_or_text() {
+        let set = base_set();
+        let kept = card(&set, Suite::Prompt, "Why can't I sleep at night? ____.");
+        let mut grouped = card(&set, Suite::Response, "Old-people smell.");
+        grouped.card_group = Some(Uuid::new_v4());
+        let reprinted = card(&set, Suite::Response, "A windmill full of corpses.");
+        let dropped = card(&set, Suite::Response, "Racism.");
+        let other_suite = card(&set, Suite::Prompt, "What's that smell?");
+
+        let mut reworded = card(&set, Suite::Response, "Old people smell.");
+        reworded.card_group = grouped.card_group;
+        // The same text under another suite is a different card.
+        let resuited = card(&set, Suite::Response, "What's that smell?");
+        let added = card(&set, Suite::Response, "Being on fire.");
+        let after = vec![
+            kept.clone(),
+            reworded.clone(),
+            // A new id for the same text is not a change.
+            card(&set, Suite::Response, "A windmill full of corpses."),
+            resuited.clone(),
+            added.clone(),
+        ];
+        let before = vec![
+            kept,
+            grouped.clone(),
+            reprinted,
+            dropped.clone(),
+            other_suite.clone(),
+        ];
+
+        let diff = CardDiff::between_editions(before, after);
+        let uuids =
+            |cards: &[CardSnapshot]| -> Vec<Uuid> { cards.iter().map(|c| c.uuid).collect() };
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].before.uuid, grouped.uuid);
+        assert_eq!(diff.changed[0].after.uuid, reworded.uuid);
+        assert_eq!(uuids(&diff.added), [resuited.uuid, added.uuid]);
+        assert_eq!(uuids(&diff.removed), [dropped.uuid, other_suite.uuid]);
+    }
+}
@@ -0,0 +1,602 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PlayEvent {
+    Dealt,
+    Submitted,
+    Won,
+}
+
+impl PlayEvent {
+    fn counter(self) -> &'static str {
+        match self {
+            PlayEvent::Dealt => "dealt",
+            PlayEvent::Submitted => "submitted",
+            PlayEvent::Won => "wins",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct CardPlay {
+    #[serde(default)]
+    game: Option<Uuid>,
+    #[serde(default)]
+    player: Option<String>,
+    card_uuid: Uuid,
+    event: PlayEvent,
+    at: DateTime,
+}
+
+/// Per-card counters maintained from the play log.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct CardStats {
+    card_uuid: Uuid,
+    #[serde(default)]
+    pub(super) dealt: i64,
+    #[serde(default)]
+    pub(super) submitted: i64,
+    #[serde(default)]
+    pub(super) wins: i64,
+    /// Share of submissions that won the round.
+    #[serde(skip_deserializing)]
+    win_rate: Option<f64>,
+}
+
+impl CardStats {
+    fn with_win_rate(mut self) -> Self {
+        self.win_rate = (self.submitted > 0).then(|| self.wins as f64 / self.submitted as f64);
+        self
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayEventEntry {
+    card: Uuid,
+    player: Option<String>,
+    event: PlayEvent,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct PlayReport {
+    game: Option<Uuid>,
+    /// Sets the game deals from, reported once when the game starts.
+    #[serde(default)]
+    sets: Vec<Uuid>,
+    #[serde(default)]
+    events: Vec<PlayEventEntry>,
+}
+
+/// Logs what happened to each card in a round and bumps its counters.
+async fn record_plays(
+    database: &Database,
+    game: Option<Uuid>,
+    events: &[PlayEventEntry],
+) -> Result<(), ActixError> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let now = DateTime::now();
+    let plays: Vec<CardPlay> = events
+        .iter()
+        .map(|e| CardPlay {
+            game,
+            player: e.player.clone(),
+            card_uuid: e.card,
+            event: e.event,
+            at: now,
+        })
+        .collect();
+    database
+        .collection::<CardPlay>("card_plays")
+        .insert_many(plays, None)
+        .await
+        .map_err(AppError::from)?;
+
+    // One `$inc` per card, however many of its events the round reported.
+    let mut counters: BTreeMap<Uuid, Document> = BTreeMap::new();
+    for event in events {
+        let counter = counters.entry(event.card).or_default();
+        let count = counter.get_i64(event.event.counter()).unwrap_or(0);
+        counter.insert(event.event.counter(), count + 1);
+    }
+    let stats: Collection<CardStats> = database.collection("card_stats");
+    let upsert = UpdateOptions::builder().upsert(true).build();
+    for (card, counter) in counters {
+        let filter = doc! { "card_uuid": uuid_bson(&card) };
+        let update = doc! { "$inc": counter };
+        // A card's first plays, reported by two rounds at once, race to
+        // create its counters; the unique index lets one in and the other
+        // then increments it.
+        match stats
+            .update_one(filter.clone(), update.clone(), upsert.clone())
+            .await
+        {
+            Err(e) if is_duplicate_key(&e) => {
+                stats.update_one(filter, update, upsert.clone()).await
+            }
+            result => result,
+        }
+        .map_err(AppError::from)?;
+    }
+    Ok(())
+}
+
+/// Where the game server posts each round's deals, submissions and wins,
+/// and the sets of a game when it starts. Players never call this
+/// directly, so it takes the server's token rather than a user.
+pub(super) async fn report_plays(
+    _: GameServer,
+    body: web::Json<PlayReport>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    for set in &body.sets {
+        record_set_activity(database, *set, SetActivityKind::Game).await?;
+    }
+    record_plays(database, body.game, &body.events).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub(super) async fn card_stats(
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let card = find_card(&database.collection("cards"), &path).await?;
+    let stats = database
+        .collection::<CardStats>("card_stats")
+        .find_one(doc! { "card_uuid": uuid_bson(&card.uuid) }, None)
+        .await
+        .map_err(AppError::from)?
+        .unwrap_or(CardStats {
+            card_uuid: card.uuid,
+            dealt: 0,
+            submitted: 0,
+            wins: 0,
+            win_rate: None,
+        });
+    Ok(web::Json(stats.with_win_rate()))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct UnpickedQuery {
+    min_dealt: Option<i64>,
+    limit: Option<i64>,
+}
+
+/// Cards dealt at least `min_dealt` times that no player ever submitted.
+pub(super) async fn unpicked_cards(
+    query: web::Query<UnpickedQuery>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let filter = doc! {
+        "dealt": { "$gte": query.min_dealt.unwrap_or(10) },
+        "submitted": { "$in": [0, Bson::Null] },
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "dealt": -1 })
+        .limit(query.limit.unwrap_or(50).clamp(1, 500))
+        .build();
+    let stats: Vec<CardStats> = database
+        .collection::<CardStats>("card_stats")
+        .find(filter, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(stats))
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Metric {
+    /// Sets created by uploads, each column block of a sheet counting once.
+    Imports,
+    Games,
+    ActivePlayers,
+    CardsAdded,
+}
+
+impl Metric {
+    /// Collection, timestamp field and, for metrics counting distinct
+    /// values per day, the field holding the value.
+    fn source(self) -> (&'static str, &'static str, Option<&'static str>) {
+        match self {
+            Metric::Imports => ("sets", "created_at", None),
+            Metric::Games => ("card_plays", "at", Some("game")),
+            Metric::ActivePlayers => ("card_plays", "at", Some("player")),
+            Metric::CardsAdded => ("cards", "created_at", None),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DailyCount {
+    /// UTC day formatted as `YYYY-MM-DD`.
+    date: String,
+    count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct AnalyticsQuery {
+    days: Option<i64>,
+}
+
+pub(super) async fn daily_metric(
+    _: Admin,
+    path: web::Path<Metric>,
+    query: web::Query<AnalyticsQuery>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let (collection, timestamp, distinct) = path.source();
+    let days = query.days.unwrap_or(30).clamp(1, 366);
+    let since = DateTime::from_millis(DateTime::now().timestamp_millis() - days * DAY_MILLIS);
+    let day = doc! { "$dateToString": { "format": "%Y-%m-%d", "date": format!("${timestamp}") } };
+
+    let mut pipeline = vec![doc! { "$match": { timestamp: { "$gte": since } } }];
+    match distinct {
+        Some(field) => pipeline.extend([
+            doc! { "$match": { field: { "$ne": Bson::Null } } },
+            doc! { "$group": { "_id": { "day": day, "value": format!("${field}") } } },
+            doc! { "$group": { "_id": "$_id.day", "count": { "$sum": 1 } } },
+        ]),
+        None => pipeline.push(doc! { "$group": { "_id": day, "count": { "$sum": 1 } } }),
+    }
+    pipeline.push(doc! { "$sort": { "_id": 1 } });
+
+    let counts: Vec<DailyCount> = group_counts(database, collection, pipeline)
+        .await?
+        .into_iter()
+        .map(|c| DailyCount {
+            date: c.key,
+            count: c.count,
+        })
+        .collect();
+    Ok(web::Json(counts))
+}
+
+const TRENDING_WINDOW_DAYS: i64 = 14;
+/// Days after which an activity counts half as much towards trending.
+const TRENDING_HALF_LIFE_DAYS: f64 = 3.0;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum SetActivityKind {
+    Favorite,
+    Deck,
+    Game,
+}
+
+impl SetActivityKind {
+    fn weight(self) -> f64 {
+        match self {
+            SetActivityKind::Favorite => 3.0,
+            SetActivityKind::Deck => 2.0,
+            SetActivityKind::Game => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetActivity {
+    set_uuid: Uuid,
+    kind: SetActivityKind,
+    weight: f64,
+    at: DateTime,
+}
+
+pub(super) async fn record_set_activity(
+    database: &Database,
+    set_uuid: Uuid,
+    kind: SetActivityKind,
+) -> Result<(), ActixError> {
+    let activity = SetActivity {
+        set_uuid,
+        kind,
+        weight: kind.weight(),
+        at: DateTime::now(),
+    };
+    database
+        .collection::<SetActivity>("set_activity")
+        .insert_one(activity, None)
+        .await
+        .map_err(AppError::from)?;
+    Ok(())
+}
+
+pub(super) async fn favorite_set(
+    path: web::Path<Uuid>,
+    user: User,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let set = find_set(&database.collection("sets"), &path).await?;
+    let favorites = database.collection::<Document>("set_favorites");
+    let filter = doc! { "set_uuid": uuid_bson(&set.uuid), "user": &user.0 };
+    let update = doc! { "$setOnInsert": { "at": DateTime::now() } };
+    let upsert = UpdateOptions::builder().upsert(true).build();
+    // Of two concurrent favorites the unique index lets one in; the other,
+    // tried again, finds it there and records no second activity.
+    let result = match favorites
+        .update_one(filter.clone(), update.clone(), upsert.clone())
+        .await
+    {
+        Err(e) if is_duplicate_key(&e) => favorites.update_one(filter, update, upsert).await,
+        result => result,
+    }
+    .map_err(AppError::from)?;
+    if result.upserted_id.is_some() {
+        record_set_activity(database, set.uuid, SetActivityKind::Favorite).await?;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub(super) async fn unfavorite_set(
+    path: web::Path<Uuid>,
+    user: User,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    database
+        .collection::<Document>("set_favorites")
+        .delete_one(doc! { "set_uuid": uuid_bson(&path), "user": &user.0 }, None)
+        .await
+        .map_err(AppError::from)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TrendingSet {
+    set: Set,
+    score: f64,
+}
+
+/// Ranks sets by recent favorites, deck builds and game selections, each
+/// weighted by kind and decayed by age.
+pub(super) async fn trending_sets(
+    query: web::Query<LimitQuery>,
+    safe_mode: SafeMode,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let since = DateTime::from_millis(
+        DateTime::now().timestamp_millis() - TRENDING_WINDOW_DAYS * DAY_MILLIS,
+    );
+    let age_days = doc! { "$divide": [{ "$subtract": ["$$NOW", "$at"] }, DAY_MILLIS] };
+    let decay = doc! { "$pow": [0.5, { "$divide": [age_days, TRENDING_HALF_LIFE_DAYS] }] };
+    let visible: Document = visible_sets(safe_mode.0)
+        .into_iter()
+        .map(|(field, condition)| (format!("set.{field}"), condition))
+        .collect();
+    let pipeline = [
+        doc! { "$match": { "at": { "$gte": since } } },
+        doc! { "$group": {
+            "_id": "$set_uuid",
+            "score": { "$sum": { "$multiply": ["$weight", decay] } },
+        } },
+        doc! { "$sort": { "score": -1 } },
+        doc! { "$lookup": {
+            "from": "sets",
+            "localField": "_id",
+            "foreignField": "uuid",
+            "as": "set",
+        } },
+        doc! { "$unwind": "$set" },
+        doc! { "$match": visible },
+        doc! { "$limit": query.limit.unwrap_or(20).clamp(1, 100) },
+        doc! { "$project": { "_id": 0, "set": 1, "score": 1 } },
+    ];
+    let documents: Vec<Document> = database
+        .collection::<SetActivity>("set_activity")
+        .aggregate(pipeline, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let trending = documents
+        .into_iter()
+        .map(|d| from_document::<TrendingSet>(d).map_err(ErrorInternalServerError))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(web::Json(trending))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CorpusQuery {
+    /// Days of import history to chart, counting today.
+    days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct EditionCount {
+    edition: Edition,
+    cards: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct DailyImports {
+    /// `YYYY-MM-DD`, in UTC.
+    day: String,
+    imports: u64,
+    cards: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct CorpusStats {
+    sets: u64,
+    cards: u64,
+    suites: BTreeMap<String, u64>,
+    editions: Vec<EditionCount>,
+    /// Cards printed in an edition of each country.
+    countries: BTreeMap<String, u64>,
+    imports: Vec<DailyImports>,
+    /// Clusters `GET /api/admin/duplicates` finds at its default threshold,
+    /// and the cards in them.
+    duplicate_clusters: usize,
+    duplicate_cards: usize,
+}
+
+/// A count from an aggregation's `$sum`, which Mongo types by its size.
+fn aggregated_count(document: &Document, key: &str) -> u64 {
+    match document.get(key) {
+        Some(Bson::Int32(n)) => *n as u64,
+        Some(Bson::Int64(n)) => *n as u64,
+        _ => 0,
+    }
+}
+
+async fn aggregate_all(
+    database: &Database,
+    collection: &str,
+    pipeline: Vec<Document>,
+) -> Result<Vec<Document>, ActixError> {
+    database
+        .collection::<Document>(collection)
+        .aggregate(pipeline, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(|e| AppError::from(e).into())
+}
+
+/// The fields `duplicate_clusters` compares, all the dashboard loads of
+/// each card.
+#[derive(Debug, Deserialize)]
+struct CardText {
+    suite: Suite,
+    text: String,
+}
+
+/// Totals across the whole library, for the content team's dashboard.
+/// Trashed sets and cards are left out, as everywhere else.
+pub(super) async fn corpus_stats(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+    query: web::Query<CorpusQuery>,
+) -> Result<impl Responder, ActixError> {
+    let sets = database
+        .collection::<Document>("sets")
+        .count_documents(doc! { "deleted_at": null }, None)
+        .await
+        .map_err(AppError::from)?;
+
+    let live = doc! { "$match": { "deleted_at": null } };
+    let by_suite = aggregate_all(
+        &database,
+        "cards",
+        vec![
+            live.clone(),
+            doc! { "$group": { "_id": "$suite", "cards": { "$sum": 1 } } },
+        ],
+    )
+    .await?;
+    let suites: BTreeMap<String, u64> = by_suite
+        .iter()
+        .filter_map(|d| {
+            Some((
+                d.get_str("_id").ok()?.to_string(),
+                aggregated_count(d, "cards"),
+            ))
+        })
+        .collect();
+
+    let by_edition = aggregate_all(
+        &database,
+        "cards",
+        vec![
+            live,
+            doc! { "$unwind": "$editions" },
+            doc! { "$group": { "_id": "$editions", "cards": { "$sum": 1 } } },
+        ],
+    )
+    .await?;
+    let mut known: HashMap<Uuid, Edition> = database
+        .collection::<Edition>("editions")
+        .find(None, None)
+        .await
+        .map_err(AppError::from)?
+        .map_ok(|edition| (edition.uuid, edition))
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let mut editions = Vec::new();
+    let mut countries = BTreeMap::new();
+    for counted in &by_edition {
+        let Some(edition) = counted
+            .get_binary_generic("_id")
+            .ok()
+            .and_then(|bytes| Uuid::from_slice(bytes).ok())
+            .and_then(|id| known.remove(&id))
+        else {
+            continue;
+        };
+        let cards = aggregated_count(counted, "cards");
+        *countries.entry(edition.country_code.clone()).or_default() += cards;
+        editions.push(EditionCount { edition, cards });
+    }
+    editions.sort_by(|a, b| {
+        (&a.edition.country_code, &a.edition.version)
+            .cmp(&(&b.edition.country_code, &b.edition.version))
+    });
+
+    let days = query.days.unwrap_or(30).clamp(1, 366);
+    let since =
+        DateTime::from_millis(DateTime::now().timestamp_millis() - days * 24 * 60 * 60 * 1000);
+    let imports = aggregate_all(
+        &database,
+        "imports",
+        vec![
+            doc! { "$match": { "started_at": { "$gte": since } } },
+            doc! { "$group": {
+                "_id": { "$dateToString": { "format": "%Y-%m-%d", "date": "$started_at" } },
+                "imports": { "$sum": 1 },
+                "cards": { "$sum": "$summary.cards" },
+            } },
+            doc! { "$sort": { "_id": 1 } },
+        ],
+    )
+    .await?
+    .iter()
+    .filter_map(|d| {
+        Some(DailyImports {
+            day: d.get_str("_id").ok()?.to_string(),
+            imports: aggregated_count(d, "imports"),
+            cards: aggregated_count(d, "cards"),
+        })
+    })
+    .collect();
+
+    let options = FindOptions::builder()
+        .projection(doc! { "_id": 0, "suite": 1, "text": 1 })
+        .build();
+    let cards: Vec<CardText> = database
+        .collection::<CardText>("cards")
+        .find(doc! { "deleted_at": null }, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let texts: Vec<(Suite, &str)> = cards
+        .iter()
+        .map(|card| (card.suite, card.text.as_str()))
+        .collect();
+    let clusters = duplicate_clusters(&texts, DEFAULT_DUPLICATE_SIMILARITY);
+
+    Ok(web::Json(CorpusStats {
+        sets,
+        cards: cards.len() as u64,
+        suites,
+        editions,
+        countries,
+        imports,
+        duplicate_clusters: clusters.len(),
+        duplicate_cards: clusters.iter().map(Vec::len).sum(),
+    }))
+}
@@ -0,0 +1,405 @@
+use super::*;
+
+/// Turns a cursor into a newline-delimited JSON body, one document per line,
+/// so large exports are written out as Mongo yields them instead of being
+/// collected in memory first.
+fn ndjson<T>(cursor: Cursor<T>) -> impl Stream<Item = Result<Bytes, Box<dyn Error>>>
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    cursor.map(|item| {
+        let mut line = serde_json::to_vec(&item?)?;
+        line.push(b'\n');
+        Ok(Bytes::from(line))
+    })
+}
+
+/// Every card or set of `collection` as NDJSON, straight from a cursor.
+async fn export_stream(
+    database: &Database,
+    collection: &str,
+) -> Result<LocalBoxStream<'static, Result<Bytes, Box<dyn Error>>>, ActixError> {
+    Ok(match collection {
+        "cards" => ndjson(
+            database
+                .collection::<Card>("cards")
+                .find(None, None)
+                .await
+                .map_err(AppError::from)?,
+        )
+        .boxed_local(),
+        "sets" => ndjson(
+            database
+                .collection::<Set>("sets")
+                .find(None, None)
+                .await
+                .map_err(AppError::from)?,
+        )
+        .boxed_local(),
+        other => return Err(ErrorNotFound(format!("cannot export {other}"))),
+    })
+}
+
+/// How long an export snapshot is served before it is regenerated. Handing
+/// out the same file for a while is what lets a client resume a download and
+/// get the bytes it started with.
+const EXPORT_TTL: Duration = Duration::from_secs(10 * 60);
+const EXPORT_CHUNK: usize = 64 * 1024;
+
+async fn write_ndjson<T>(cursor: Cursor<T>, path: &Path) -> Result<(), Box<dyn Error>>
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    let mut lines = Box::pin(ndjson(cursor));
+    let mut file = BufWriter::new(tokio::fs::File::create(path).await?);
+    while let Some(line) = lines.next().await {
+        file.write_all(&line?).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Returns a snapshot of `collection` no older than `EXPORT_TTL`, streaming
+/// a new one to `dir` from a cursor when the last has expired. Only `HEAD`
+/// and ranged requests need one.
+async fn export_snapshot(
+    database: &Database,
+    dir: &str,
+    collection: &str,
+) -> Result<PathBuf, ActixError> {
+    let path = Path::new(dir).join(format!("{}.{collection}.ndjson", database.name()));
+    let fresh = fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < EXPORT_TTL);
+    if fresh {
+        return Ok(path);
+    }
+    fs::create_dir_all(dir).map_err(AppError::from)?;
+    // Written aside and renamed into place, so readers never see half a file.
+    let partial = path.with_extension(format!("{}.part", Uuid::new_v4()));
+    let written = match collection {
+        "cards" => {
+            let cursor = database
+                .collection::<Card>("cards")
+                .find(None, None)
+                .await
+                .map_err(AppError::from)?;
+            write_ndjson(cursor, &partial).await
+        }
+        "sets" => {
+            let cursor = database
+                .collection::<Set>("sets")
+                .find(None, None)
+                .await
+                .map_err(AppError::from)?;
+            write_ndjson(cursor, &partial).await
+        }
+        other => return Err(ErrorNotFound(format!("cannot export {other}"))),
+    };
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(ErrorInternalServerError(e.to_string()));
+    }
+    fs::rename(&partial, &path).map_err(AppError::from)?;
+    Ok(path)
+}
+
+/// What part of a body of known length a `Range` header asks for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum ByteRange {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Resolves a single `bytes=` range against `len`. Anything else,
+    /// including multiple ranges, is answered with the full body as RFC 9110
+    /// allows.
+    pub(super) fn parse(header: Option<&str>, len: u64) -> Self {
+        let Some((start, end)) = header
+            .and_then(|h| h.trim().strip_prefix("bytes="))
+            .filter(|spec| !spec.contains(','))
+            .and_then(|spec| spec.trim().split_once('-'))
+        else {
+            return ByteRange::Full;
+        };
+        let last = len.saturating_sub(1);
+        let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(last)),
+            (Ok(start), Err(_)) if end.is_empty() => (start, last),
+            (Err(_), Ok(0)) if start.is_empty() => return ByteRange::Unsatisfiable,
+            (Err(_), Ok(suffix)) if start.is_empty() => (len.saturating_sub(suffix), last),
+            _ => return ByteRange::Full,
+        };
+        if start >= len {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Partial(start, end)
+        }
+    }
+}
+
+/// Reads `count` bytes of `file` from its current position as a body stream.
+fn file_chunks(
+    file: tokio::fs::File,
+    count: u64,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    futures::stream::try_unfold(file.take(count), |mut reader| async move {
+        let mut chunk = vec![0; EXPORT_CHUNK];
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((Bytes::from(chunk), reader)))
+    })
+}
+
+/// Serves every card or set in the library as NDJSON, for backups and bulk
+/// moves between deployments. A plain `GET` streams the documents straight
+/// from the database as they are read. Downloads that may need resuming
+/// start with `HEAD`, which reports the size and ETag of the current
+/// snapshot, and fetch it with `Range` requests, which let them pick up
+/// where they stopped.
+pub(super) async fn export_collection(
+    _admin: Admin,
+    TargetDatabase(database): TargetDatabase,
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ActixError> {
+    let collection = path.into_inner();
+    let disposition = format!("attachment; filename=\"{collection}.ndjson\"");
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+    if req.method() != Method::HEAD && header("Range").is_none() {
+        let body = export_stream(&database, &collection).await?;
+        // The stream has no size or ETag, so a broken one cannot be resumed.
+        return Ok(HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .insert_header(("Accept-Ranges", "none"))
+            .insert_header(("Content-Disposition", disposition))
+            .streaming(body));
+    }
+    let snapshot = export_snapshot(&database, &config.export_dir, &collection).await?;
+    let mut file = tokio::fs::File::open(&snapshot)
+        .await
+        .map_err(AppError::from)?;
+    let metadata = file.metadata().await.map_err(AppError::from)?;
+    let len = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let etag = format!("\"{len:x}-{modified:x}\"");
+
+    if header("If-None-Match") == Some(etag.as_str()) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(("ETag", etag))
+            .finish());
+    }
+    // A resumed download only gets a range while the snapshot is still the
+    // one it started on.
+    let range = match header("If-Range") {
+        Some(tag) if tag != etag => ByteRange::Full,
+        _ => ByteRange::parse(header("Range"), len),
+    };
+    let (mut response, start, count) = match range {
+        ByteRange::Full => (HttpResponse::Ok(), 0, len),
+        ByteRange::Partial(start, end) => {
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header(("Content-Range", format!("bytes {start}-{end}/{len}")));
+            (response, start, end - start + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header(("Content-Range", format!("bytes */{len}")))
+                .finish())
+        }
+    };
+    response
+        .content_type("application/x-ndjson")
+        .insert_header(("Accept-Ranges", "bytes"))
+        .insert_header(("ETag", etag))
+        .insert_header(("Content-Disposition", disposition));
+    if req.method() == Method::HEAD {
+        let empty = futures::stream::empty::<Result<Bytes, std::io::Error>>();
+        return Ok(response.body(SizedStream::new(count, empty)));
+    }
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(AppError::from)?;
+    Ok(response.body(SizedStream::new(count, file_chunks(file, count))))
+}
+
+/// Most sets a single archive may bundle.
+const MAX_ARCHIVE_SETS: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ArchiveFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ArchiveRequest {
+    sets: Vec<Uuid>,
+    #[serde(default)]
+    format: ArchiveFormat,
+}
+
+/// A set with its cards and editions, as archived in JSON.
+#[derive(Serialize)]
+struct SetExport<'a> {
+    #[serde(flatten)]
+    set: &'a Set,
+    cards: &'a [Card],
+    editions: &'a [Edition],
+}
+
+/// Writes a set in the layout uploads are parsed from, editions marked
+/// with an `x` in their columns, so archived CSVs import as the same set.
+pub(super) fn write_set_csv<W: std::io::Write>(set: &Set, out: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    let labels: Vec<String> = set.editions.iter().map(Edition::label).collect();
+    let mut header = vec!["Set", set.name.as_str(), "Special"];
+    if !labels.is_empty() {
+        header.push("Edition");
+        header.extend(labels.iter().map(String::as_str));
+    }
+    // The reader skips the first row, as it does a sheet's title row.
+    writer.write_record(&header)?;
+    writer.write_record(&header)?;
+    for card in &set.cards {
+        let suite = match card.suite {
+            Suite::Prompt => "Prompt",
+            Suite::Response => "Response",
+        };
+        let mut row = vec![suite, card.text.as_str(), card.special.as_str()];
+        if !labels.is_empty() {
+            row.push("");
+            row.extend(set.editions.iter().map(
+                |edition| match card.editions.contains(&edition.uuid) {
+                    true => "x",
+                    false => "",
+                },
+            ));
+        }
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A file name for `set` inside an archive, readable but safe on any system.
+fn archive_entry_name(set: &Set, extension: &str) -> String {
+    let name: String = set
+        .name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("{}-{}.{extension}", name.trim_matches('-'), set.uuid)
+}
+
+fn write_archive(path: &Path, sets: &[Set], format: ArchiveFormat) -> Result<(), Box<dyn Error>> {
+    let mut zip = zip::ZipWriter::new(File::create(path)?);
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for set in sets {
+        match format {
+            ArchiveFormat::Json => {
+                zip.start_file(archive_entry_name(set, "json"), options)?;
+                let export = SetExport {
+                    set,
+                    cards: &set.cards,
+                    editions: &set.editions,
+                };
+                serde_json::to_writer_pretty(&mut zip, &export)?;
+            }
+            ArchiveFormat::Csv => {
+                zip.start_file(archive_entry_name(set, "csv"), options)?;
+                write_set_csv(set, &mut zip)?;
+            }
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Bundles the chosen sets, each with its cards, into one ZIP download.
+pub(super) async fn export_archive(
+    body: web::Json<ArchiveRequest>,
+    safe_mode: SafeMode,
+    max_rating: MaxRating,
+    config: web::Data<Config>,
+    store: web::Data<Store>,
+) -> Result<HttpResponse, ActixError> {
+    let ArchiveRequest { sets: ids, format } = body.into_inner();
+    if ids.is_empty() || ids.len() > MAX_ARCHIVE_SETS {
+        return Err(ErrorBadRequest(format!(
+            "an archive holds between 1 and {MAX_ARCHIVE_SETS} sets"
+        )));
+    }
+    let database = store.database();
+    let wanted: Vec<Bson> = ids.iter().map(uuid_bson).collect();
+    let mut filter = visible_sets(safe_mode.0);
+    filter.insert("uuid", doc! { "$in": wanted });
+    let mut sets: Vec<Set> = database
+        .collection::<Set>("sets")
+        .find(filter, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    if let Some(missing) = ids.iter().find(|id| !sets.iter().any(|s| s.uuid == **id)) {
+        return Err(ErrorNotFound(format!("set {missing} not found")));
+    }
+    let cards: Collection<Card> = database.collection("cards");
+    let editions: Collection<Edition> = database.collection("editions");
+    for set in &mut sets {
+        let filter =
+            visible_cards(database, card_filter(&[set.uuid], &[], &[]), safe_mode.0).await?;
+        let filter = rated_cards(filter, max_rating);
+        set.cards = cards
+            .find(
+                filter,
+                FindOptions::builder().sort(doc! { "text": 1 }).build(),
+            )
+            .await
+            .map_err(AppError::from)?
+            .try_collect()
+            .await
+            .map_err(AppError::from)?;
+        set.editions = editions
+            .find(doc! { "set_uuid": uuid_bson(&set.uuid) }, None)
+            .await
+            .map_err(AppError::from)?
+            .try_collect()
+            .await
+            .map_err(AppError::from)?;
+    }
+
+    let path = PathBuf::from(format!(
+        "{}/archive-{}.zip",
+        config.temp_dir,
+        Uuid::new_v4()
+    ));
+    let written = path.clone();
+    web::block(move || write_archive(&written, &sets, format).map_err(|e| e.to_string()))
+        .await?
+        .map_err(ErrorInternalServerError)?;
+    let file = tokio::fs::File::open(&path).await.map_err(AppError::from)?;
+    // The open handle keeps the archive readable while it is sent.
+    let _ = fs::remove_file(&path);
+    let len = file.metadata().await.map_err(AppError::from)?.len();
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(("Content-Disposition", "attachment; filename=\"sets.zip\""))
+        .body(SizedStream::new(len, file_chunks(file, len))))
+}
@@ -0,0 +1,2010 @@
+use super::*;
+
+#[derive(Debug, MultipartForm)]
+pub(super) struct UploadForm {
+    #[multipart(rename = "file")]
+    files: Vec<TempFile>,
+    language: Option<Text<String>>,
+    /// Flags every imported set as not safe for work.
+    nsfw: Option<Text<bool>>,
+    /// Imports the sets as drafts, to be published at this RFC 3339 time.
+    publish_at: Option<Text<String>>,
+    /// `random` draws fresh ids instead of deriving them from the set name
+    /// and card text.
+    id_mode: Option<Text<IdMode>>,
+    /// The sheet's separator, see `parse_delimiter`.
+    delimiter: Option<Text<String>>,
+    /// Name of the mapping profile to read the sheet's headers by.
+    profile: Option<Text<String>>,
+    /// `strict` rejects the sheet at its first bad row instead of skipping
+    /// it.
+    mode: Option<Text<ParseMode>>,
+}
+
+/// Caps how many uploaded sheets are parsed and stored at once, across all
+/// requests and archives, see `Config::upload_concurrency`.
+pub(super) struct UploadLimit(Semaphore);
+
+impl UploadLimit {
+    pub(super) fn new(permits: usize) -> Self {
+        UploadLimit(Semaphore::new(permits.max(1)))
+    }
+}
+
+/// Reads a separator given by name (`comma`, `semicolon`, `tab`, `pipe`)
+/// or as the character itself.
+fn parse_delimiter(value: &str) -> Result<char, ActixError> {
+    let delimiter = match value {
+        "comma" => ',',
+        "semicolon" => ';',
+        "tab" | "\\t" => '\t',
+        "pipe" => '|',
+        _ => {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii() && !c.is_ascii_alphanumeric() => c,
+                _ => return Err(ErrorBadRequest(format!("invalid delimiter {value:?}"))),
+            }
+        }
+    };
+    match delimiter {
+        '"' | '\n' | '\r' => Err(ErrorBadRequest(format!("invalid delimiter {value:?}"))),
+        delimiter => Ok(delimiter),
+    }
+}
+
+/// Settings from the upload form applied to every set it imports.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(super) struct ImportOptions {
+    pub(super) language: Option<String>,
+    pub(super) nsfw: bool,
+    pub(super) publish_at: Option<DateTime>,
+    pub(super) id_mode: IdMode,
+    /// Separates the fields of CSV sheets. Guessed from each sheet's first
+    /// line when not given.
+    #[serde(default)]
+    pub(super) delimiter: Option<char>,
+    /// Mapping profile the sheets' headers are read by, the master sheet's
+    /// layout when not given.
+    #[serde(default)]
+    pub(super) profile: Option<String>,
+    #[serde(default)]
+    pub(super) mode: ParseMode,
+}
+
+impl ImportOptions {
+    fn from_form(form: &UploadForm) -> Result<Self, ActixError> {
+        Ok(ImportOptions {
+            language: form.language.as_ref().map(|l| l.0.clone()),
+            nsfw: form.nsfw.as_ref().is_some_and(|n| n.0),
+            publish_at: form
+                .publish_at
+                .as_ref()
+                .map(|at| DateTime::parse_rfc3339_str(at.as_str()))
+                .transpose()
+                .map_err(ErrorBadRequest)?,
+            id_mode: form.id_mode.as_ref().map(|m| m.0).unwrap_or_default(),
+            delimiter: form
+                .delimiter
+                .as_ref()
+                .map(|d| parse_delimiter(d))
+                .transpose()?,
+            profile: form.profile.as_ref().map(|p| p.0.clone()),
+            mode: form.mode.as_ref().map(|m| m.0).unwrap_or_default(),
+        })
+    }
+
+    /// The mapping profile the options name.
+    async fn profile(&self, database: &Database) -> Result<ColumnProfile, AppError> {
+        ColumnProfile::load(database, self.profile.as_deref())
+            .await?
+            .ok_or_else(|| {
+                AppError::Upload(format!(
+                    "no mapping profile named {:?}",
+                    self.profile.as_deref().unwrap_or_default()
+                ))
+            })
+    }
+
+    /// The separator of the CSV sheet at `path`.
+    fn delimiter(&self, path: &str) -> Result<u8, AppError> {
+        match self.delimiter {
+            // `parse_delimiter` only lets ASCII through.
+            Some(delimiter) => Ok(delimiter as u8),
+            None => detect_delimiter(path),
+        }
+    }
+
+    fn apply(&self, set: &mut Set) {
+        if self.id_mode == IdMode::Deterministic {
+            set.derive_ids();
+        }
+        if self.publish_at.is_some() {
+            set.status = SetStatus::Draft;
+            set.publish_at = self.publish_at;
+        }
+        set.language = self.language.clone();
+        set.nsfw = self.nsfw;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ImportStatus {
+    Running,
+    Done,
+    Failed,
+    /// Stopped on request, see `cancel_import`. The sets in `done` were
+    /// written before it stopped and stay in the library.
+    Cancelled,
+}
+
+/// A sheet being read for a job, which fails the next read once the job is
+/// cancelled so a long sheet stops parsing part way through a set.
+struct Cancellable<R> {
+    inner: R,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<R: Read> Read for Cancellable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::other("import cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// A sheet being written to the library, checkpointed around every set so
+/// an import cut short by a restart picks up where it stopped instead of
+/// leaving a half-written set behind with no record of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct ImportJob {
+    uuid: Uuid,
+    /// Working copy of the sheet, kept until the job is done.
+    path: String,
+    options: ImportOptions,
+    status: ImportStatus,
+    /// Names of the sets already written.
+    done: Vec<String>,
+    /// The set being written at the last checkpoint. Writing it again is
+    /// safe: `add_set` only adds the cards that are still missing.
+    current: Option<String>,
+    error: Option<String>,
+    /// How many events the job has stored in `import_events`.
+    #[serde(default)]
+    event_count: u64,
+    #[serde(default)]
+    source: ImportSource,
+    /// The instance running the job, see `instance_id`.
+    #[serde(default)]
+    owner: Option<String>,
+    created_at: DateTime,
+    /// Refreshed every `IMPORT_HEARTBEAT` while the job runs, so a running
+    /// job whose instance died can be told from one still in progress.
+    updated_at: DateTime,
+}
+
+/// How often a running job refreshes its `updated_at`.
+const IMPORT_HEARTBEAT: Duration = Duration::from_secs(30);
+/// How long a running job may go without a heartbeat before another
+/// instance takes it over, see `resume_import_jobs`.
+const IMPORT_STALE: Duration = Duration::from_secs(3 * 60);
+/// How often instances look for jobs to take over.
+pub(super) const IMPORT_RESUME_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where an import's sheets came from and who asked for it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(super) struct ImportSource {
+    #[serde(default)]
+    pub(super) files: Vec<String>,
+    pub(super) url: Option<String>,
+    /// The `X-User-Id` of the request, if it had one.
+    pub(super) user: Option<String>,
+}
+
+/// An entry of the import history kept in `imports`, see `list_imports`.
+/// Imports run as jobs are recorded under the job's id.
+#[derive(Debug, Serialize, Deserialize)]
+struct ImportRecord {
+    uuid: Uuid,
+    #[serde(flatten)]
+    source: ImportSource,
+    status: ImportStatus,
+    summary: ImportSummary,
+    /// Left out of listings; `import_errors` serves them. At most
+    /// `MAX_RECORDED_WARNINGS` are kept, the first ones.
+    #[serde(default)]
+    warnings: Vec<ParseWarning>,
+    /// How many warnings the import raised in all.
+    #[serde(default)]
+    warning_count: u64,
+    error: Option<String>,
+    started_at: DateTime,
+    finished_at: DateTime,
+}
+
+/// Most warnings an import record keeps. A sheet flagging every one of its
+/// rows would otherwise outgrow Mongo's 16 MB document limit.
+const MAX_RECORDED_WARNINGS: usize = 1000;
+
+impl ImportRecord {
+    /// Adds `warnings` to the record, keeping at most
+    /// `MAX_RECORDED_WARNINGS` of them.
+    fn warn(&mut self, warnings: &[ParseWarning]) {
+        self.warning_count += warnings.len() as u64;
+        let room = MAX_RECORDED_WARNINGS.saturating_sub(self.warnings.len());
+        self.warnings.extend(warnings.iter().take(room).cloned());
+    }
+
+    /// Stores the record, replacing the one of an earlier run of the same
+    /// job. The history is an audit aid, so failing to write it only logs.
+    async fn save(&self, database: &Database) {
+        let result = database
+            .collection::<ImportRecord>("imports")
+            .replace_one(
+                doc! { "uuid": uuid_bson(&self.uuid) },
+                self,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await;
+        if let Err(e) = result {
+            eprintln!("could not record import {}: {e}", self.uuid);
+        }
+        audit(
+            database,
+            self.source.user.clone().map(User).as_ref(),
+            "import",
+            "import",
+            Some(self.uuid),
+            doc! {},
+            doc! {
+                "status": to_bson(&self.status).unwrap_or(Bson::Null),
+                "summary": to_bson(&self.summary).unwrap_or(Bson::Null),
+            },
+        )
+        .await;
+    }
+}
+
+/// A step of an import, as sent by `import_events`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ImportEvent {
+    /// A set was written. `line` is how far into the sheet parsing got.
+    Set {
+        name: String,
+        cards: usize,
+        line: u64,
+    },
+    Warning(ParseWarning),
+}
+
+impl ImportEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            ImportEvent::Set { .. } => "set",
+            ImportEvent::Warning(_) => "warning",
+        }
+    }
+}
+
+/// An event of a job as kept in `import_events`, numbered in the order the
+/// job stored it. Events live apart from the job so a sheet with a warning
+/// on every row cannot outgrow the job's document.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredEvent {
+    job: Uuid,
+    index: u64,
+    #[serde(flatten)]
+    event: ImportEvent,
+}
+
+/// A set read from a job's sheet, with what the parser found along the way.
+struct ParsedSet {
+    set: Result<Set, AppError>,
+    line: u64,
+    warnings: Vec<ParseWarning>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub(super) struct ImportSummary {
+    pub(super) sets: usize,
+    pub(super) cards: usize,
+    /// Cards by how they compare to what was stored, see `SetChanges`.
+    #[serde(default)]
+    unchanged: usize,
+    #[serde(default)]
+    changed: usize,
+    #[serde(default)]
+    added: usize,
+    #[serde(default)]
+    removed: usize,
+}
+
+impl ImportSummary {
+    pub(super) fn record(&mut self, cards: usize, changes: SetChanges) {
+        self.sets += 1;
+        self.cards += cards;
+        self.unchanged += changes.unchanged;
+        self.changed += changes.changed;
+        self.added += changes.added;
+        self.removed += changes.removed;
+    }
+}
+
+impl ImportJob {
+    /// Moves `sheet` into `import_dir` and records a job for it.
+    pub(super) async fn create(
+        database: &Database,
+        import_dir: &str,
+        sheet: &Path,
+        options: ImportOptions,
+        source: ImportSource,
+    ) -> Result<ImportJob, Box<dyn Error>> {
+        let uuid = Uuid::new_v4();
+        fs::create_dir_all(import_dir)?;
+        let path = Path::new(import_dir).join(format!("{uuid}.csv"));
+        if fs::rename(sheet, &path).is_err() {
+            fs::copy(sheet, &path)?;
+            fs::remove_file(sheet)?;
+        }
+        let job = ImportJob {
+            uuid,
+            path: path.to_string_lossy().into_owned(),
+            options,
+            status: ImportStatus::Running,
+            done: Vec::new(),
+            current: None,
+            error: None,
+            event_count: 0,
+            source,
+            owner: Some(instance_id().to_string()),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        };
+        database
+            .collection::<ImportJob>("import_jobs")
+            .insert_one(&job, None)
+            .await?;
+        Ok(job)
+    }
+
+    /// Records `fields` on the job and, once a set is written, its name
+    /// and the events that came with it.
+    async fn checkpoint(
+        &mut self,
+        database: &Database,
+        mut fields: Document,
+        done: Option<(&str, Vec<ImportEvent>)>,
+    ) -> Result<(), Box<dyn Error>> {
+        fields.insert("updated_at", DateTime::now());
+        let mut push = None;
+        if let Some((name, events)) = done {
+            let stored = database.collection::<StoredEvent>("import_events");
+            // Events of a run cut short after storing them but before its
+            // checkpoint are stored again below.
+            stored
+                .delete_many(
+                    doc! {
+                        "job": uuid_bson(&self.uuid),
+                        "index": { "$gte": self.event_count as i64 },
+                    },
+                    None,
+                )
+                .await?;
+            let first = self.event_count;
+            self.event_count += events.len() as u64;
+            let events: Vec<StoredEvent> = (first..)
+                .zip(events)
+                .map(|(index, event)| StoredEvent {
+                    job: self.uuid,
+                    index,
+                    event,
+                })
+                .collect();
+            if !events.is_empty() {
+                stored.insert_many(events, None).await?;
+            }
+            fields.insert("event_count", self.event_count as i64);
+            push = Some(doc! { "done": name });
+        }
+        let mut update = doc! { "$set": fields };
+        if let Some(push) = push {
+            update.insert("$push", push);
+        }
+        database
+            .collection::<ImportJob>("import_jobs")
+            .update_one(doc! { "uuid": uuid_bson(&self.uuid) }, update, None)
+            .await?;
+        Ok(())
+    }
+
+    /// Parses the sheet and writes every set not yet done, then removes the
+    /// working copy. A failure marks the job failed and keeps the sheet so
+    /// the job can be resumed. Either way the outcome goes into the import
+    /// history.
+    pub(super) async fn run(
+        mut self,
+        store: &Store,
+        limits: ParseLimits,
+        rules: CardRules,
+    ) -> Result<ImportSummary, Box<dyn Error>> {
+        let database = store.database();
+        let heartbeat = {
+            let jobs = database.collection::<ImportJob>("import_jobs");
+            let filter = doc! { "uuid": uuid_bson(&self.uuid) };
+            actix_web::rt::spawn(async move {
+                let mut interval = actix_web::rt::time::interval(IMPORT_HEARTBEAT);
+                loop {
+                    interval.tick().await;
+                    let beat = doc! { "$set": { "updated_at": DateTime::now() } };
+                    if let Err(e) = jobs.update_one(filter.clone(), beat, None).await {
+                        eprintln!("could not refresh an import's heartbeat: {e}");
+                    }
+                }
+            })
+        };
+        let result = self.write_sets(store, limits, rules).await;
+        heartbeat.abort();
+        let status = match &result {
+            Ok(_) if self.status == ImportStatus::Cancelled => ImportStatus::Cancelled,
+            Ok(_) => ImportStatus::Done,
+            Err(_) => ImportStatus::Failed,
+        };
+        let (warnings, warning_count) = match self.warnings(database).await {
+            Ok(warnings) => warnings,
+            Err(e) => {
+                eprintln!("could not read the warnings of import {}: {e}", self.uuid);
+                (Vec::new(), 0)
+            }
+        };
+        ImportRecord {
+            uuid: self.uuid,
+            source: self.source.clone(),
+            status,
+            summary: result.as_ref().ok().copied().unwrap_or_default(),
+            warnings,
+            warning_count,
+            error: result.as_ref().err().map(|e| e.to_string()),
+            started_at: self.created_at,
+            finished_at: DateTime::now(),
+        }
+        .save(database)
+        .await;
+        match &result {
+            Ok(_) if self.status == ImportStatus::Cancelled => {
+                let _ = fs::remove_file(&self.path);
+            }
+            Ok(_) => {
+                self.checkpoint(database, doc! { "status": "done" }, None)
+                    .await?;
+                let _ = fs::remove_file(&self.path);
+            }
+            Err(e) => {
+                self.checkpoint(
+                    database,
+                    doc! { "status": "failed", "error": e.to_string() },
+                    None,
+                )
+                .await?;
+            }
+        }
+        result
+    }
+
+    /// The first `MAX_RECORDED_WARNINGS` warnings the job stored, over all
+    /// its runs, and how many there are in all.
+    async fn warnings(
+        &self,
+        database: &Database,
+    ) -> Result<(Vec<ParseWarning>, u64), Box<dyn Error>> {
+        let stored = database.collection::<StoredEvent>("import_events");
+        let filter = doc! { "job": uuid_bson(&self.uuid), "event": "warning" };
+        let count = stored.count_documents(filter.clone(), None).await?;
+        let options = FindOptions::builder()
+            .sort(doc! { "index": 1 })
+            .limit(MAX_RECORDED_WARNINGS as i64)
+            .build();
+        let warnings = stored
+            .find(filter, options)
+            .await?
+            .try_filter_map(|stored| {
+                ready(Ok(match stored.event {
+                    ImportEvent::Warning(warning) => Some(warning),
+                    ImportEvent::Set { .. } => None,
+                }))
+            })
+            .try_collect()
+            .await?;
+        Ok((warnings, count))
+    }
+
+    /// Whether `cancel_import` was called on the job.
+    async fn cancel_requested(&self, database: &Database) -> Result<bool, Box<dyn Error>> {
+        let cancelled = database
+            .collection::<ImportJob>("import_jobs")
+            .count_documents(
+                doc! { "uuid": uuid_bson(&self.uuid), "status": "cancelled" },
+                None,
+            )
+            .await?;
+        Ok(cancelled > 0)
+    }
+
+    async fn write_sets(
+        &mut self,
+        store: &Store,
+        limits: ParseLimits,
+        rules: CardRules,
+    ) -> Result<ImportSummary, Box<dyn Error>> {
+        let database = store.database();
+        let types = CardTypes::load(database).await?;
+        let profile = self.options.profile(database).await?;
+        let path = self.path.clone();
+        let options = self.options.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let open = {
+            let cancelled = cancelled.clone();
+            move |path: &str| -> Result<Cancellable<File>, AppError> {
+                Ok(Cancellable {
+                    inner: File::open(path)?,
+                    cancelled: cancelled.clone(),
+                })
+            }
+        };
+        // Sets are written as the parser finishes them, as for uploads.
+        let (sender, mut receiver) = mpsc::channel(IMPORT_QUEUE);
+        let parser = web::block(move || -> Result<(), AppError> {
+            // A conversion concerns the whole file, so it is reported with
+            // the first set.
+            let encoding = transcode_to_utf8(&path)?.map(encoding_warning);
+            if looks_like_json(&path)? {
+                // A deck comes whole, so its warnings go with the first set.
+                let (sets, report) = parse_json_deck(open(&path)?, &limits, &rules)?;
+                let mut warnings: Vec<ParseWarning> =
+                    encoding.into_iter().chain(report.warnings).collect();
+                for set in sets {
+                    let parsed = ParsedSet {
+                        set: Ok(set),
+                        line: 0,
+                        warnings: std::mem::take(&mut warnings),
+                    };
+                    if sender.blocking_send(parsed).is_err() {
+                        break;
+                    }
+                }
+                return Ok(());
+            }
+            let delimiter = options.delimiter(&path)?;
+            let mut reader = SetReader::delimited(open(&path)?, delimiter, limits, types)
+                .with_rules(rules)
+                .with_profile(profile)
+                .with_mode(options.mode);
+            let mut reported = 0;
+            let mut encoding = encoding;
+            while let Some(set) = reader.next() {
+                let warnings = &reader.report().warnings;
+                let parsed = ParsedSet {
+                    set,
+                    line: reader.line(),
+                    warnings: encoding
+                        .take()
+                        .into_iter()
+                        .chain(warnings[reported..].iter().cloned())
+                        .collect(),
+                };
+                reported = warnings.len();
+                if sender.blocking_send(parsed).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        let mut summary = ImportSummary::default();
+        // Between sets, and every `IMPORT_POLL` while a long one is being
+        // parsed, the job looks for a cancel request.
+        loop {
+            let next = actix_web::rt::time::timeout(IMPORT_POLL, receiver.recv()).await;
+            if self.cancel_requested(database).await? {
+                cancelled.store(true, Ordering::Relaxed);
+                self.status = ImportStatus::Cancelled;
+                break;
+            }
+            let parsed = match next {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => break,
+                Err(_) => continue,
+            };
+            let mut set = parsed.set?;
+            if self.done.contains(&set.name) {
+                continue;
+            }
+            self.options.apply(&mut set);
+            self.checkpoint(database, doc! { "current": &set.name }, None)
+                .await?;
+            let changes = store.add_set(&set).await?;
+            let mut events: Vec<ImportEvent> = parsed
+                .warnings
+                .into_iter()
+                .map(ImportEvent::Warning)
+                .collect();
+            events.push(ImportEvent::Set {
+                name: set.name.clone(),
+                cards: set.cards.len(),
+                line: parsed.line,
+            });
+            self.checkpoint(
+                database,
+                doc! { "current": Bson::Null },
+                Some((&set.name, events)),
+            )
+            .await?;
+            self.done.push(set.name.clone());
+            summary.record(set.cards.len(), changes);
+        }
+        // The parser may be waiting to hand over a set nobody will take.
+        drop(receiver);
+        match self.status {
+            ImportStatus::Cancelled => {
+                let _ = parser.await;
+            }
+            _ => parser.await??,
+        }
+        Ok(summary)
+    }
+}
+
+/// Picks up every job a restart interrupted: running jobs whose heartbeat
+/// went stale, each claimed by one instance before it runs it.
+pub(super) async fn resume_import_jobs() -> Result<(), ActixError> {
+    let config = config::get();
+    let limits = ParseLimits::from_config(config);
+    let rules = CardRules::from_config(config);
+    let store = Store::connect(config).await.map_err(AppError::from)?;
+    for name in allowed_databases(config) {
+        let store = store.with_database(store.client().database(&name));
+        let database = store.database();
+        let jobs = database.collection::<ImportJob>("import_jobs");
+        let claim = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        loop {
+            let stale = DateTime::from_millis(
+                DateTime::now().timestamp_millis() - IMPORT_STALE.as_millis() as i64,
+            );
+            let Some(job) = jobs
+                .find_one_and_update(
+                    doc! { "status": "running", "updated_at": { "$lt": stale } },
+                    doc! { "$set": { "owner": instance_id(), "updated_at": DateTime::now() } },
+                    claim.clone(),
+                )
+                .await
+                .map_err(AppError::from)?
+            else {
+                break;
+            };
+            println!(
+                "resuming import {} in {name} after {} sets",
+                job.uuid,
+                job.done.len()
+            );
+            queue_import(&store, job, limits, &rules);
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn list_import_jobs(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<impl Responder, ActixError> {
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .limit(100)
+        .build();
+    let jobs: Vec<ImportJob> = database
+        .collection::<ImportJob>("import_jobs")
+        .find(None, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(jobs))
+}
+
+/// Asks a running import to stop. The job notices within `IMPORT_POLL`,
+/// abandons the set it is parsing and removes its sheet; sets written by
+/// then stay in the library and are listed in the job's `done`.
+pub(super) async fn cancel_import(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<impl Responder, ActixError> {
+    let uuid = path.into_inner();
+    database
+        .collection::<ImportJob>("import_jobs")
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&uuid), "status": "running" },
+            doc! { "$set": { "status": "cancelled", "updated_at": DateTime::now() } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorConflict(format!("no running import {uuid}")))?;
+    audit(
+        &database,
+        user.as_ref(),
+        "cancel",
+        "import",
+        Some(uuid),
+        doc! { "status": { "before": "running", "after": "cancelled" } },
+        doc! {},
+    )
+    .await;
+    Ok(HttpResponse::Accepted().json(QueuedImport {
+        uuid,
+        events: format!("/api/imports/{uuid}/events"),
+    }))
+}
+
+/// The most recent imports, newest first, without their warnings.
+pub(super) async fn list_imports(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<impl Responder, ActixError> {
+    let options = FindOptions::builder()
+        .sort(doc! { "started_at": -1 })
+        .limit(100)
+        .projection(doc! { "warnings": 0 })
+        .build();
+    let imports: Vec<ImportRecord> = database
+        .collection::<ImportRecord>("imports")
+        .find(None, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(imports))
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ErrorsFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ErrorsQuery {
+    #[serde(default)]
+    format: ErrorsFormat,
+}
+
+/// The rows a past import skipped or flagged, each with its line, the
+/// column at fault and why, for the sheet's maintainer to fix the source
+/// by. In CSV a failed import ends with a row giving the error.
+pub(super) async fn import_errors(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+    path: web::Path<Uuid>,
+    query: web::Query<ErrorsQuery>,
+) -> Result<HttpResponse, ActixError> {
+    let uuid = path.into_inner();
+    let record = database
+        .collection::<ImportRecord>("imports")
+        .find_one(doc! { "uuid": uuid_bson(&uuid) }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("import {uuid} not found")))?;
+    if let ErrorsFormat::Json = query.format {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "uuid": uuid,
+            "status": record.status,
+            "error": record.error,
+            "warnings": record.warnings,
+            "warning_count": record.warning_count,
+        })));
+    }
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    // Written by hand so a report with no warnings still has its header.
+    writer
+        .write_record(["line", "column", "kind", "set", "value"])
+        .map_err(ErrorInternalServerError)?;
+    for warning in &record.warnings {
+        writer
+            .serialize(warning)
+            .map_err(ErrorInternalServerError)?;
+    }
+    let dropped = record
+        .warning_count
+        .saturating_sub(record.warnings.len() as u64);
+    if dropped > 0 {
+        writer
+            .write_record(["", "", "truncated", "", &format!("{dropped} more warnings")])
+            .map_err(ErrorInternalServerError)?;
+    }
+    if let Some(error) = &record.error {
+        writer
+            .write_record(["", "", "error", "", error.as_str()])
+            .map_err(ErrorInternalServerError)?;
+    }
+    let body = writer.into_inner().map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"import-{uuid}.csv\""),
+        ))
+        .body(body))
+}
+
+/// Runs a failed job again from its last checkpoint.
+pub(super) async fn resume_import_job(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+    path: web::Path<Uuid>,
+    limits: web::Data<ParseLimits>,
+    rules: web::Data<CardRules>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let store = store.with_database(database);
+    let jobs = store.database().collection::<ImportJob>("import_jobs");
+    let job = jobs
+        .find_one_and_update(
+            doc! { "uuid": uuid_bson(&path), "status": "failed" },
+            doc! { "$set": {
+                "status": "running",
+                "error": Bson::Null,
+                "owner": instance_id(),
+                "updated_at": DateTime::now(),
+            } },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorConflict(format!("no failed import {path}")))?;
+    let summary = job
+        .run(&store, **limits, CardRules::clone(&rules))
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+    Ok(web::Json(summary))
+}
+
+#[derive(Debug, Serialize)]
+struct QueuedImport {
+    uuid: Uuid,
+    /// Where to follow the job, see `import_events`.
+    events: String,
+}
+
+/// Queues every uploaded file as an import job and answers straight away,
+/// leaving the caller to follow the jobs instead of holding the request
+/// open for a large sheet.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn start_imports(
+    MultipartForm(form): MultipartForm<UploadForm>,
+    _: Admin,
+    user: Option<User>,
+    TargetDatabase(database): TargetDatabase,
+    limits: web::Data<ParseLimits>,
+    rules: web::Data<CardRules>,
+    config: web::Data<Config>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let store = store.with_database(database);
+    let options = ImportOptions::from_form(&form)?;
+    let mut queued = Vec::new();
+    for file in form.files {
+        let source = ImportSource {
+            files: file.file_name.into_iter().collect(),
+            url: None,
+            user: user.as_ref().map(|u| u.0.clone()),
+        };
+        let sheet = file.file.into_temp_path();
+        let job = ImportJob::create(
+            store.database(),
+            &config.import_dir,
+            &sheet,
+            options.clone(),
+            source,
+        )
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+        queued.push(queue_import(&store, job, **limits, &rules));
+    }
+    Ok(HttpResponse::Accepted().json(queued))
+}
+
+/// Runs `job` in the background.
+fn queue_import(
+    store: &Store,
+    job: ImportJob,
+    limits: ParseLimits,
+    rules: &CardRules,
+) -> QueuedImport {
+    let queued = QueuedImport {
+        uuid: job.uuid,
+        events: format!("/api/imports/{}/events", job.uuid),
+    };
+    let store = store.clone();
+    let rules = rules.clone();
+    actix_web::rt::spawn(async move {
+        let uuid = job.uuid;
+        if let Err(e) = job.run(&store, limits, rules).await {
+            eprintln!("import {uuid} failed: {e}");
+        }
+    });
+    queued
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct UrlImport {
+    /// A raw CSV or JSON deck, or a Google Sheets link.
+    url: String,
+    language: Option<String>,
+    #[serde(default)]
+    nsfw: bool,
+    /// RFC 3339, as in the upload form.
+    publish_at: Option<String>,
+    #[serde(default)]
+    id_mode: IdMode,
+    delimiter: Option<String>,
+    profile: Option<String>,
+    #[serde(default)]
+    mode: ParseMode,
+}
+
+/// Turns a link to a Google Sheets document, as copied from the browser,
+/// into its CSV export. The `gid` picks the tab. Other URLs are returned
+/// as they are.
+fn sheet_export_url(url: &str) -> String {
+    let Some(rest) = url.strip_prefix("https://docs.google.com/spreadsheets/d/") else {
+        return url.to_string();
+    };
+    let id = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let gid = rest
+        .split(['?', '#', '&'])
+        .find_map(|part| part.strip_prefix("gid="));
+    match gid {
+        Some(gid) => {
+            format!("https://docs.google.com/spreadsheets/d/{id}/export?format=csv&gid={gid}")
+        }
+        None => format!("https://docs.google.com/spreadsheets/d/{id}/export?format=csv"),
+    }
+}
+
+/// Downloads a sheet and queues it like an uploaded one, so a shared sheet
+/// can be imported without saving it first.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn import_url(
+    _: Admin,
+    user: Option<User>,
+    body: web::Json<UrlImport>,
+    config: web::Data<Config>,
+    TargetDatabase(database): TargetDatabase,
+    limits: web::Data<ParseLimits>,
+    rules: web::Data<CardRules>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let store = store.with_database(database);
+    let body = body.into_inner();
+    let options = ImportOptions {
+        language: body.language,
+        nsfw: body.nsfw,
+        publish_at: body
+            .publish_at
+            .map(|at| DateTime::parse_rfc3339_str(&at))
+            .transpose()
+            .map_err(ErrorBadRequest)?,
+        id_mode: body.id_mode,
+        delimiter: body.delimiter.as_deref().map(parse_delimiter).transpose()?,
+        profile: body.profile,
+        mode: body.mode,
+    };
+    let url = sheet_export_url(&body.url);
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(ErrorBadRequest("only http and https URLs can be imported"));
+    }
+    let path = download(&url, &config).await?;
+    let source = ImportSource {
+        files: Vec::new(),
+        url: Some(body.url),
+        user: user.map(|u| u.0),
+    };
+    let job = ImportJob::create(
+        store.database(),
+        &config.import_dir,
+        Path::new(&path),
+        options,
+        source,
+    )
+    .await
+    .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+    Ok(HttpResponse::Accepted().json(queue_import(&store, job, **limits, &rules)))
+}
+
+/// Fetches `url` into the temp directory and returns where it was written.
+/// The body is written as it arrives and cut off past
+/// `Config::max_download_bytes`.
+pub(super) async fn download(url: &str, config: &Config) -> Result<String, AppError> {
+    let fetch_failed = |e: reqwest::Error| AppError::Upload(format!("could not fetch {url}: {e}"));
+    let client = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()
+        .map_err(fetch_failed)?;
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(fetch_failed)?;
+
+    let max_bytes = config.max_download_bytes;
+    let path = format!("{}/download-{}.csv", config.temp_dir, Uuid::new_v4());
+    let mut file = BufWriter::new(tokio::fs::File::create(&path).await?);
+    let mut written = 0;
+    let downloaded = async {
+        while let Some(chunk) = response.chunk().await.map_err(fetch_failed)? {
+            written += chunk.len() as u64;
+            if written > max_bytes {
+                return Err(AppError::Upload(format!(
+                    "{url} is larger than {max_bytes} bytes"
+                )));
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = downloaded {
+        let _ = fs::remove_file(&path);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+/// How long `import_url` waits for a download to finish.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// One run of the scheduled sync, kept as its history.
+#[derive(Debug, Serialize, Deserialize)]
+struct SyncRecord {
+    url: String,
+    started_at: DateTime,
+    finished_at: DateTime,
+    status: ImportStatus,
+    /// The import job the sheet went through, once it was fetched.
+    job: Option<Uuid>,
+    summary: Option<ImportSummary>,
+    error: Option<String>,
+}
+
+/// Re-imports the sheet named by `Config::sync_url`, so the library follows
+/// edits made to it. Sets and cards are refreshed in place, see `add_set`,
+/// and every run is recorded in `syncs`.
+pub(super) async fn sync_source() -> Result<(), ActixError> {
+    let config = config::get();
+    let Some(url) = &config.sync_url else {
+        return Ok(());
+    };
+    let url = sheet_export_url(url);
+    let store = Store::connect(config).await.map_err(AppError::from)?;
+    let database = store.database();
+    let started_at = DateTime::now();
+    let mut job_id = None;
+    let result = async {
+        let path = download(&url, config).await?;
+        let source = ImportSource {
+            url: Some(url.clone()),
+            ..ImportSource::default()
+        };
+        let job = ImportJob::create(
+            database,
+            &config.import_dir,
+            Path::new(&path),
+            ImportOptions::default(),
+            source,
+        )
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+        job_id = Some(job.uuid);
+        let limits = ParseLimits::from_config(config);
+        job.run(&store, limits, CardRules::from_config(config))
+            .await
+            .map_err(|e| ErrorInternalServerError(e.to_string()))
+    }
+    .await;
+    let record = SyncRecord {
+        url,
+        started_at,
+        finished_at: DateTime::now(),
+        status: match result {
+            Ok(_) => ImportStatus::Done,
+            Err(_) => ImportStatus::Failed,
+        },
+        job: job_id,
+        error: result.as_ref().err().map(|e| e.to_string()),
+        summary: result.ok(),
+    };
+    if let Some(summary) = &record.summary {
+        println!(
+            "Synced {} sets from {}: {} cards added, {} changed, {} removed",
+            summary.sets, record.url, summary.added, summary.changed, summary.removed
+        );
+    }
+    database
+        .collection::<SyncRecord>("syncs")
+        .insert_one(&record, None)
+        .await
+        .map_err(AppError::from)?;
+    match record.error {
+        Some(error) => Err(ErrorInternalServerError(error)),
+        None => Ok(()),
+    }
+}
+
+/// The most recent syncs, newest first.
+pub(super) async fn list_syncs(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<impl Responder, ActixError> {
+    let options = FindOptions::builder()
+        .sort(doc! { "started_at": -1 })
+        .limit(100)
+        .build();
+    let syncs: Vec<SyncRecord> = database
+        .collection::<SyncRecord>("syncs")
+        .find(None, options)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    Ok(web::Json(syncs))
+}
+
+/// How often `import_events` looks for progress.
+const IMPORT_POLL: Duration = Duration::from_secs(1);
+/// Most events `import_events` reads at once.
+const EVENT_PAGE: i64 = 500;
+
+/// Follows an import job as Server-Sent Events: a `set` event per set
+/// written and a `warning` per row the parser flagged, then `done` or
+/// `failed` once the job stops. Events carry their index as id, so a
+/// client reconnecting with `Last-Event-ID` only gets the ones it missed.
+pub(super) async fn import_events(
+    _: Admin,
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<HttpResponse, ActixError> {
+    let uuid = path.into_inner();
+    let jobs = database.collection::<ImportJob>("import_jobs");
+    let stored = database.collection::<StoredEvent>("import_events");
+    let filter = doc! { "uuid": uuid_bson(&uuid) };
+    jobs.find_one(filter.clone(), None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("import {uuid} not found")))?;
+    let sent = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .map_or(0, |last| last + 1);
+
+    let events = futures::stream::try_unfold(Some(sent), move |sent| {
+        let jobs = jobs.clone();
+        let stored = stored.clone();
+        let filter = filter.clone();
+        async move {
+            let Some(mut sent) = sent else {
+                return Ok(None);
+            };
+            loop {
+                // The status is read first, so a finished job has stored
+                // all its events by the time they are read.
+                let job = jobs
+                    .find_one(filter.clone(), None)
+                    .await
+                    .map_err(AppError::from)?
+                    .ok_or_else(|| ErrorNotFound(format!("import {uuid} not found")))?;
+                let options = FindOptions::builder()
+                    .sort(doc! { "index": 1 })
+                    .limit(EVENT_PAGE)
+                    .build();
+                let page: Vec<StoredEvent> = stored
+                    .find(
+                        doc! { "job": uuid_bson(&uuid), "index": { "$gte": sent as i64 } },
+                        options,
+                    )
+                    .await
+                    .map_err(AppError::from)?
+                    .try_collect()
+                    .await
+                    .map_err(AppError::from)?;
+                let mut chunk = String::new();
+                for stored in &page {
+                    let data =
+                        serde_json::to_string(&stored.event).map_err(ErrorInternalServerError)?;
+                    chunk.push_str(&format!(
+                        "id: {}\nevent: {}\ndata: {data}\n\n",
+                        stored.index,
+                        stored.event.name()
+                    ));
+                    sent = stored.index + 1;
+                }
+                let finished = match job.status {
+                    _ if page.len() as i64 == EVENT_PAGE => None,
+                    ImportStatus::Running => None,
+                    ImportStatus::Done => Some("done"),
+                    ImportStatus::Failed => Some("failed"),
+                    ImportStatus::Cancelled => Some("cancelled"),
+                };
+                let next = match finished {
+                    None => Some(sent),
+                    Some(status) => {
+                        let data = serde_json::json!({ "status": job.status, "error": job.error });
+                        chunk.push_str(&format!("event: {status}\ndata: {data}\n\n"));
+                        None
+                    }
+                };
+                if !chunk.is_empty() {
+                    return Ok::<_, ActixError>(Some((Bytes::from(chunk), next)));
+                }
+                actix_web::rt::time::sleep(IMPORT_POLL).await;
+            }
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events))
+}
+
+pub(super) const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long files may sit in each working directory before the retention
+/// job removes them.
+#[derive(Debug, Clone, Copy)]
+struct Retention {
+    /// Upload scratch space.
+    temp: Duration,
+    /// Sheets of failed imports, kept so the job can be resumed.
+    imports: Duration,
+    /// Export snapshots no longer being served.
+    exports: Duration,
+}
+
+impl Retention {
+    fn from_config(config: &Config) -> Self {
+        let hours = |hours: u64| Duration::from_secs(hours * 60 * 60);
+        Retention {
+            temp: hours(config.temp_retention_hours),
+            imports: hours(config.import_retention_hours),
+            exports: hours(config.export_retention_hours),
+        }
+    }
+
+    /// Each working directory `config` names, with its window.
+    fn directories<'a>(&self, config: &'a Config) -> [(&'a str, Duration); 3] {
+        [
+            (&config.temp_dir, self.temp),
+            (&config.import_dir, self.imports),
+            (&config.export_dir, self.exports),
+        ]
+    }
+}
+
+/// Files directly inside `dir`, with their metadata. A missing directory
+/// has none.
+fn directory_files(dir: &str) -> Vec<(PathBuf, fs::Metadata)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let metadata = entry.metadata().ok()?;
+            metadata
+                .is_file()
+                .then(|| (Path::new(dir).join(entry.file_name()), metadata))
+        })
+        .collect()
+}
+
+fn is_expired(metadata: &fs::Metadata, window: Duration) -> bool {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > window)
+}
+
+/// Removes files older than their directory's retention window, sparing
+/// the sheets of imports still running.
+pub(super) async fn enforce_retention() -> Result<(), ActixError> {
+    let config = config::get();
+    let retention = Retention::from_config(config);
+    let database = database().await.map_err(AppError::from)?;
+    let running: Vec<PathBuf> = database
+        .collection::<ImportJob>("import_jobs")
+        .distinct("path", doc! { "status": "running" }, None)
+        .await
+        .map_err(AppError::from)?
+        .iter()
+        .filter_map(|path| path.as_str().map(PathBuf::from))
+        .collect();
+    let mut removed = 0;
+    for (dir, window) in retention.directories(config) {
+        for (path, metadata) in directory_files(dir) {
+            if is_expired(&metadata, window)
+                && !running.contains(&path)
+                && fs::remove_file(&path).is_ok()
+            {
+                removed += 1;
+            }
+        }
+    }
+    if removed > 0 {
+        println!("Retention removed {removed} files");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct DirectoryUsage {
+    path: String,
+    files: usize,
+    bytes: u64,
+    oldest: Option<DateTime>,
+    retention_hours: u64,
+    /// Files past retention, removed on the next maintenance run.
+    expired: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct StorageReport {
+    directories: Vec<DirectoryUsage>,
+    /// `dataSize`, `storageSize` and `indexSize` from MongoDB's `dbStats`.
+    database: Document,
+}
+
+/// What the working directories and the database currently hold.
+pub(super) async fn storage_report(
+    _: Admin,
+    TargetDatabase(database): TargetDatabase,
+    config: web::Data<Config>,
+) -> Result<impl Responder, ActixError> {
+    let retention = Retention::from_config(&config);
+    let directories = retention
+        .directories(&config)
+        .into_iter()
+        .map(|(path, window)| {
+            let files = directory_files(path);
+            DirectoryUsage {
+                path: path.to_string(),
+                files: files.len(),
+                bytes: files.iter().map(|(_, m)| m.len()).sum(),
+                oldest: files
+                    .iter()
+                    .filter_map(|(_, m)| m.modified().ok())
+                    .min()
+                    .map(DateTime::from_system_time),
+                retention_hours: window.as_secs() / (60 * 60),
+                expired: files.iter().filter(|(_, m)| is_expired(m, window)).count(),
+            }
+        })
+        .collect();
+    let stats = database
+        .run_command(doc! { "dbStats": 1 }, None)
+        .await
+        .map_err(AppError::from)?;
+    let database = ["dataSize", "storageSize", "indexSize"]
+        .into_iter()
+        .filter_map(|key| Some((key.to_string(), stats.get(key)?.clone())))
+        .collect();
+    Ok(web::Json(StorageReport {
+        directories,
+        database,
+    }))
+}
+
+#[derive(Debug, Default, Serialize)]
+struct SuiteCounts {
+    prompt: usize,
+    response: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct SetImport {
+    name: String,
+    cards: usize,
+    suites: SuiteCounts,
+    /// The stored id and how the stored cards compare to the sheet's.
+    #[serde(flatten)]
+    changes: SetChanges,
+}
+
+/// What became of one uploaded sheet, with the rows the parser passed over.
+#[derive(Debug, Serialize)]
+struct FileImport {
+    file: String,
+    sets: Vec<SetImport>,
+    #[serde(flatten)]
+    parse: ParseReport,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct UploadReport {
+    /// Nothing was written, see `UploadQuery::dry_run`.
+    dry_run: bool,
+    sets: usize,
+    cards: usize,
+    files: Vec<FileImport>,
+}
+
+/// Parsed sets an upload may queue ahead of the database.
+const IMPORT_QUEUE: usize = 4;
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct UploadQuery {
+    /// Parses and validates the sheets and reports on them as usual, but
+    /// writes nothing to the library.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Stores an uploaded sheet, deck or archive of them and imports what it
+/// holds, each set and its cards in their own transaction. An archive
+/// gives one report per sheet in it. The sheets of an archive or workbook
+/// are imported concurrently, within `limit` like separate uploads.
+#[allow(clippy::too_many_arguments)]
+async fn import_file(
+    store: &Store,
+    config: &Config,
+    file: TempFile,
+    options: &ImportOptions,
+    limit: &UploadLimit,
+    limits: ParseLimits,
+    rules: &CardRules,
+    dry_run: bool,
+) -> Result<Vec<FileImport>, ActixError> {
+    let types = CardTypes::load(store.database())
+        .await
+        .map_err(AppError::from)?;
+    let profile = options.profile(store.database()).await?;
+    // Only the last component of the client's name is used, so it cannot
+    // point outside the temp directory, and it is saved under a fresh prefix
+    // so two uploads of the same name do not overwrite each other.
+    let name = file
+        .file_name
+        .as_deref()
+        .and_then(|name| Path::new(name).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| AppError::Upload("uploaded file has no name".to_string()))?;
+    let is_type = |essence: &str, extension: &str| {
+        file.content_type
+            .as_ref()
+            .is_some_and(|mime| mime.essence_str() == essence)
+            || Path::new(&name)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+    };
+    // Decks from community tools are JSON, anything else is a sheet.
+    let json = is_type("application/json", "json");
+    let zip = is_type("application/zip", "zip");
+    let workbook = is_type(
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "xlsx",
+    );
+    let path = format!("{}/{}-{name}", config.temp_dir, Uuid::new_v4());
+    file.file
+        .persist(&path)
+        .map_err(|e| AppError::Io(e.error))?;
+    let importer = &Importer {
+        store,
+        options,
+        limit,
+        limits,
+        rules,
+        types,
+        profile,
+        dry_run,
+    };
+    if workbook {
+        let worksheets = web::block(move || {
+            let worksheets = read_workbook(&path);
+            let _ = fs::remove_file(&path);
+            worksheets
+        })
+        .await??;
+        let imports = worksheets.into_iter().map(|(worksheet, rows)| {
+            importer.import(format!("{name}/{worksheet}"), Sheet::Worksheet(rows))
+        });
+        return try_join_all(imports).await;
+    }
+    if !zip {
+        let sheet = match json {
+            true => Sheet::Json(path),
+            false => Sheet::file(path)?,
+        };
+        return Ok(vec![importer.import(name, sheet).await?]);
+    }
+    let config = config.clone();
+    let entries = web::block(move || extract_archive(&path, &config)).await??;
+    let imports = entries.into_iter().map(|(entry, path)| {
+        let file = format!("{name}/{entry}");
+        async move { importer.import(file, Sheet::file(path)?).await }
+    });
+    try_join_all(imports).await
+}
+
+/// Unpacks the sheets and decks of an uploaded archive into the temp
+/// directory under fresh names, then removes the archive. Returns each
+/// entry's name in the archive with the path it was written to. Other
+/// entries are ignored, and the entry count and unpacked size are capped
+/// by `Config::max_archive_entries` and `Config::max_archive_bytes`.
+fn extract_archive(path: &str, config: &Config) -> Result<Vec<(String, String)>, AppError> {
+    let invalid = |e: zip::result::ZipError| AppError::Upload(format!("invalid archive: {e}"));
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(invalid)?;
+    let max_entries = config.max_archive_entries;
+    let max_bytes = config.max_archive_bytes;
+    let mut budget: u64 = max_bytes;
+    let mut extracted = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(invalid)?;
+        // Folders, hidden files and macOS resource forks hold no sheets.
+        let Some(file_name) = entry
+            .enclosed_name()
+            .and_then(|name| name.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+        else {
+            continue;
+        };
+        let sheet = Path::new(&file_name)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("csv") || ext.eq_ignore_ascii_case("json"));
+        if entry.is_dir()
+            || !sheet
+            || file_name.starts_with('.')
+            || entry.name().starts_with("__MACOSX/")
+        {
+            continue;
+        }
+        if extracted.len() == max_entries {
+            return Err(AppError::Upload(format!(
+                "archive has more than {max_entries} sheets"
+            )));
+        }
+        let target = format!("{}/{}-{file_name}", config.temp_dir, Uuid::new_v4());
+        let written = std::io::copy(
+            &mut std::io::Read::take(&mut entry, budget + 1),
+            &mut File::create(&target)?,
+        )?;
+        if written > budget {
+            let _ = fs::remove_file(&target);
+            return Err(AppError::Upload(format!(
+                "archive unpacks to more than {max_bytes} bytes"
+            )));
+        }
+        budget -= written;
+        extracted.push((entry.name().to_string(), target));
+    }
+    let _ = fs::remove_file(path);
+    Ok(extracted)
+}
+
+/// What one import reads its sets from. Files are removed once read.
+enum Sheet {
+    Csv(String),
+    Json(String),
+    /// The rows of a workbook's worksheet, see `read_workbook`.
+    Worksheet(Vec<csv::StringRecord>),
+}
+
+impl Sheet {
+    /// The file at `path`, as a deck if its content looks like JSON.
+    fn file(path: String) -> Result<Sheet, AppError> {
+        match looks_like_json(&path)? {
+            true => Ok(Sheet::Json(path)),
+            false => Ok(Sheet::Csv(path)),
+        }
+    }
+}
+
+/// What it takes to import the sheets of one upload.
+struct Importer<'a> {
+    store: &'a Store,
+    options: &'a ImportOptions,
+    limit: &'a UploadLimit,
+    limits: ParseLimits,
+    rules: &'a CardRules,
+    types: CardTypes,
+    profile: ColumnProfile,
+    dry_run: bool,
+}
+
+impl Importer<'_> {
+    /// Parses a sheet, deck or worksheet and stores every set in it.
+    async fn import(&self, file: String, sheet: Sheet) -> Result<FileImport, ActixError> {
+        let _permit = self
+            .limit
+            .0
+            .acquire()
+            .await
+            .map_err(ErrorInternalServerError)?;
+        let limits = self.limits;
+        let rules = self.rules.clone();
+        let types = self.types.clone();
+        let profile = self.profile.clone();
+        let options = self.options.clone();
+        // The sheet is parsed on the blocking pool and each set is stored as
+        // soon as it is complete, so a large sheet is never held in memory
+        // whole. A failure part way keeps the sets already stored.
+        let (sender, mut receiver) = mpsc::channel(IMPORT_QUEUE);
+        let parser = web::block(move || {
+            let stream = |sets: &mut dyn Iterator<Item = Result<Set, AppError>>| {
+                for set in sets {
+                    if sender.blocking_send(set).is_err() {
+                        break;
+                    }
+                }
+            };
+            let (parse, path) = match sheet {
+                Sheet::Csv(path) => {
+                    let parse = transcode_to_utf8(&path).and_then(|encoding| {
+                        let delimiter = options.delimiter(&path)?;
+                        let file = File::open(&path)?;
+                        let mut reader = SetReader::delimited(file, delimiter, limits, types)
+                            .with_rules(rules)
+                            .with_profile(profile)
+                            .with_mode(options.mode);
+                        stream(&mut reader);
+                        let mut report = reader.into_report();
+                        report.transcoded(encoding);
+                        Ok(report)
+                    });
+                    (parse, path)
+                }
+                Sheet::Json(path) => {
+                    // A JSON deck is read whole, there is no row to stop at.
+                    let parse = transcode_to_utf8(&path).and_then(|encoding| {
+                        let (sets, mut report) =
+                            parse_json_deck(File::open(&path)?, &limits, &rules)?;
+                        stream(&mut sets.into_iter().map(Ok));
+                        report.transcoded(encoding);
+                        Ok(report)
+                    });
+                    (parse, path)
+                }
+                Sheet::Worksheet(rows) => {
+                    let mut reader = SetReader::from_rows(rows, limits, types)
+                        .with_rules(rules)
+                        .with_profile(profile)
+                        .with_mode(options.mode);
+                    stream(&mut reader);
+                    return Ok(reader.into_report());
+                }
+            };
+            if let Err(e) = fs::remove_file(&path) {
+                eprintln!("failed to delete {path}: {e}");
+            }
+            parse
+        });
+
+        let mut imported = Vec::new();
+        while let Some(set) = receiver.recv().await {
+            let mut set = set?;
+            self.options.apply(&mut set);
+            let changes = if self.dry_run {
+                preview_set(self.store.database(), &set)
+                    .await
+                    .map_err(AppError::from)?
+            } else {
+                self.store.add_set(&set).await?
+            };
+            let mut suites = SuiteCounts::default();
+            for card in &set.cards {
+                match card.suite {
+                    Suite::Prompt => suites.prompt += 1,
+                    Suite::Response => suites.response += 1,
+                }
+            }
+            imported.push(SetImport {
+                cards: set.cards.len(),
+                name: set.name,
+                suites,
+                changes,
+            });
+        }
+        // An early return above drops the receiver, which stops the parser.
+        let parse = parser.await??;
+        Ok(FileImport {
+            file,
+            sets: imported,
+            parse,
+        })
+    }
+}
+
+/// Imports every uploaded file, several at a time within the upload limit.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn upload_csv(
+    _: Admin,
+    MultipartForm(form): MultipartForm<UploadForm>,
+    query: web::Query<UploadQuery>,
+    user: Option<User>,
+    limit: web::Data<UploadLimit>,
+    limits: web::Data<ParseLimits>,
+    rules: web::Data<CardRules>,
+    config: web::Data<Config>,
+    TargetDatabase(database): TargetDatabase,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let store = store.with_database(database);
+    let options = ImportOptions::from_form(&form)?;
+    let mut record = ImportRecord {
+        uuid: Uuid::new_v4(),
+        source: ImportSource {
+            files: form
+                .files
+                .iter()
+                .filter_map(|file| file.file_name.clone())
+                .collect(),
+            url: None,
+            user: user.map(|u| u.0),
+        },
+        status: ImportStatus::Done,
+        summary: ImportSummary::default(),
+        warnings: Vec::new(),
+        warning_count: 0,
+        error: None,
+        started_at: DateTime::now(),
+        finished_at: DateTime::now(),
+    };
+    let imports = form.files.into_iter().map(|file| {
+        import_file(
+            &store,
+            &config,
+            file,
+            &options,
+            &limit,
+            **limits,
+            &rules,
+            query.dry_run,
+        )
+    });
+    let imported = try_join_all(imports).await;
+    // A dry run loads nothing, so it is not part of the history.
+    if !query.dry_run {
+        record.finished_at = DateTime::now();
+        match &imported {
+            Ok(files) => {
+                for file in files.iter().flatten() {
+                    for set in &file.sets {
+                        record.summary.record(set.cards, set.changes);
+                    }
+                    record.warn(&file.parse.warnings);
+                }
+            }
+            Err(e) => {
+                record.status = ImportStatus::Failed;
+                record.error = Some(e.to_string());
+            }
+        }
+        record.save(store.database()).await;
+    }
+    let mut report = UploadReport {
+        dry_run: query.dry_run,
+        ..UploadReport::default()
+    };
+    for file in imported?.into_iter().flatten() {
+        report.sets += file.sets.len();
+        report.cards += file.sets.iter().map(|set| set.cards).sum::<usize>();
+        report.files.push(file);
+    }
+    if query.dry_run {
+        return Ok(HttpResponse::Ok().json(report));
+    }
+    Ok(HttpResponse::Created().json(report))
+}
+
+/// What an append-only import wrote to a set.
+#[derive(Debug, Default, Serialize)]
+struct DeltaReport {
+    added: Vec<CardSnapshot>,
+    /// Incoming cards whose normalized text the set already had.
+    skipped: usize,
+}
+
+/// Inserts the cards of `incoming` whose suite and normalized text `set`
+/// does not have yet, leaving everything already stored untouched.
+async fn append_cards(
+    database: &Database,
+    actor: Option<&User>,
+    set: &Set,
+    incoming: Vec<Card>,
+) -> Result<DeltaReport, ActixError> {
+    let cards: Collection<Card> = database.collection("cards");
+    let mut known: HashSet<(Suite, String)> = set_cards(&cards, &set.uuid)
+        .await?
+        .into_iter()
+        .map(|card| (card.suite, normalize_text(&card.text)))
+        .collect();
+    let mut report = DeltaReport::default();
+    let mut added = Vec::new();
+    for mut card in incoming {
+        if card.text.trim().is_empty() || !known.insert((card.suite, normalize_text(&card.text))) {
+            report.skipped += 1;
+            continue;
+        }
+        card.set_uuid = set.uuid;
+        card.nsfw = set.nsfw;
+        added.push(card);
+    }
+    if !added.is_empty() {
+        cards
+            .insert_many(&added, None)
+            .await
+            .map_err(AppError::from)?;
+        snapshot_set(database, set.uuid, "append")
+            .await
+            .map_err(AppError::from)?;
+        let uuids: Vec<Bson> = added.iter().map(|card| uuid_bson(&card.uuid)).collect();
+        audit(
+            database,
+            actor,
+            "append",
+            "set",
+            Some(set.uuid),
+            doc! {},
+            doc! { "added": uuids, "skipped": report.skipped as i64 },
+        )
+        .await;
+    }
+    report.added = added.into_iter().map(CardSnapshot::from).collect();
+    Ok(report)
+}
+
+/// Reads a CSV fragment of `suite,text[,special]` rows, with or without a
+/// header row, into cards for `set_uuid`. Suites are matched case
+/// insensitively against the built-in suites and card type labels.
+fn parse_fragment(
+    fragment: &str,
+    set_uuid: Uuid,
+    limits: &ParseLimits,
+    types: &CardTypes,
+    rules: &CardRules,
+) -> Result<Vec<Card>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(fragment.as_bytes());
+    let mut cards = Vec::new();
+    let mut record = csv::StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let row = record.position().map_or(0, |p| p.line() as usize);
+        limits.check_record(row, &record)?;
+        let label = parse_field(&record, 0);
+        let lower = label.to_lowercase();
+        if row == 1 && (lower == "suite" || lower == "set") {
+            continue;
+        }
+        let resolved = match lower.as_str() {
+            "prompt" => Some((Suite::Prompt, None)),
+            "response" => Some((Suite::Response, None)),
+            _ => types
+                .0
+                .values()
+                .find(|t| t.label.to_lowercase() == lower)
+                .map(|t| (t.suite, Some(t))),
+        };
+        let Some((suite, card_type)) = resolved else {
+            return Err(format!("row {row}: unknown suite {label:?}").into());
+        };
+        let special = match (parse_field(&record, 2), card_type) {
+            ("", Some(card_type)) => card_type.special.as_str(),
+            (special, _) => special,
+        };
+        let mut card = Card::new(
+            set_uuid,
+            suite,
+            parse_field(&record, 1).to_string(),
+            special.to_string(),
+        );
+        card.card_type = card_type.map(|t| t.name.clone());
+        rules.prepare(&mut card);
+        cards.push(card);
+    }
+    Ok(cards)
+}
+
+/// Adds the cards of a small `suite,text,special` CSV fragment to a set,
+/// skipping any it already has, without the full multi-set sheet layout.
+pub(super) async fn append_fragment(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    body: String,
+    limits: web::Data<ParseLimits>,
+    rules: web::Data<CardRules>,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<impl Responder, ActixError> {
+    let set = find_set(&database.collection("sets"), &path).await?;
+    let types = CardTypes::load(&database).await.map_err(AppError::from)?;
+    let cards = parse_fragment(&body, set.uuid, &limits, &types, &rules)
+        .map_err(|e| ErrorUnprocessableEntity(e.to_string()))?;
+    Ok(web::Json(
+        append_cards(&database, user.as_ref(), &set, cards).await?,
+    ))
+}
+
+#[derive(Debug, MultipartForm)]
+pub(super) struct DeltaForm {
+    file: TempFile,
+}
+
+/// Treats an uploaded sheet as an append-only delta for a stored set: only
+/// cards the set does not have yet are added, and the response lists them.
+pub(super) async fn import_delta(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    MultipartForm(form): MultipartForm<DeltaForm>,
+    limits: web::Data<ParseLimits>,
+    rules: web::Data<CardRules>,
+    TargetDatabase(database): TargetDatabase,
+) -> Result<impl Responder, ActixError> {
+    let set = find_set(&database.collection("sets"), &path).await?;
+    let types = CardTypes::load(&database).await.map_err(AppError::from)?;
+    let limits = **limits;
+    let rules = rules.into_inner();
+    let sheet = form.file.file.into_temp_path();
+    let parsed = web::block(move || {
+        let path = sheet.to_string_lossy().into_owned();
+        parse_csv_file(&path, &limits, &types, &rules)
+    })
+    .await??;
+    // A sheet of several sets contributes the one named like the target.
+    let incoming = match parsed.len() {
+        1 => parsed.into_iter().next(),
+        _ => parsed
+            .into_iter()
+            .find(|s| normalize_text(&s.name) == normalize_text(&set.name)),
+    }
+    .ok_or_else(|| ErrorUnprocessableEntity(format!("sheet has no set named {:?}", set.name)))?;
+    // A delta does not add editions, its cards only join the ones the set
+    // already has.
+    let stored: Vec<Edition> = database
+        .collection::<Edition>("editions")
+        .find(doc! { "set_uuid": uuid_bson(&set.uuid) }, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    let (edition_ids, _) = match_editions(&stored, &incoming.editions);
+    let mut cards = incoming.cards;
+    for card in &mut cards {
+        card.editions = card
+            .editions
+            .iter()
+            .filter_map(|id| edition_ids.get(id))
+            .filter(|id| stored.iter().any(|edition| edition.uuid == **id))
+            .copied()
+            .collect();
+    }
+    Ok(web::Json(
+        append_cards(&database, user.as_ref(), &set, cards).await?,
+    ))
+}
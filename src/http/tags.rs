@@ -0,0 +1,394 @@
+use super::*;
+
+/// Lowercases a tag and joins its words with dashes, so `Topical 2024`
+/// and `topical-2024` are the same tag.
+pub(super) fn normalize_tag(tag: &str) -> Result<String, ActixError> {
+    let tag = tag
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    let valid = !tag.is_empty()
+        && tag.len() <= 64
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    match valid {
+        true => Ok(tag),
+        false => Err(ErrorBadRequest(format!("invalid tag {tag:?}"))),
+    }
+}
+
+const NSFW_TAG: &str = "nsfw";
+
+/// Update adding or removing `tag`; the `nsfw` tag also moves the flag.
+fn tag_update(tag: String, add: bool) -> Document {
+    let mut update = match add {
+        true => doc! { "$addToSet": { "tags": &tag } },
+        false => doc! { "$pull": { "tags": &tag } },
+    };
+    if tag == NSFW_TAG {
+        update.insert("$set", doc! { "nsfw": add });
+    }
+    update
+}
+
+/// Adds or removes `tag` on the document with `uuid` in `collection`.
+async fn update_tag(
+    database: &Database,
+    actor: Option<&User>,
+    collection: &str,
+    uuid: &Uuid,
+    tag: &str,
+    add: bool,
+) -> Result<HttpResponse, ActixError> {
+    let update = tag_update(normalize_tag(tag)?, add);
+    let result = database
+        .collection::<Document>(collection)
+        .update_one(doc! { "uuid": uuid_bson(uuid) }, update.clone(), None)
+        .await
+        .map_err(AppError::from)?;
+    if result.matched_count == 0 {
+        return Err(ErrorNotFound(format!("{uuid} not found")));
+    }
+    audit(
+        database,
+        actor,
+        "tag",
+        collection.trim_end_matches('s'),
+        Some(*uuid),
+        doc! {},
+        doc! { "update": update },
+    )
+    .await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub(super) async fn tag_card(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(Uuid, String)>,
+    store: web::Data<Store>,
+) -> Result<HttpResponse, ActixError> {
+    update_tag(
+        store.database(),
+        user.as_ref(),
+        "cards",
+        &path.0,
+        &path.1,
+        true,
+    )
+    .await
+}
+
+pub(super) async fn untag_card(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(Uuid, String)>,
+    store: web::Data<Store>,
+) -> Result<HttpResponse, ActixError> {
+    update_tag(
+        store.database(),
+        user.as_ref(),
+        "cards",
+        &path.0,
+        &path.1,
+        false,
+    )
+    .await
+}
+
+pub(super) async fn tag_set(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(Uuid, String)>,
+    store: web::Data<Store>,
+) -> Result<HttpResponse, ActixError> {
+    update_tag(
+        store.database(),
+        user.as_ref(),
+        "sets",
+        &path.0,
+        &path.1,
+        true,
+    )
+    .await
+}
+
+pub(super) async fn untag_set(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<(Uuid, String)>,
+    store: web::Data<Store>,
+) -> Result<HttpResponse, ActixError> {
+    update_tag(
+        store.database(),
+        user.as_ref(),
+        "sets",
+        &path.0,
+        &path.1,
+        false,
+    )
+    .await
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TagAction {
+    Add,
+    Remove,
+}
+
+/// Which cards a bulk operation applies to; every given condition must hold.
+#[derive(Debug, Default, Deserialize)]
+struct CardSelection {
+    #[serde(default)]
+    sets: Vec<Uuid>,
+    suite: Option<Suite>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    exclude_tags: Vec<String>,
+    /// Case-insensitive substring of the card text.
+    text: Option<String>,
+}
+
+impl CardSelection {
+    fn filter(&self) -> Result<Document, ActixError> {
+        let tags = normalize_tags(&self.tags)?;
+        let exclude_tags = normalize_tags(&self.exclude_tags)?;
+        let mut filter = card_filter(&self.sets, &tags, &exclude_tags);
+        if let Some(suite) = self.suite {
+            filter.insert("suite", bson_value(&suite)?);
+        }
+        if let Some(text) = &self.text {
+            filter.insert(
+                "text",
+                doc! { "$regex": regex_escape(text), "$options": "i" },
+            );
+        }
+        Ok(filter)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct BulkTag {
+    tag: String,
+    action: TagAction,
+    #[serde(default)]
+    selection: CardSelection,
+}
+
+#[derive(Debug, Serialize)]
+struct BulkResult {
+    matched: u64,
+    modified: u64,
+}
+
+/// Applies or strips a tag on every card matching the selection at once.
+pub(super) async fn bulk_tag_cards(
+    _: Admin,
+    user: Option<User>,
+    body: web::Json<BulkTag>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let update = tag_update(
+        normalize_tag(&body.tag)?,
+        matches!(body.action, TagAction::Add),
+    );
+    let filter = body.selection.filter()?;
+    let database = store.database();
+    let result = database
+        .collection::<Card>("cards")
+        .update_many(filter.clone(), update.clone(), None)
+        .await
+        .map_err(AppError::from)?;
+    audit(
+        database,
+        user.as_ref(),
+        "tag",
+        "card",
+        None,
+        doc! {},
+        doc! { "filter": filter, "update": update, "modified": result.modified_count as i64 },
+    )
+    .await;
+    Ok(web::Json(BulkResult {
+        matched: result.matched_count,
+        modified: result.modified_count,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct BulkRating {
+    rating: Rating,
+    #[serde(default)]
+    selection: CardSelection,
+}
+
+/// Rates every card matching the selection at once. Explicit cards are
+/// flagged NSFW too, and milder ones unflagged, so safe mode agrees with
+/// the rating.
+pub(super) async fn bulk_rate_cards(
+    _: Admin,
+    user: Option<User>,
+    body: web::Json<BulkRating>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let update = doc! { "$set": {
+        "rating": bson_value(&body.rating)?,
+        "nsfw": body.rating == Rating::Explicit,
+    } };
+    let filter = body.selection.filter()?;
+    let result = store
+        .cards()
+        .update_many(filter.clone(), update.clone(), None)
+        .await
+        .map_err(AppError::from)?;
+    audit(
+        store.database(),
+        user.as_ref(),
+        "rate",
+        "card",
+        None,
+        doc! {},
+        doc! { "filter": filter, "update": update, "modified": result.modified_count as i64 },
+    )
+    .await;
+    Ok(web::Json(BulkResult {
+        matched: result.matched_count,
+        modified: result.modified_count,
+    }))
+}
+
+/// A tag admins have vetted and described, as opposed to free-form tags
+/// that only exist on the documents carrying them.
+#[derive(Debug, Serialize, Deserialize)]
+struct CuratedTag {
+    name: String,
+    description: String,
+    /// Groups related tags, such as "holiday" and "halloween" under
+    /// "theme" or "politics" under "content".
+    #[serde(default)]
+    category: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct TagUsage {
+    name: String,
+    curated: bool,
+    description: Option<String>,
+    category: Option<String>,
+    cards: i64,
+    sets: i64,
+}
+
+/// Lists every tag in use or curated, with how many cards and sets carry it.
+pub(super) async fn list_tags(store: web::Data<Store>) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let mut usage: HashMap<String, TagUsage> = HashMap::new();
+    let curated: Vec<CuratedTag> = database
+        .collection::<CuratedTag>("curated_tags")
+        .find(doc! {}, None)
+        .await
+        .map_err(AppError::from)?
+        .try_collect()
+        .await
+        .map_err(AppError::from)?;
+    for tag in curated {
+        let entry = usage.entry(tag.name.clone()).or_default();
+        entry.curated = true;
+        entry.description = Some(tag.description);
+        entry.category = tag.category;
+    }
+    let by_tag = || {
+        vec![
+            doc! { "$unwind": "$tags" },
+            doc! { "$group": { "_id": "$tags", "count": { "$sum": 1 } } },
+        ]
+    };
+    for count in group_counts(database, "cards", by_tag()).await? {
+        usage.entry(count.key).or_default().cards = count.count;
+    }
+    for count in group_counts(database, "sets", by_tag()).await? {
+        usage.entry(count.key).or_default().sets = count.count;
+    }
+
+    let mut tags: Vec<TagUsage> = usage
+        .into_iter()
+        .map(|(name, usage)| TagUsage { name, ..usage })
+        .collect();
+    tags.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(web::Json(tags))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CurateTag {
+    description: String,
+    category: Option<String>,
+}
+
+pub(super) async fn curate_tag(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<String>,
+    body: web::Json<CurateTag>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let body = body.into_inner();
+    let tag = CuratedTag {
+        name: normalize_tag(&path)?,
+        description: body.description,
+        category: body.category.as_deref().map(normalize_tag).transpose()?,
+    };
+    let database = store.database();
+    let before = database
+        .collection::<CuratedTag>("curated_tags")
+        .find_one_and_replace(
+            doc! { "name": &tag.name },
+            &tag,
+            FindOneAndReplaceOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map_err(AppError::from)?;
+    let changes = field_changes(before.as_ref(), Some(&tag))?;
+    audit(
+        database,
+        user.as_ref(),
+        "curate",
+        "tag",
+        None,
+        changes,
+        doc! { "name": &tag.name },
+    )
+    .await;
+    Ok(web::Json(tag))
+}
+
+pub(super) async fn uncurate_tag(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<String>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let deleted = database
+        .collection::<CuratedTag>("curated_tags")
+        .find_one_and_delete(doc! { "name": normalize_tag(&path)? }, None)
+        .await
+        .map_err(AppError::from)?;
+    if let Some(tag) = deleted {
+        let changes = field_changes(Some(&tag), None)?;
+        audit(
+            database,
+            user.as_ref(),
+            "uncurate",
+            "tag",
+            None,
+            changes,
+            doc! { "name": &tag.name },
+        )
+        .await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
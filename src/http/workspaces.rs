@@ -0,0 +1,436 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum WorkspaceStatus {
+    Open,
+    InReview,
+    Approved,
+    Rejected,
+}
+
+/// One staged edit to a set's cards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(super) enum CardChange {
+    Add {
+        suite: Suite,
+        text: String,
+        #[serde(default)]
+        special: String,
+    },
+    Update {
+        card: Uuid,
+        suite: Option<Suite>,
+        text: Option<String>,
+        special: Option<String>,
+    },
+    Remove {
+        card: Uuid,
+    },
+}
+
+/// A batch of changes to a published set, applied only once an admin
+/// approves it.
+#[derive(Debug, Serialize, Deserialize)]
+struct Workspace {
+    uuid: Uuid,
+    set_uuid: Uuid,
+    author: String,
+    status: WorkspaceStatus,
+    changes: Vec<CardChange>,
+    #[serde(default)]
+    reviewer_note: Option<String>,
+    created_at: DateTime,
+    updated_at: DateTime,
+}
+
+async fn find_workspace(
+    workspaces: &Collection<Workspace>,
+    id: &Uuid,
+) -> Result<Workspace, ActixError> {
+    workspaces
+        .find_one(doc! { "uuid": uuid_bson(id) }, None)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorNotFound(format!("workspace {id} not found")))
+}
+
+/// Moves a workspace from `from` to `to`, failing if it is no longer in `from`.
+async fn transition_workspace(
+    workspaces: &Collection<Workspace>,
+    id: &Uuid,
+    from: WorkspaceStatus,
+    mut update: Document,
+    session: Option<&mut ClientSession>,
+) -> Result<Workspace, ActixError> {
+    update.insert("updated_at", DateTime::now());
+    let filter = doc! { "uuid": uuid_bson(id), "status": bson_value(&from)? };
+    let update = doc! { "$set": update };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    let result = match session {
+        Some(session) => {
+            workspaces
+                .find_one_and_update_with_session(filter, update, options, session)
+                .await
+        }
+        None => {
+            workspaces
+                .find_one_and_update(filter, update, options)
+                .await
+        }
+    };
+    result
+        .map_err(AppError::from)?
+        .ok_or_else(|| ErrorConflict(format!("workspace {id} is not {from:?}")))
+}
+
+pub(super) async fn create_workspace(
+    path: web::Path<Uuid>,
+    user: User,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let set = find_set(&database.collection("sets"), &path).await?;
+    let now = DateTime::now();
+    let workspace = Workspace {
+        uuid: Uuid::new_v4(),
+        set_uuid: set.uuid,
+        author: user.0,
+        status: WorkspaceStatus::Open,
+        changes: Vec::new(),
+        reviewer_note: None,
+        created_at: now,
+        updated_at: now,
+    };
+    database
+        .collection::<Workspace>("workspaces")
+        .insert_one(&workspace, None)
+        .await
+        .map_err(AppError::from)?;
+    Ok(HttpResponse::Created().json(workspace))
+}
+
+pub(super) async fn get_workspace(
+    path: web::Path<Uuid>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    Ok(web::Json(
+        find_workspace(&database.collection("workspaces"), &path).await?,
+    ))
+}
+
+pub(super) async fn stage_change(
+    path: web::Path<Uuid>,
+    body: web::Json<CardChange>,
+    user: User,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let workspaces: Collection<Workspace> = database.collection("workspaces");
+    let result = workspaces
+        .update_one(
+            doc! { "uuid": uuid_bson(&path), "author": &user.0, "status": "open" },
+            doc! {
+                "$push": { "changes": bson_value(&body.into_inner())? },
+                "$set": { "updated_at": DateTime::now() },
+            },
+            None,
+        )
+        .await
+        .map_err(AppError::from)?;
+    if result.matched_count == 0 {
+        return Err(ErrorConflict(
+            "only the author can change an open workspace",
+        ));
+    }
+    Ok(web::Json(find_workspace(&workspaces, &path).await?))
+}
+
+pub(super) async fn submit_workspace(
+    path: web::Path<Uuid>,
+    user: User,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let workspaces: Collection<Workspace> = database.collection("workspaces");
+    let workspace = find_workspace(&workspaces, &path).await?;
+    if workspace.author != user.0 {
+        return Err(ErrorForbidden("only the author can request review"));
+    }
+    if workspace.changes.is_empty() {
+        return Err(ErrorBadRequest("workspace has no changes"));
+    }
+    let workspace = transition_workspace(
+        &workspaces,
+        &path,
+        WorkspaceStatus::Open,
+        doc! { "status": "in_review" },
+        None,
+    )
+    .await?;
+    Ok(web::Json(workspace))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ReviewNote {
+    note: Option<String>,
+}
+
+pub(super) async fn reject_workspace(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    body: web::Json<ReviewNote>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let database = store.database();
+    let note = body.into_inner().note;
+    let workspace = transition_workspace(
+        &database.collection("workspaces"),
+        &path,
+        WorkspaceStatus::InReview,
+        doc! { "status": "rejected", "reviewer_note": &note },
+        None,
+    )
+    .await?;
+    audit(
+        database,
+        user.as_ref(),
+        "reject",
+        "workspace",
+        Some(workspace.uuid),
+        doc! { "status": { "before": "in_review", "after": "rejected" } },
+        doc! { "note": note },
+    )
+    .await;
+    Ok(web::Json(workspace))
+}
+
+/// Applies every staged change and closes the workspace in one transaction,
+/// so the set either gets the whole batch or none of it. On a standalone
+/// server `ApprovalUndo` takes back what was written if a change fails.
+pub(super) async fn approve_workspace(
+    _: Admin,
+    user: Option<User>,
+    path: web::Path<Uuid>,
+    body: web::Json<ReviewNote>,
+    rules: web::Data<CardRules>,
+    store: web::Data<Store>,
+) -> Result<impl Responder, ActixError> {
+    let client = store.client();
+    let database = store.database();
+    let workspaces: Collection<Workspace> = database.collection("workspaces");
+    let cards: Collection<Card> = database.collection("cards");
+    let workspace = find_workspace(&workspaces, &path).await?;
+    if workspace.status != WorkspaceStatus::InReview {
+        return Err(ErrorConflict(format!(
+            "workspace {} is not in review",
+            path
+        )));
+    }
+    let set = workspace.set_uuid;
+
+    let mut session = client.start_session(None).await.map_err(AppError::from)?;
+    let transaction = try_transaction(&mut session).await?;
+    // Cards as they were before an update, kept as revisions once committed
+    // and, without a transaction, put back if a later change fails.
+    let mut revised = Vec::new();
+    let mut undo = ApprovalUndo::default();
+    let approved = async {
+        // Closed first, so that without a transaction a second approval
+        // cannot apply the same changes again.
+        let approved = transition_workspace(
+            &workspaces,
+            &path,
+            WorkspaceStatus::InReview,
+            doc! { "status": "approved", "reviewer_note": body.into_inner().note },
+            Some(&mut session),
+        )
+        .await?;
+        undo.approved = true;
+        for change in &workspace.changes {
+            let result = match change {
+                CardChange::Add {
+                    suite,
+                    text,
+                    special,
+                } => {
+                    let mut card = Card::new(set, *suite, text.clone(), special.clone());
+                    rules.prepare(&mut card);
+                    let uuid = card.uuid;
+                    let result = cards
+                        .insert_one_with_session(card, None, &mut session)
+                        .await
+                        .map(|_| 1);
+                    undo.added.push(uuid);
+                    result
+                }
+                CardChange::Update {
+                    card,
+                    suite,
+                    text,
+                    special,
+                } => {
+                    let filter = doc! {
+                        "uuid": uuid_bson(card),
+                        "set_uuid": uuid_bson(&set),
+                        "deleted_at": null,
+                    };
+                    match cards
+                        .find_one_with_session(filter.clone(), None, &mut session)
+                        .await
+                    {
+                        Ok(Some(before)) => {
+                            let update = edit_card(
+                                &before,
+                                *suite,
+                                text.as_deref(),
+                                special.as_deref(),
+                                &rules,
+                            )?;
+                            revised.push(before);
+                            cards
+                                .update_one_with_session(
+                                    filter,
+                                    doc! { "$set": update },
+                                    None,
+                                    &mut session,
+                                )
+                                .await
+                                .map(|r| r.matched_count)
+                        }
+                        Ok(None) => Ok(0),
+                        Err(err) => Err(err),
+                    }
+                }
+                CardChange::Remove { card } => {
+                    let now = DateTime::now();
+                    undo.removed.push((*card, now));
+                    cards
+                        .update_one_with_session(
+                            doc! {
+                                "uuid": uuid_bson(card),
+                                "set_uuid": uuid_bson(&set),
+                                "deleted_at": null,
+                            },
+                            doc! { "$set": { "deleted_at": now } },
+                            None,
+                            &mut session,
+                        )
+                        .await
+                        .map(|r| r.matched_count)
+                }
+            };
+            match result {
+                Ok(0) => {
+                    return Err(ErrorConflict(format!(
+                        "change {change:?} no longer applies to set {set}"
+                    )));
+                }
+                Ok(_) => {}
+                Err(err) => return Err(ErrorInternalServerError(err)),
+            }
+        }
+        Ok(approved)
+    }
+    .await;
+    let workspace = match approved {
+        Ok(approved) => {
+            if transaction {
+                session.commit_transaction().await.map_err(AppError::from)?;
+            }
+            approved
+        }
+        Err(err) => {
+            if transaction {
+                session.abort_transaction().await.map_err(AppError::from)?;
+            } else if let Err(e) = undo
+                .restore(&workspaces, &cards, &workspace, &revised)
+                .await
+            {
+                eprintln!("could not undo the partial approval of workspace {path}: {e}");
+            }
+            return Err(err);
+        }
+    };
+
+    for card in &revised {
+        record_revision(database, card, "workspace", Some(&workspace.author))
+            .await
+            .map_err(AppError::from)?;
+    }
+    snapshot_set(database, set, "workspace")
+        .await
+        .map_err(AppError::from)?;
+    audit(
+        database,
+        user.as_ref(),
+        "approve",
+        "workspace",
+        Some(workspace.uuid),
+        doc! {},
+        doc! { "set": uuid_bson(&set), "changes": workspace.changes.len() as i64 },
+    )
+    .await;
+    Ok(web::Json(workspace))
+}
+
+/// What `approve_workspace` has written so far, so that on a standalone
+/// server, which has no transactions, a failed approval can be undone.
+#[derive(Default)]
+struct ApprovalUndo {
+    /// Whether the workspace was closed.
+    approved: bool,
+    added: Vec<Uuid>,
+    /// Cards trashed, with the time they were trashed at.
+    removed: Vec<(Uuid, DateTime)>,
+}
+
+impl ApprovalUndo {
+    /// Deletes the added cards, puts `revised` cards back as they were,
+    /// takes the removed ones out of the trash and reopens the review.
+    async fn restore(
+        &self,
+        workspaces: &Collection<Workspace>,
+        cards: &Collection<Card>,
+        workspace: &Workspace,
+        revised: &[Card],
+    ) -> Result<(), mongodb::error::Error> {
+        let added: Vec<Bson> = self.added.iter().map(uuid_bson).collect();
+        cards
+            .delete_many(doc! { "uuid": { "$in": added } }, None)
+            .await?;
+        for card in revised {
+            cards
+                .replace_one(doc! { "uuid": uuid_bson(&card.uuid) }, card, None)
+                .await?;
+        }
+        for (card, at) in &self.removed {
+            cards
+                .update_one(
+                    doc! { "uuid": uuid_bson(card), "deleted_at": at },
+                    doc! { "$unset": { "deleted_at": "" } },
+                    None,
+                )
+                .await?;
+        }
+        if self.approved {
+            workspaces
+                .update_one(
+                    doc! { "uuid": uuid_bson(&workspace.uuid), "status": "approved" },
+                    doc! { "$set": {
+                        "status": "in_review",
+                        "reviewer_note": &workspace.reviewer_note,
+                        "updated_at": workspace.updated_at,
+                    } },
+                    None,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+pub mod config;
+pub mod error;
+pub mod http;
+pub mod migrations;
+pub mod model;
+pub mod parser;
+pub mod repository;
+pub mod storage;
@@ -0,0 +1,336 @@
+use futures::{future::BoxFuture, FutureExt, TryStreamExt};
+use mongodb::{
+    bson::{doc, Bson, DateTime, Document},
+    options::{FindOptions, UpdateOptions},
+    Database,
+};
+
+use crate::storage::special_fields;
+
+/// A change to how the library is stored, applied once per database.
+/// `apply` must be safe to run again, since two instances starting together
+/// may both get to it before either records it.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub apply: for<'a> fn(&'a Database) -> BoxFuture<'a, Result<(), mongodb::error::Error>>,
+}
+
+/// Every migration, oldest first. New ones go at the end with the next
+/// version; applied ones are never edited or removed.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "cards_set_uuid",
+        apply: cards_set_uuid,
+    },
+    Migration {
+        version: 2,
+        name: "cards_pick_draw",
+        apply: cards_pick_draw,
+    },
+    Migration {
+        version: 3,
+        name: "listed_at",
+        apply: listed_at,
+    },
+    Migration {
+        version: 4,
+        name: "card_votes_unique",
+        apply: card_votes_unique,
+    },
+    Migration {
+        version: 5,
+        name: "card_stats_unique",
+        apply: card_stats_unique,
+    },
+    Migration {
+        version: 6,
+        name: "set_favorites_unique",
+        apply: set_favorites_unique,
+    },
+];
+
+/// Applies the migrations not yet recorded in `schema_versions`, in order,
+/// stopping at the first that fails so later ones never see a half-migrated
+/// library. A migration explains a failure of its own making with a custom
+/// error holding a `String`.
+pub async fn migrate(database: &Database) -> Result<(), mongodb::error::Error> {
+    let versions = database.collection::<Document>("schema_versions");
+    let applied = versions.distinct("version", None, None).await?;
+    for migration in MIGRATIONS {
+        if applied.contains(&Bson::Int32(migration.version)) {
+            continue;
+        }
+        eprintln!(
+            "applying migration {} ({})",
+            migration.version, migration.name
+        );
+        if let Err(err) = (migration.apply)(database).await {
+            let reason = err.get_custom::<String>().cloned();
+            eprintln!(
+                "migration {} ({}) failed: {}",
+                migration.version,
+                migration.name,
+                reason.unwrap_or_else(|| err.to_string())
+            );
+            return Err(err);
+        }
+        versions
+            .update_one(
+                doc! { "version": migration.version },
+                doc! { "$setOnInsert": { "name": migration.name, "applied_at": DateTime::now() } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Cards stored before they carried `set_uuid` take it from their first
+/// edition or, as early libraries kept no editions, from the set document
+/// that embeds or lists the card. Cards neither can place fail the
+/// migration, naming a few of them, so it is not recorded as applied until
+/// they are given a set or removed.
+fn cards_set_uuid(database: &Database) -> BoxFuture<'_, Result<(), mongodb::error::Error>> {
+    async move {
+        let cards = database.collection::<Document>("cards");
+        let editions = database.collection::<Document>("editions");
+        let sets = database.collection::<Document>("sets");
+        let options = FindOptions::builder()
+            .projection(doc! { "uuid": 1, "editions": 1 })
+            .build();
+        let mut cursor = cards
+            .find(doc! { "set_uuid": { "$exists": false } }, options)
+            .await?;
+        let (mut unplaced, mut samples) = (0, Vec::new());
+        while let Some(card) = cursor.try_next().await? {
+            let mut set_uuid = None;
+            if let Some(edition) = card.get_array("editions").ok().and_then(|ids| ids.first()) {
+                set_uuid = editions
+                    .find_one(doc! { "uuid": edition }, None)
+                    .await?
+                    .and_then(|edition| edition.get("set_uuid").cloned());
+            }
+            if let (None, Some(uuid)) = (&set_uuid, card.get("uuid")) {
+                let holder = doc! { "$or": [{ "cards": uuid }, { "cards.uuid": uuid }] };
+                set_uuid = sets
+                    .find_one(holder, None)
+                    .await?
+                    .and_then(|set| set.get("uuid").cloned());
+            }
+            let Some(set_uuid) = set_uuid else {
+                unplaced += 1;
+                if samples.len() < 5 {
+                    let id = card.get("uuid").or(card.get("_id"));
+                    samples.push(id.map_or_else(String::new, Bson::to_string));
+                }
+                continue;
+            };
+            cards
+                .update_one(
+                    doc! { "_id": card.get("_id") },
+                    doc! { "$set": { "set_uuid": set_uuid } },
+                    None,
+                )
+                .await?;
+        }
+        if unplaced > 0 {
+            return Err(mongodb::error::Error::custom(format!(
+                "{unplaced} cards have no set_uuid and no edition or set that places them, \
+                 such as {}; give them a set_uuid or remove them",
+                samples.join(", ")
+            )));
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Cards stored before `special` was split record the pick and draw it
+/// spells out.
+fn cards_pick_draw(database: &Database) -> BoxFuture<'_, Result<(), mongodb::error::Error>> {
+    async move {
+        let cards = database.collection::<Document>("cards");
+        let options = FindOptions::builder()
+            .projection(doc! { "special": 1 })
+            .build();
+        let mut cursor = cards
+            .find(doc! { "pick": { "$exists": false } }, options)
+            .await?;
+        while let Some(card) = cursor.try_next().await? {
+            let special = card.get_str("special").unwrap_or_default();
+            cards
+                .update_one(
+                    doc! { "_id": card.get("_id") },
+                    doc! { "$set": special_fields(special) },
+                    None,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Cards and sets stored before the feeds paged on `listed_at` count as
+/// listed when they were created, so mirrors polling with an older cursor
+/// are not sent the whole library again. Hidden ones lose the stamp at the
+/// next listing pass.
+fn listed_at(database: &Database) -> BoxFuture<'_, Result<(), mongodb::error::Error>> {
+    async move {
+        for collection in ["cards", "sets"] {
+            database
+                .collection::<Document>(collection)
+                .update_many(
+                    doc! { "listed_at": { "$exists": false } },
+                    vec![doc! { "$set": { "listed_at": "$created_at" } }],
+                    None,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Before `card_votes` had a unique index on `(card_uuid, user)`, two
+/// concurrent first votes could both be stored and counted. Each user's
+/// latest vote is kept, and the tallies of the cards concerned are counted
+/// again from the votes left, so the index can be built.
+fn card_votes_unique(database: &Database) -> BoxFuture<'_, Result<(), mongodb::error::Error>> {
+    async move {
+        let votes = database.collection::<Document>("card_votes");
+        let cards = database.collection::<Document>("cards");
+        let duplicates: Vec<Document> = votes
+            .aggregate(
+                vec![
+                    doc! { "$sort": { "updated_at": -1 } },
+                    doc! { "$group": {
+                        "_id": { "card_uuid": "$card_uuid", "user": "$user" },
+                        "ids": { "$push": "$_id" },
+                    } },
+                    doc! { "$match": { "ids.1": { "$exists": true } } },
+                ],
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?;
+        let mut touched = Vec::new();
+        for duplicate in duplicates {
+            let Ok(ids) = duplicate.get_array("ids") else {
+                continue;
+            };
+            votes
+                .delete_many(doc! { "_id": { "$in": &ids[1..] } }, None)
+                .await?;
+            if let Ok(key) = duplicate.get_document("_id") {
+                touched.extend(key.get("card_uuid").cloned());
+            }
+        }
+        for card in touched {
+            let mut tally = doc! { "funny": 0_i64, "meh": 0_i64, "remove": 0_i64 };
+            let mut counts = votes
+                .aggregate(
+                    vec![
+                        doc! { "$match": { "card_uuid": &card } },
+                        doc! { "$group": { "_id": "$vote", "count": { "$sum": 1_i64 } } },
+                    ],
+                    None,
+                )
+                .await?;
+            while let Some(count) = counts.try_next().await? {
+                if let (Ok(vote), Ok(n)) = (count.get_str("_id"), count.get_i64("count")) {
+                    tally.insert(vote, n);
+                }
+            }
+            let score = tally.get_i64("funny").unwrap_or(0) - tally.get_i64("remove").unwrap_or(0);
+            tally.insert("score", score);
+            cards
+                .update_one(
+                    doc! { "uuid": &card },
+                    doc! { "$set": { "votes": tally } },
+                    None,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Before `card_stats` had a unique index on `card_uuid`, the first plays of
+/// a card reported at once could each create its counters. The counters of
+/// each card are summed into one document so the index can be built.
+fn card_stats_unique(database: &Database) -> BoxFuture<'_, Result<(), mongodb::error::Error>> {
+    async move {
+        let stats = database.collection::<Document>("card_stats");
+        let mut duplicates = stats
+            .aggregate(
+                vec![
+                    doc! { "$group": {
+                        "_id": "$card_uuid",
+                        "ids": { "$push": "$_id" },
+                        "dealt": { "$sum": "$dealt" },
+                        "submitted": { "$sum": "$submitted" },
+                        "wins": { "$sum": "$wins" },
+                    } },
+                    doc! { "$match": { "ids.1": { "$exists": true } } },
+                ],
+                None,
+            )
+            .await?;
+        while let Some(duplicate) = duplicates.try_next().await? {
+            let Ok(ids) = duplicate.get_array("ids") else {
+                continue;
+            };
+            let mut counters = Document::new();
+            for counter in ["dealt", "submitted", "wins"] {
+                counters.insert(
+                    counter,
+                    duplicate.get(counter).cloned().unwrap_or(Bson::Int64(0)),
+                );
+            }
+            stats
+                .update_one(doc! { "_id": &ids[0] }, doc! { "$set": counters }, None)
+                .await?;
+            stats
+                .delete_many(doc! { "_id": { "$in": &ids[1..] } }, None)
+                .await?;
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Before `set_favorites` had a unique index on `(set_uuid, user)`, a double
+/// click could store a favorite twice. The earliest one is kept so the index
+/// can be built.
+fn set_favorites_unique(database: &Database) -> BoxFuture<'_, Result<(), mongodb::error::Error>> {
+    async move {
+        let favorites = database.collection::<Document>("set_favorites");
+        let mut duplicates = favorites
+            .aggregate(
+                vec![
+                    doc! { "$sort": { "at": 1 } },
+                    doc! { "$group": {
+                        "_id": { "set_uuid": "$set_uuid", "user": "$user" },
+                        "ids": { "$push": "$_id" },
+                    } },
+                    doc! { "$match": { "ids.1": { "$exists": true } } },
+                ],
+                None,
+            )
+            .await?;
+        while let Some(duplicate) = duplicates.try_next().await? {
+            if let Ok(ids) = duplicate.get_array("ids") {
+                favorites
+                    .delete_many(doc! { "_id": { "$in": &ids[1..] } }, None)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
@@ -0,0 +1,502 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use mongodb::bson::DateTime;
+use uuid::{uuid, Uuid};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Suite {
+    Prompt,
+    Response,
+}
+
+impl Suite {
+    pub(crate) fn from_str(value: &str) -> Option<Suite> {
+        match value {
+            "Prompt" => Some(Suite::Prompt),
+            "Response" => Some(Suite::Response),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Edition {
+    pub uuid: Uuid,
+    pub set_uuid: Uuid,
+    pub country_code: String,
+    pub version: String,
+}
+
+impl Edition {
+    /// Reads an edition column header such as "US v1.6" or "UK": the
+    /// alphabetic word is the country, whatever else is the version.
+    pub fn from_label(set_uuid: Uuid, label: &str) -> Self {
+        let mut country_code = String::new();
+        let mut version = Vec::new();
+        for word in label.split_whitespace() {
+            if country_code.is_empty() && word.chars().all(|c| c.is_ascii_alphabetic()) {
+                country_code = word.to_uppercase();
+            } else {
+                version.push(word);
+            }
+        }
+        Edition {
+            uuid: Uuid::new_v4(),
+            set_uuid,
+            country_code,
+            version: version.join(" "),
+        }
+    }
+
+    /// The column header `from_label` reads this edition back from.
+    pub fn label(&self) -> String {
+        format!("{} {}", self.country_code, self.version)
+            .trim()
+            .to_string()
+    }
+
+    /// Whether the two describe the same printing, whatever their ids.
+    pub fn same_printing(&self, other: &Edition) -> bool {
+        self.country_code == other.country_code && self.version == other.version
+    }
+}
+
+/// A kind of card an instance defines on top of the built-in prompts and
+/// responses, such as a "Haiku" finale. Cards of the type play as `suite`
+/// and get `special` unless their own row says otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardType {
+    pub name: String,
+    /// What the sheet's suite column says for cards of this type.
+    pub label: String,
+    pub suite: Suite,
+    #[serde(default)]
+    pub special: String,
+}
+
+/// The card types an import recognises, by sheet label.
+#[derive(Debug, Clone, Default)]
+pub struct CardTypes(pub HashMap<String, CardType>);
+
+impl CardTypes {
+    /// Reads a suite column value as a built-in suite or a defined type.
+    pub fn resolve(&self, label: &str) -> Option<(Suite, Option<&CardType>)> {
+        match Suite::from_str(label) {
+            Some(suite) => Some((suite, None)),
+            None => self.0.get(label).map(|t| (t.suite, Some(t))),
+        }
+    }
+}
+
+/// The header labels a sheet marks its column blocks with, for sheets that
+/// don't follow the master sheet's "Set"/"Special"/"Edition" layout.
+/// Stored by name in `mapping_profiles` and picked per import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnProfile {
+    #[serde(default)]
+    pub name: String,
+    /// Header of the column holding each card's suite or card type.
+    pub suite: String,
+    /// Header of the card text column, or its start when the rest names
+    /// the set, as in "Cards: Base Game". Without one, the last header
+    /// longer than three characters between the suite and special headers
+    /// is the text column and names the set, or the first non-empty one
+    /// when all of them are shorter, as for a set called "PAX".
+    #[serde(default)]
+    pub text: Option<String>,
+    pub special: String,
+    /// Header after which every non-empty header names an edition.
+    pub edition: String,
+}
+
+impl Default for ColumnProfile {
+    fn default() -> Self {
+        ColumnProfile {
+            name: "default".to_string(),
+            suite: "Set".to_string(),
+            text: None,
+            special: "Special".to_string(),
+            edition: "Edition".to_string(),
+        }
+    }
+}
+
+impl ColumnProfile {
+    /// Whether `header` marks a text column, see `text`.
+    pub fn is_text_header(&self, header: &str) -> bool {
+        match &self.text {
+            Some(label) => header.starts_with(label.as_str()),
+            None => header.len() > 3,
+        }
+    }
+
+    /// The name of the set whose text column has `header`.
+    pub fn set_name(&self, header: &str) -> String {
+        let name = match &self.text {
+            Some(label) => header[label.len()..].trim(),
+            None => header,
+        };
+        match name.is_empty() {
+            true => header.trim().to_string(),
+            false => name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Card {
+    pub uuid: Uuid,
+    pub set_uuid: Uuid,
+    pub suite: Suite,
+    /// Name of the `CardType` the card was imported as, if not a plain
+    /// prompt or response.
+    #[serde(default)]
+    pub card_type: Option<String>,
+    pub text: String,
+    /// As written in the sheet, kept for exports. `pick` and `draw` hold
+    /// what it says.
+    pub special: String,
+    /// Responses the prompt asks for. Cards stored before it was recorded
+    /// have 0, see `rules`.
+    #[serde(default)]
+    pub pick: u8,
+    #[serde(default)]
+    pub draw: u8,
+    pub editions: Vec<Uuid>,
+    /// Shared by every translation/edition of the same conceptual card.
+    #[serde(default)]
+    pub card_group: Option<Uuid>,
+    #[serde(default)]
+    pub votes: VoteTally,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub nsfw: bool,
+    /// Unrated cards count as mature, or explicit when flagged NSFW.
+    #[serde(default)]
+    pub rating: Option<Rating>,
+    /// Banned terms the import screening found in the text. Such a card is
+    /// held back from public queries until an admin approves it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub screened: Vec<String>,
+    /// When the card was moved to the trash, see `Set::deleted_at`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime>,
+    #[serde(default = "DateTime::now")]
+    pub created_at: DateTime,
+    /// When the public could first see the card, stamped by the server once
+    /// it is stored, published and not held back, see `Set::listed_at`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listed_at: Option<DateTime>,
+}
+
+/// How strong a card is, mildest first, so hosts can ask for a deck fit
+/// for the office or the family table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Family,
+    Mature,
+    Explicit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Vote {
+    Funny,
+    Meh,
+    Remove,
+}
+
+impl Vote {
+    pub fn field(self) -> &'static str {
+        match self {
+            Vote::Funny => "funny",
+            Vote::Meh => "meh",
+            Vote::Remove => "remove",
+        }
+    }
+
+    pub fn score(self) -> i64 {
+        match self {
+            Vote::Funny => 1,
+            Vote::Meh => 0,
+            Vote::Remove => -1,
+        }
+    }
+}
+
+/// Running totals of user votes, kept on the card for sorting and filtering.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VoteTally {
+    pub funny: i64,
+    pub meh: i64,
+    pub remove: i64,
+    pub score: i64,
+}
+
+impl Card {
+    pub fn new(set_uuid: Uuid, suite: Suite, text: String, special: String) -> Self {
+        let rules = RoundRules::from_special(&special);
+        Card {
+            uuid: Uuid::new_v4(),
+            set_uuid,
+            suite,
+            card_type: None,
+            text,
+            special,
+            pick: rules.pick,
+            draw: rules.draw,
+            editions: Vec::new(),
+            card_group: None,
+            votes: VoteTally::default(),
+            tags: Vec::new(),
+            nsfw: false,
+            rating: None,
+            screened: Vec::new(),
+            deleted_at: None,
+            created_at: DateTime::now(),
+            listed_at: None,
+        }
+    }
+
+    /// Number of responses a prompt asks for.
+    pub fn pick(&self) -> u8 {
+        self.rules().pick
+    }
+
+    /// Extra cards each player draws before answering.
+    pub fn draw(&self) -> u8 {
+        self.rules().draw
+    }
+
+    /// The stored pick and draw, or for cards stored without them, what
+    /// `special` says.
+    pub fn rules(&self) -> RoundRules {
+        match self.pick {
+            0 => RoundRules::from_special(&self.special),
+            pick => RoundRules {
+                pick,
+                draw: self.draw,
+            },
+        }
+    }
+}
+
+/// How a round dealt with a prompt plays: players first draw `draw` extra
+/// responses, then each submits exactly `pick` of them in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RoundRules {
+    pub pick: u8,
+    pub draw: u8,
+}
+
+impl RoundRules {
+    /// Reads a special such as "PICK 2" or "DRAW 2, PICK 3". Whatever it
+    /// does not mention is pick 1, draw 0.
+    pub fn from_special(special: &str) -> Self {
+        RoundRules {
+            pick: special_value(special, "PICK").unwrap_or(1),
+            draw: special_value(special, "DRAW").unwrap_or(0),
+        }
+    }
+}
+
+/// Reads the number following `keyword` in a special like "DRAW 2, PICK 3".
+fn special_value(special: &str, keyword: &str) -> Option<u8> {
+    special.split(',').find_map(|part| {
+        let mut words = part.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(word), Some(value)) if word.eq_ignore_ascii_case(keyword) => value.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Set {
+    pub uuid: Uuid,
+    pub name: String,
+    /// BCP 47 language tag of the card text, e.g. `de` or `pt-BR`.
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub nsfw: bool,
+    #[serde(default)]
+    pub status: SetStatus,
+    /// When a draft is due to go live; the publisher job flips it.
+    #[serde(default)]
+    pub publish_at: Option<DateTime>,
+    /// Sets this one cannot be played without, such as the base set an
+    /// expansion builds on.
+    #[serde(default)]
+    pub requires: Vec<Uuid>,
+    /// When the set was moved to the trash. Trashed sets and cards are
+    /// left out of every query but the trash's own, until restored or
+    /// purged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime>,
+    #[serde(default = "DateTime::now")]
+    pub created_at: DateTime,
+    /// When the public could first see the set. Unlike `created_at`, which
+    /// the parser sets, it is stamped by the server once the set is stored
+    /// and published, and cleared while it is hidden again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listed_at: Option<DateTime>,
+    #[serde(skip)]
+    pub cards: Vec<Card>,
+    /// Stored in their own collection, like the cards.
+    #[serde(skip)]
+    pub editions: Vec<Edition>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SetStatus {
+    Draft,
+    #[default]
+    Published,
+}
+
+impl Set {
+    pub fn new(name: String) -> Self {
+        Set {
+            uuid: Uuid::new_v4(),
+            name,
+            language: None,
+            tags: Vec::new(),
+            nsfw: false,
+            status: SetStatus::default(),
+            publish_at: None,
+            requires: Vec::new(),
+            deleted_at: None,
+            created_at: DateTime::now(),
+            listed_at: None,
+            cards: Vec::new(),
+            editions: Vec::new(),
+        }
+    }
+}
+
+/// Namespace for ids derived from content, see `Set::derive_ids`.
+pub const ID_NAMESPACE: Uuid = uuid!("9af258c4-2f12-4461-b989-b7bef327db8d");
+
+/// Card text reduced to what identifies it: lowercase with runs of
+/// whitespace collapsed, so spacing and capitalisation fixes don't make a
+/// card count as new.
+pub fn normalize_text(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+impl Set {
+    /// Replaces the random ids given at parse time with UUIDv5s derived from
+    /// the set name and card text, so importing the same sheet on any
+    /// instance yields the same ids. Repeated cards are told apart by the
+    /// order they appear in.
+    pub fn derive_ids(&mut self) {
+        let set_uuid = Uuid::new_v5(&ID_NAMESPACE, normalize_text(&self.name).as_bytes());
+        self.uuid = set_uuid;
+        let editions: HashMap<Uuid, Uuid> = self
+            .editions
+            .iter_mut()
+            .map(|edition| {
+                let old = edition.uuid;
+                let key = format!("edition:{}:{}", edition.country_code, edition.version);
+                edition.uuid = Uuid::new_v5(&set_uuid, key.as_bytes());
+                edition.set_uuid = set_uuid;
+                (old, edition.uuid)
+            })
+            .collect();
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for card in &mut self.cards {
+            let mut key = format!("card:{:?}:{}", card.suite, normalize_text(&card.text));
+            let count = seen.entry(key.clone()).or_default();
+            if *count > 0 {
+                key.push_str(&format!("#{count}"));
+            }
+            *count += 1;
+            card.uuid = Uuid::new_v5(&set_uuid, key.as_bytes());
+            card.set_uuid = set_uuid;
+            for edition in &mut card.editions {
+                *edition = editions.get(edition).copied().unwrap_or(*edition);
+            }
+        }
+    }
+}
+
+/// What storing an imported set did to the stored copy, card by card.
+/// Cards are matched on suite and normalized text, so only real edits
+/// count as changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SetChanges {
+    /// The id the set is stored under, which a refresh keeps.
+    pub uuid: Uuid,
+    pub unchanged: usize,
+    pub changed: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// The parts of a card a set version records.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CardSnapshot {
+    pub uuid: Uuid,
+    pub suite: Suite,
+    pub text: String,
+    pub special: String,
+}
+
+impl From<Card> for CardSnapshot {
+    fn from(card: Card) -> Self {
+        CardSnapshot {
+            uuid: card.uuid,
+            suite: card.suite,
+            text: card.text,
+            special: card.special,
+        }
+    }
+}
+
+/// A set's card list as it stood after a significant change.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetVersion {
+    pub set_uuid: Uuid,
+    pub version: i64,
+    pub reason: String,
+    pub created_at: DateTime,
+    pub cards: Vec<CardSnapshot>,
+}
+
+/// The wording a card had before an edit replaced it. Revisions are
+/// numbered per card from 1, oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardRevision {
+    pub card_uuid: Uuid,
+    pub revision: i64,
+    pub suite: Suite,
+    pub text: String,
+    pub special: String,
+    /// What replaced it: `edit`, `workspace`, `import` or `revert`.
+    pub reason: String,
+    /// The `X-User-Id` behind that change, if known.
+    pub editor: Option<String>,
+    pub created_at: DateTime,
+}
+
+/// How an import assigns ids to the sets, cards and editions it creates.
+/// Deterministic ids stay the same every time the master sheet is
+/// imported, so references from other systems keep working.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdMode {
+    Random,
+    #[default]
+    Deterministic,
+}
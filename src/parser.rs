@@ -0,0 +1,1456 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    sync::Arc,
+};
+
+use calamine::{open_workbook, Reader, Xlsx};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::model::{normalize_text, Card, CardTypes, ColumnProfile, Edition, Rating, Set, Suite};
+
+#[derive(Debug, Clone)]
+pub struct SetColumns {
+    pub suite: usize,
+    pub text: usize,
+    pub special: usize,
+    pub editions: HashMap<Uuid, usize>,
+}
+
+/// Reads the editions of the set whose header starts at `columns`: every
+/// non-empty header after its edition marker ("Edition" by default), up to
+/// the next set, names one. Their columns are recorded in
+/// `columns.editions` so cards can be matched to them.
+pub fn parse_set_editions(
+    record: &csv::StringRecord,
+    columns: &mut SetColumns,
+    set_uuid: Uuid,
+    profile: &ColumnProfile,
+) -> Vec<Edition> {
+    let mut editions = Vec::new();
+    let mut marked = false;
+    for (index, field) in record.iter().enumerate().skip(columns.special + 1) {
+        match field {
+            _ if field == profile.suite => break,
+            _ if field == profile.edition => marked = true,
+            "" => {}
+            label if marked => {
+                let edition = Edition::from_label(set_uuid, label);
+                columns.editions.insert(edition.uuid, index);
+                editions.push(edition);
+            }
+            _ => {}
+        }
+    }
+    editions
+}
+
+pub fn parse_set_columns(record: &csv::StringRecord, profile: &ColumnProfile) -> Vec<SetColumns> {
+    let mut suite_index = None;
+    let mut text_index = None;
+    let mut short_text_index = None;
+    let mut special_index = None;
+
+    let mut result = Vec::new();
+
+    for (index, field) in record.iter().enumerate() {
+        match field {
+            _ if field == profile.suite => suite_index = Some(index),
+            _ if field == profile.special => special_index = Some(index),
+            _ if profile.is_text_header(field)
+                && suite_index.is_some_and(|suite| index > suite)
+                && special_index.is_none() =>
+            {
+                text_index = Some(index);
+            }
+            _ if profile.text.is_none()
+                && !field.is_empty()
+                && suite_index.is_some_and(|suite| index > suite)
+                && special_index.is_none()
+                && short_text_index.is_none() =>
+            {
+                short_text_index = Some(index);
+            }
+            _ => {}
+        }
+
+        let text = text_index.or(short_text_index);
+        if let (Some(suite), Some(text), Some(special)) = (suite_index, text, special_index) {
+            result.push(SetColumns {
+                suite,
+                text,
+                special,
+                editions: HashMap::new(),
+            });
+            suite_index = None;
+            text_index = None;
+            short_text_index = None;
+            special_index = None;
+        }
+    }
+
+    result
+}
+
+pub fn parse_field(record: &csv::StringRecord, idx: usize) -> &str {
+    record.get(idx).unwrap_or("")
+}
+
+/// What every blank in a prompt is written as once imported.
+pub const BLANK: &str = "____";
+
+/// Cleans up imported card text so the same card reads, hashes and dedupes
+/// the same whichever sheet it came from: NFC, no stray spaces on or within
+/// lines, every blank as `BLANK`, and with `typographic_quotes`, curly
+/// quotes and em dashes.
+pub fn normalize_card_text(text: &str, typographic_quotes: bool) -> String {
+    let text: String = text.nfc().collect();
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    let text = standardize_blanks(lines.join("\n").trim());
+    match typographic_quotes {
+        true => typographic(&text),
+        false => text,
+    }
+}
+
+/// Words and phrases that rate the cards they appear in, one per line after
+/// the rating they imply, such as `mature hangover`. Blank lines and lines
+/// starting with `#` are skipped. Matching ignores case and punctuation and
+/// only takes whole words.
+#[derive(Debug, Default)]
+pub struct RatingWordlist {
+    entries: Vec<(Rating, String)>,
+}
+
+impl RatingWordlist {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (rating, words) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rating = match rating.to_ascii_lowercase().as_str() {
+                "family" => Rating::Family,
+                "mature" => Rating::Mature,
+                "explicit" => Rating::Explicit,
+                other => return Err(format!("line {}: unknown rating {other:?}", number + 1)),
+            };
+            let words = wordlist_key(words);
+            if words.trim().is_empty() {
+                return Err(format!("line {}: no word after the rating", number + 1));
+            }
+            entries.push((rating, words));
+        }
+        Ok(RatingWordlist { entries })
+    }
+
+    /// The strongest rating implied by a listed word in `text`, or family
+    /// when there is none.
+    pub fn rate(&self, text: &str) -> Rating {
+        let text = wordlist_key(text);
+        self.entries
+            .iter()
+            .filter(|(_, words)| text.contains(words.as_str()))
+            .map(|(rating, _)| *rating)
+            .max()
+            .unwrap_or(Rating::Family)
+    }
+}
+
+/// Lowercase words separated, and surrounded, by single spaces, so a
+/// substring match is a whole-word match.
+fn wordlist_key(text: &str) -> String {
+    let words: String = text
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    format!(" {} ", normalize_text(&words))
+}
+
+/// Terms imports hold cards back for, one per line: a word or phrase
+/// matched whole and ignoring case, or `re:` followed by a regular
+/// expression. Blank lines and lines starting with `#` are skipped.
+#[derive(Debug, Default)]
+pub struct ScreenList {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl ScreenList {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut patterns = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let source = match line.strip_prefix("re:") {
+                Some(pattern) => pattern.trim().to_string(),
+                None => format!(r"(?i)\b{}\b", regex::escape(line)),
+            };
+            let pattern = Regex::new(&source).map_err(|e| format!("line {}: {e}", number + 1))?;
+            patterns.push((line.to_string(), pattern));
+        }
+        Ok(ScreenList { patterns })
+    }
+
+    /// The entries that match somewhere in `text`, as written in the list.
+    pub fn matches(&self, text: &str) -> Vec<String> {
+        self.patterns
+            .iter()
+            .filter(|(_, pattern)| pattern.is_match(text))
+            .map(|(entry, _)| entry.clone())
+            .collect()
+    }
+}
+
+/// Settings that shape the cards the parser makes: how their text is
+/// written and what rates or holds them back. They are passed in rather
+/// than read from the configuration, so the parser works without one; the
+/// default only normalizes.
+#[derive(Debug, Clone, Default)]
+pub struct CardRules {
+    /// Curly quotes and em dashes in card text, see `normalize_card_text`.
+    pub typographic_quotes: bool,
+    pub rating_wordlist: Option<Arc<RatingWordlist>>,
+    pub screen_list: Option<Arc<ScreenList>>,
+}
+
+impl CardRules {
+    /// The rules `config` sets. A wordlist or screen list that cannot be
+    /// read is reported and ignored.
+    pub fn from_config(config: &Config) -> Self {
+        CardRules {
+            typographic_quotes: config.typographic_quotes,
+            rating_wordlist: load_list(config.rating_wordlist.as_deref(), RatingWordlist::parse),
+            screen_list: load_list(config.screen_list.as_deref(), ScreenList::parse),
+        }
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        normalize_card_text(text, self.typographic_quotes)
+    }
+
+    /// Rates a card from the wordlist, when there is one and the card has
+    /// no rating yet.
+    pub fn rate(&self, card: &mut Card) {
+        if let (Some(wordlist), None) = (&self.rating_wordlist, card.rating) {
+            let rating = wordlist.rate(&card.text);
+            card.rating = Some(rating);
+            card.nsfw |= rating == Rating::Explicit;
+        }
+    }
+
+    /// The screen list entries `text` matches.
+    pub fn screen(&self, text: &str) -> Vec<String> {
+        self.screen_list
+            .as_ref()
+            .map(|list| list.matches(text))
+            .unwrap_or_default()
+    }
+
+    /// What every imported card goes through, for cards written any other
+    /// way: normalized text, a pick taken from the blanks when the special
+    /// does not give one, a rating and screening.
+    pub fn prepare(&self, card: &mut Card) {
+        card.text = self.normalize(&card.text);
+        infer_pick(card);
+        self.rate(card);
+        card.screened = self.screen(&card.text);
+    }
+}
+
+fn load_list<T>(path: Option<&str>, parse: fn(&str) -> Result<T, String>) -> Option<Arc<T>> {
+    let path = path?;
+    let loaded = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| parse(&text));
+    match loaded {
+        Ok(list) => Some(Arc::new(list)),
+        Err(e) => {
+            eprintln!("ignoring {path}: {e}");
+            None
+        }
+    }
+}
+
+/// Takes a prompt's pick from its blanks when the special does not say.
+/// Returns the number of blanks when the special gives a different pick.
+fn infer_pick(card: &mut Card) -> Option<usize> {
+    let blanks = card.text.matches(BLANK).count();
+    if card.suite != Suite::Prompt || blanks == 0 {
+        return None;
+    }
+    if !card.special.to_uppercase().contains("PICK") {
+        card.pick = blanks.min(u8::MAX as usize) as u8;
+        None
+    } else {
+        (blanks != card.pick as usize).then_some(blanks)
+    }
+}
+
+/// Replaces runs of underscores, such as `_` or `______.`, with `BLANK`.
+/// Underscores between two letters, as in a handle, are left alone.
+fn standardize_blanks(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('_') {
+        let end = rest[start..]
+            .find(|c| c != '_')
+            .map_or(rest.len(), |len| start + len);
+        let before = rest[..start].chars().next_back();
+        let after = rest[end..].chars().next();
+        result.push_str(&rest[..start]);
+        let inside_word =
+            before.is_some_and(char::is_alphanumeric) && after.is_some_and(char::is_alphanumeric);
+        match inside_word {
+            true => result.push_str(&rest[start..end]),
+            false => result.push_str(BLANK),
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Curls straight quotes by what precedes them and turns `--` into an em
+/// dash.
+fn typographic(text: &str) -> String {
+    let text = text.replace("--", "\u{2014}");
+    let mut result = String::with_capacity(text.len());
+    let mut previous: Option<char> = None;
+    for c in text.chars() {
+        let opening = previous.is_none_or(|p| p.is_whitespace() || "([{\u{2014}".contains(p));
+        result.push(match (c, opening) {
+            ('"', true) => '\u{201C}',
+            ('"', false) => '\u{201D}',
+            ('\'', true) => '\u{2018}',
+            ('\'', false) => '\u{2019}',
+            _ => c,
+        });
+        previous = Some(c);
+    }
+    result
+}
+
+/// Adds the cards found in `record` to the sets being parsed, copying out
+/// only the text that ends up on a card.
+pub fn parse_cards(
+    record: &csv::StringRecord,
+    mapping: &HashMap<Uuid, SetColumns>,
+    parsing: &mut HashMap<Uuid, Set>,
+    types: &CardTypes,
+    profile: &ColumnProfile,
+    rules: &CardRules,
+    report: &mut ParseReport,
+) {
+    let line = record.position().map_or(0, |p| p.line());
+    for (set_id, col) in mapping.iter() {
+        let Some(set) = parsing.get_mut(set_id) else {
+            continue;
+        };
+        let label = parse_field(record, col.suite);
+        let Some((suite, card_type)) = types.resolve(label) else {
+            // Blank rows and the header of the next set are expected.
+            if !label.is_empty() && label != profile.suite {
+                report.reject(line, col.suite, WarningKind::UnknownSuite, &set.name, label);
+            }
+            continue;
+        };
+        let text = rules.normalize(parse_field(record, col.text));
+        if text.is_empty() {
+            report.reject(line, col.text, WarningKind::EmptyText, &set.name, label);
+            continue;
+        }
+        if !report.seen.insert((*set_id, suite, normalize_text(&text))) {
+            report.warn(
+                line,
+                Some(col.text),
+                WarningKind::DuplicateRow,
+                &set.name,
+                &text,
+            );
+        }
+        let special = match (parse_field(record, col.special), card_type) {
+            ("", Some(card_type)) => card_type.special.as_str(),
+            (special, _) => special,
+        };
+        let mut card = Card::new(*set_id, suite, text, special.to_string());
+        card.card_type = card_type.map(|t| t.name.clone());
+        rules.rate(&mut card);
+        report.check_blanks(line, Some(col.text), &set.name, &mut card);
+        report.screen(rules, line, Some(col.text), &set.name, &mut card);
+        // Any mark in an edition column puts the card in that edition.
+        card.editions = col
+            .editions
+            .iter()
+            .filter(|(_, idx)| !parse_field(record, **idx).trim().is_empty())
+            .map(|(id, _)| *id)
+            .collect();
+        set.cards.push(card);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// The suite column holds neither a suite nor a defined card type.
+    UnknownSuite,
+    EmptyText,
+    /// The set already has a card of the same suite and text.
+    DuplicateRow,
+    /// The file was not UTF-8 and was converted from the encoding named in
+    /// `value`.
+    Encoding,
+    /// A prompt's special asks for a different number of responses than it
+    /// has blanks. The card keeps the special's pick.
+    PickMismatch,
+    /// The card contains terms on the screening list, named in `value`
+    /// before its text. It is stored but held back until approved.
+    Screened,
+}
+
+/// Something off about one row of a sheet, by its line in the file. For
+/// JSON decks `line` is the card's position in its set instead, prompts
+/// first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParseWarning {
+    pub line: u64,
+    /// The sheet column at fault, counting from 1, when there is one.
+    #[serde(default)]
+    pub column: Option<usize>,
+    pub kind: WarningKind,
+    pub set: String,
+    pub value: String,
+}
+
+/// How a sheet parse treats rows that cannot become cards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    /// Skips them, listing each in the report.
+    #[default]
+    Lenient,
+    /// Stops at the first one with an error giving its line and column.
+    Strict,
+}
+
+/// What a parse left out or found suspicious. Duplicate rows are kept,
+/// the other warnings mark rows that did not become cards.
+#[derive(Debug, Default, Serialize)]
+pub struct ParseReport {
+    pub rows_skipped: usize,
+    pub warnings: Vec<ParseWarning>,
+    #[serde(skip)]
+    seen: HashSet<(Uuid, Suite, String)>,
+    #[serde(skip)]
+    mode: ParseMode,
+    /// The first rejected row in strict mode, for the reader to fail with.
+    #[serde(skip)]
+    failure: Option<AppError>,
+}
+
+impl ParseReport {
+    /// Records a warning. `column` counts from zero, like record fields.
+    fn warn(
+        &mut self,
+        line: u64,
+        column: Option<usize>,
+        kind: WarningKind,
+        set: &str,
+        value: &str,
+    ) {
+        self.warnings.push(ParseWarning {
+            line,
+            column: column.map(|c| c + 1),
+            kind,
+            set: set.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    fn skip(
+        &mut self,
+        line: u64,
+        column: Option<usize>,
+        kind: WarningKind,
+        set: &str,
+        value: &str,
+    ) {
+        self.rows_skipped += 1;
+        self.warn(line, column, kind, set, value);
+    }
+
+    /// Cross-checks a prompt's pick against its blanks. A special that
+    /// does not say takes the pick from the blanks instead of defaulting
+    /// to 1.
+    fn check_blanks(&mut self, line: u64, column: Option<usize>, set: &str, card: &mut Card) {
+        if let Some(blanks) = infer_pick(card) {
+            let value = format!("PICK {} but {blanks} blanks: {}", card.pick, card.text);
+            self.warn(line, column, WarningKind::PickMismatch, set, &value);
+        }
+    }
+
+    /// Holds back a card matching the screening list, warning with the
+    /// terms it matched, or fails the parse on it in strict mode.
+    fn screen(
+        &mut self,
+        rules: &CardRules,
+        line: u64,
+        column: Option<usize>,
+        set: &str,
+        card: &mut Card,
+    ) {
+        let matched = rules.screen(&card.text);
+        if matched.is_empty() {
+            return;
+        }
+        let terms = matched.join(", ");
+        if self.mode == ParseMode::Strict && self.failure.is_none() {
+            self.failure = Some(AppError::Csv {
+                location: match column {
+                    Some(column) => format!("line {line}, column {}", column + 1),
+                    None => format!("line {line}"),
+                },
+                message: format!("the card contains banned terms: {terms} (set {set:?})"),
+            });
+        }
+        let value = format!("{terms}: {}", card.text);
+        self.warn(line, column, WarningKind::Screened, set, &value);
+        card.screened = matched;
+    }
+
+    /// Skips a sheet row because of the field at `column`, counting from
+    /// zero, or fails the parse on it in strict mode.
+    fn reject(&mut self, line: u64, column: usize, kind: WarningKind, set: &str, value: &str) {
+        if self.mode == ParseMode::Strict && self.failure.is_none() {
+            let message = match kind {
+                WarningKind::UnknownSuite => format!("{value:?} is not a suite or card type"),
+                WarningKind::EmptyText => "the card has no text".to_string(),
+                _ => format!("{kind:?}"),
+            };
+            self.failure = Some(AppError::Csv {
+                location: format!("line {line}, column {}", column + 1),
+                message: format!("{message} (set {set:?})"),
+            });
+        }
+        self.skip(line, Some(column), kind, set, value);
+    }
+
+    /// Notes, ahead of the row warnings, that the file had to be converted.
+    pub fn transcoded(&mut self, encoding: Option<&'static Encoding>) {
+        if let Some(encoding) = encoding {
+            self.warnings.insert(0, encoding_warning(encoding));
+        }
+    }
+}
+
+/// The warning for a file converted from `encoding`; it concerns the whole
+/// file, so it has no line or set.
+pub fn encoding_warning(encoding: &'static Encoding) -> ParseWarning {
+    ParseWarning {
+        line: 0,
+        column: None,
+        kind: WarningKind::Encoding,
+        set: String::new(),
+        value: encoding.name().to_string(),
+    }
+}
+
+/// Bounds on what a single sheet may contain, so a corrupted or hostile
+/// upload fails fast instead of exhausting memory or CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct ParseLimits {
+    pub max_rows: usize,
+    pub max_columns: usize,
+    pub max_cell_bytes: usize,
+    pub max_sets: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_rows: 200_000,
+            max_columns: 1_024,
+            max_cell_bytes: 4_096,
+            max_sets: 256,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// The limits `config` sets.
+    pub fn from_config(config: &Config) -> Self {
+        ParseLimits {
+            max_rows: config.max_rows,
+            max_columns: config.max_columns,
+            max_cell_bytes: config.max_cell_bytes,
+            max_sets: config.max_sets,
+        }
+    }
+
+    pub fn check_record(
+        &self,
+        row: usize,
+        record: &csv::StringRecord,
+    ) -> Result<(), LimitExceeded> {
+        if row > self.max_rows {
+            return Err(LimitExceeded::Rows(self.max_rows));
+        }
+        if record.len() > self.max_columns {
+            return Err(LimitExceeded::Columns {
+                row,
+                limit: self.max_columns,
+            });
+        }
+        match record.iter().position(|f| f.len() > self.max_cell_bytes) {
+            Some(column) => Err(LimitExceeded::CellBytes {
+                row,
+                column: column + 1,
+                limit: self.max_cell_bytes,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LimitExceeded {
+    Rows(usize),
+    Columns {
+        row: usize,
+        limit: usize,
+    },
+    CellBytes {
+        row: usize,
+        column: usize,
+        limit: usize,
+    },
+    Sets(usize),
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Rows(limit) => write!(f, "sheet has more than {limit} rows"),
+            LimitExceeded::Columns { row, limit } => {
+                write!(f, "row {row} has more than {limit} columns")
+            }
+            LimitExceeded::CellBytes { row, column, limit } => write!(
+                f,
+                "cell at row {row}, column {column} is larger than {limit} bytes"
+            ),
+            LimitExceeded::Sets(limit) => write!(f, "sheet has more than {limit} sets"),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+pub fn parse_csv_file(
+    file_path: &str,
+    limits: &ParseLimits,
+    types: &CardTypes,
+    rules: &CardRules,
+) -> Result<Vec<Set>, AppError> {
+    parse_csv_report(file_path, limits, types, rules).map(|(sets, _)| sets)
+}
+
+/// Separators a sheet may use, most likely first.
+pub const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// Guesses a sheet's separator from its first line: whichever of
+/// `DELIMITERS` appears most often outside quotes, a comma if none does.
+pub fn detect_delimiter(file_path: &str) -> Result<u8, AppError> {
+    let mut head = [0; 4096];
+    let read = File::open(file_path)?.read(&mut head)?;
+    let mut counts = [0; DELIMITERS.len()];
+    let mut quoted = false;
+    for &byte in &head[..read] {
+        match byte {
+            b'"' => quoted = !quoted,
+            b'\n' if !quoted => break,
+            _ if !quoted => {
+                if let Some(index) = DELIMITERS.iter().position(|&d| d == byte) {
+                    counts[index] += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    // Ties go to the earlier, more common separator.
+    let (best, count) = counts
+        .iter()
+        .enumerate()
+        .fold((0, 0), |best, (index, &count)| match count > best.1 {
+            true => (index, count),
+            false => best,
+        });
+    Ok(if count == 0 { b',' } else { DELIMITERS[best] })
+}
+
+/// How much of a file `transcode_to_utf8` reads or writes at a time.
+const TRANSCODE_CHUNK: usize = 64 * 1024;
+
+/// Rewrites a file saved by Excel or Notepad as UTF-8 so the parsers can
+/// read it, returning the encoding it was converted from. A byte order
+/// mark decides; without one, a file is taken for UTF-16 if every other
+/// byte of its head is zero, and for Windows-1252 if it is not valid UTF-8.
+/// The file is read and converted a chunk at a time.
+pub fn transcode_to_utf8(file_path: &str) -> Result<Option<&'static Encoding>, AppError> {
+    let mut head = Vec::with_capacity(4096);
+    File::open(file_path)?.take(4096).read_to_end(&mut head)?;
+    let (encoding, bom) = match Encoding::for_bom(&head) {
+        Some((encoding, bom)) => (encoding, bom),
+        // Plain text in UTF-16 is valid UTF-8 too, its zeros being NULs.
+        None => match guess_utf_16(&head) {
+            Some(encoding) => (encoding, 0),
+            None if is_utf8(File::open(file_path)?)? => return Ok(None),
+            None => (WINDOWS_1252, 0),
+        },
+    };
+    let mut source = File::open(file_path)?;
+    source.seek(SeekFrom::Start(bom as u64))?;
+    let converted = format!("{file_path}.utf8");
+    let written = decode_into(encoding, source, File::create(&converted)?)
+        .and_then(|()| fs::rename(&converted, file_path));
+    if written.is_err() {
+        let _ = fs::remove_file(&converted);
+    }
+    written?;
+    Ok((encoding != UTF_8).then_some(encoding))
+}
+
+/// Whether everything `reader` holds is valid UTF-8.
+fn is_utf8(mut reader: impl Read) -> io::Result<bool> {
+    let mut buffer = vec![0; TRANSCODE_CHUNK];
+    let mut pending = 0;
+    loop {
+        let read = reader.read(&mut buffer[pending..])?;
+        if read == 0 {
+            return Ok(pending == 0);
+        }
+        let filled = pending + read;
+        match std::str::from_utf8(&buffer[..filled]) {
+            Ok(_) => pending = 0,
+            // A character cut off by the end of the chunk: carry its first
+            // bytes over to the next one.
+            Err(e) if e.error_len().is_none() => {
+                buffer.copy_within(e.valid_up_to()..filled, 0);
+                pending = filled - e.valid_up_to();
+            }
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+/// Decodes `source` from `encoding` and writes it to `target` as UTF-8.
+fn decode_into(encoding: &'static Encoding, mut source: impl Read, target: File) -> io::Result<()> {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut target = BufWriter::new(target);
+    let mut chunk = vec![0; TRANSCODE_CHUNK];
+    let mut text = String::new();
+    loop {
+        let read = source.read(&mut chunk)?;
+        let last = read == 0;
+        text.clear();
+        text.reserve(decoder.max_utf8_buffer_length(read).unwrap_or(0));
+        // Room for the longest possible output was reserved, so the whole
+        // chunk is always taken.
+        let _ = decoder.decode_to_string(&chunk[..read], &mut text, last);
+        target.write_all(text.as_bytes())?;
+        if last {
+            return target.flush();
+        }
+    }
+}
+
+fn guess_utf_16(head: &[u8]) -> Option<&'static Encoding> {
+    let zeros = |offset: usize| {
+        head.iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let half = head.len() / 2;
+    if half > 0 && zeros(1) * 10 >= half * 9 {
+        Some(UTF_16LE)
+    } else if half > 0 && zeros(0) * 10 >= half * 9 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Parses a sheet like `parse_csv_file`, also reporting the rows that did
+/// not make it into a set as they were and why.
+pub fn parse_csv_report(
+    file_path: &str,
+    limits: &ParseLimits,
+    types: &CardTypes,
+    rules: &CardRules,
+) -> Result<(Vec<Set>, ParseReport), AppError> {
+    let encoding = transcode_to_utf8(file_path)?;
+    let delimiter = detect_delimiter(file_path)?;
+    let mut reader =
+        SetReader::delimited(File::open(file_path)?, delimiter, *limits, types.clone())
+            .with_rules(rules.clone());
+    let sets = reader.by_ref().collect::<Result<Vec<Set>, AppError>>()?;
+    let mut report = reader.into_report();
+    report.transcoded(encoding);
+    Ok((sets, report))
+}
+
+/// Where a `SetReader` gets its rows.
+enum Rows<R> {
+    Csv(csv::Reader<R>),
+    /// A worksheet, already read whole by the workbook reader.
+    Worksheet(std::vec::IntoIter<csv::StringRecord>),
+}
+
+/// Reads a sheet one row at a time, yielding each set as soon as its column
+/// block ends, so only the sets still being read are held in memory.
+pub struct SetReader<R> {
+    rows: Rows<R>,
+    limits: ParseLimits,
+    types: CardTypes,
+    profile: ColumnProfile,
+    rules: CardRules,
+    parsing: HashMap<Uuid, Set>,
+    mapping: HashMap<Uuid, SetColumns>,
+    /// Sets finished by the last row, not yet handed out.
+    finished: VecDeque<Set>,
+    detected_sets: usize,
+    report: ParseReport,
+    // One record buffer is reused for every row of the sheet.
+    record: csv::StringRecord,
+    done: bool,
+}
+
+impl<R: Read> SetReader<R> {
+    pub fn new(input: R, limits: ParseLimits, types: CardTypes) -> Self {
+        SetReader::delimited(input, b',', limits, types)
+    }
+
+    /// Reads a sheet whose fields are separated by `delimiter`, such as the
+    /// semicolons LibreOffice uses in many locales.
+    pub fn delimited(input: R, delimiter: u8, limits: ParseLimits, types: CardTypes) -> Self {
+        let reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .from_reader(input);
+        SetReader::with_rows(Rows::Csv(reader), limits, types)
+    }
+
+    fn with_rows(rows: Rows<R>, limits: ParseLimits, types: CardTypes) -> Self {
+        SetReader {
+            rows,
+            limits,
+            types,
+            profile: ColumnProfile::default(),
+            rules: CardRules::default(),
+            parsing: HashMap::new(),
+            mapping: HashMap::new(),
+            finished: VecDeque::new(),
+            detected_sets: 0,
+            report: ParseReport::default(),
+            record: csv::StringRecord::new(),
+            done: false,
+        }
+    }
+
+    /// Reads the column blocks by the headers of `profile` instead of the
+    /// master sheet's.
+    pub fn with_profile(mut self, profile: ColumnProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Normalizes, rates and screens cards by `rules` instead of only
+    /// normalizing them.
+    pub fn with_rules(mut self, rules: CardRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Fails on the first row that cannot become a card instead of
+    /// skipping it, see `ParseMode`.
+    pub fn with_mode(mut self, mode: ParseMode) -> Self {
+        self.report.mode = mode;
+        self
+    }
+
+    /// What the rows read so far left out, complete once the reader is
+    /// exhausted.
+    pub fn into_report(self) -> ParseReport {
+        self.report
+    }
+
+    pub fn report(&self) -> &ParseReport {
+        &self.report
+    }
+
+    /// The line of the sheet read last.
+    pub fn line(&self) -> u64 {
+        self.record.position().map_or(0, |p| p.line())
+    }
+
+    /// Reads one row. Returns false at the end of the sheet.
+    fn read_row(&mut self) -> Result<bool, AppError> {
+        let record = &mut self.record;
+        let more = match &mut self.rows {
+            Rows::Csv(reader) => reader.read_record(record)?,
+            Rows::Worksheet(rows) => rows.next().map(|row| *record = row).is_some(),
+        };
+        if !more {
+            return Ok(false);
+        }
+        let row = record.position().map_or(0, |p| p.line() as usize);
+        self.limits.check_record(row, record)?;
+        parse_cards(
+            record,
+            &self.mapping,
+            &mut self.parsing,
+            &self.types,
+            &self.profile,
+            &self.rules,
+            &mut self.report,
+        );
+        if let Some(failure) = self.report.failure.take() {
+            return Err(failure);
+        }
+
+        let new_set_columns = parse_set_columns(record, &self.profile);
+
+        let finished: Vec<Uuid> = new_set_columns
+            .iter()
+            .filter_map(|set_column| {
+                self.mapping
+                    .iter()
+                    .find(|(_, column)| {
+                        column.suite == set_column.suite
+                            && column.text == set_column.text
+                            && column.special == set_column.special
+                    })
+                    .map(|(key, _)| *key)
+            })
+            .collect();
+
+        for id in finished {
+            self.finished.extend(self.parsing.remove(&id));
+            let _ = self.mapping.remove(&id);
+        }
+
+        self.detected_sets += new_set_columns.len();
+        if self.detected_sets > self.limits.max_sets {
+            return Err(LimitExceeded::Sets(self.limits.max_sets).into());
+        }
+        for mut set_column in new_set_columns {
+            let mut s = Set::new(self.profile.set_name(&record[set_column.text]));
+            let id = s.uuid;
+            s.editions = parse_set_editions(record, &mut set_column, id, &self.profile);
+            self.parsing.insert(id, s);
+            self.mapping.insert(id, set_column);
+        }
+        Ok(true)
+    }
+}
+
+impl SetReader<std::io::Empty> {
+    /// Reads the rows of a worksheet, see `read_workbook`.
+    pub fn from_rows(rows: Vec<csv::StringRecord>, limits: ParseLimits, types: CardTypes) -> Self {
+        SetReader::with_rows(Rows::Worksheet(rows.into_iter()), limits, types)
+    }
+}
+
+/// Reads every worksheet of an Excel workbook into rows for
+/// `SetReader::from_rows`, by worksheet name. Each worksheet is parsed on
+/// its own, so sets laid out side by side on different sheets do not mix,
+/// and rows keep their line in the worksheet.
+pub fn read_workbook(file_path: &str) -> Result<Vec<(String, Vec<csv::StringRecord>)>, AppError> {
+    let invalid = |e: calamine::XlsxError| AppError::Workbook(e.to_string());
+    let mut workbook: Xlsx<_> = open_workbook(file_path).map_err(invalid)?;
+    let mut worksheets = Vec::new();
+    for name in workbook.sheet_names() {
+        let range = workbook.worksheet_range(&name).map_err(invalid)?;
+        let first = range.start().map_or(0, |(row, _)| row as u64);
+        // Like the CSV reader, the first row is taken as the header.
+        let rows = range
+            .rows()
+            .enumerate()
+            .skip(1)
+            .map(|(index, cells)| {
+                let mut record: csv::StringRecord =
+                    cells.iter().map(|cell| cell.to_string()).collect();
+                let mut position = csv::Position::new();
+                position.set_line(first + index as u64 + 1);
+                record.set_position(Some(position));
+                record
+            })
+            .collect();
+        worksheets.push((name, rows));
+    }
+    Ok(worksheets)
+}
+
+impl<R: Read> Iterator for SetReader<R> {
+    type Item = Result<Set, AppError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(set) = self.finished.pop_front() {
+                return Some(Ok(set));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_row() {
+                Ok(true) => {}
+                Ok(false) => {
+                    // Sets still open at the end of the sheet are complete.
+                    self.done = true;
+                    self.mapping.clear();
+                    self.finished
+                        .extend(self.parsing.drain().map(|(_, set)| set));
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// One set of a JSON deck, as community tools write them.
+#[derive(Debug, Deserialize)]
+pub struct JsonSet {
+    pub name: String,
+    #[serde(default)]
+    pub prompts: Vec<JsonCard>,
+    #[serde(default)]
+    pub responses: Vec<JsonCard>,
+}
+
+/// A card of a JSON deck: its text alone, or an object that also gives
+/// the special.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum JsonCard {
+    Text(String),
+    Card {
+        text: String,
+        #[serde(default)]
+        special: String,
+    },
+}
+
+/// The "compact" layout of the JSON Against Humanity dataset: every card
+/// once in `white` or `black`, and packs listing theirs by index.
+#[derive(Debug, Deserialize)]
+pub struct CompactDeck {
+    pub white: Vec<String>,
+    pub black: Vec<CompactPrompt>,
+    pub packs: Vec<CompactPack>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompactPrompt {
+    pub text: String,
+    pub pick: u8,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompactPack {
+    pub name: String,
+    #[serde(default)]
+    pub official: bool,
+    #[serde(default)]
+    pub white: Vec<usize>,
+    #[serde(default)]
+    pub black: Vec<usize>,
+}
+
+/// Turns deck entries into cards under the same limits and with the same
+/// warnings as the rows of a sheet.
+struct DeckCards<'a> {
+    limits: &'a ParseLimits,
+    rules: &'a CardRules,
+    report: ParseReport,
+    rows: usize,
+}
+
+impl DeckCards<'_> {
+    fn push(
+        &mut self,
+        set: &mut Set,
+        position: u64,
+        suite: Suite,
+        text: String,
+        special: String,
+    ) -> Result<(), AppError> {
+        self.rows += 1;
+        if self.rows > self.limits.max_rows {
+            return Err(LimitExceeded::Rows(self.limits.max_rows).into());
+        }
+        if let Some(column) = [&text, &special]
+            .iter()
+            .position(|field| field.len() > self.limits.max_cell_bytes)
+        {
+            return Err(LimitExceeded::CellBytes {
+                row: self.rows,
+                column: column + 1,
+                limit: self.limits.max_cell_bytes,
+            }
+            .into());
+        }
+        let text = self.rules.normalize(&text);
+        if text.is_empty() {
+            self.report
+                .skip(position, None, WarningKind::EmptyText, &set.name, "");
+            return Ok(());
+        }
+        if !self
+            .report
+            .seen
+            .insert((set.uuid, suite, normalize_text(&text)))
+        {
+            self.report
+                .warn(position, None, WarningKind::DuplicateRow, &set.name, &text);
+        }
+        let mut card = Card::new(set.uuid, suite, text, special);
+        self.rules.rate(&mut card);
+        self.report
+            .check_blanks(position, None, &set.name, &mut card);
+        self.report
+            .screen(self.rules, position, None, &set.name, &mut card);
+        set.cards.push(card);
+        Ok(())
+    }
+}
+
+/// Whether a file holds a JSON deck rather than a sheet, going by its first
+/// non-blank character. No sheet starts with a bracket or brace.
+pub fn looks_like_json(file_path: &str) -> Result<bool, AppError> {
+    let mut head = [0; 256];
+    let read = File::open(file_path)?.read(&mut head)?;
+    // Byte order marks and the zero half of UTF-16 characters are skipped,
+    // so a deck is recognised before `transcode_to_utf8` has run.
+    Ok(head[..read]
+        .iter()
+        .find(|&&byte| !byte.is_ascii_whitespace() && byte != 0 && byte.is_ascii())
+        .is_some_and(|byte| matches!(byte, b'[' | b'{')))
+}
+
+/// Reads a JSON deck: an array of `JsonSet`s, or an object in the
+/// `CompactDeck` layout, told apart by their first character.
+pub fn parse_json_deck<R: Read>(
+    mut input: R,
+    limits: &ParseLimits,
+    rules: &CardRules,
+) -> Result<(Vec<Set>, ParseReport), AppError> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+    let mut cards = DeckCards {
+        limits,
+        rules,
+        report: ParseReport::default(),
+        rows: 0,
+    };
+    let sets = match text.trim_start().starts_with('{') {
+        true => compact_sets(serde_json::from_str(&text)?, &mut cards)?,
+        false => json_sets(serde_json::from_str(&text)?, &mut cards)?,
+    };
+    Ok((sets, cards.report))
+}
+
+fn json_sets(deck: Vec<JsonSet>, cards: &mut DeckCards) -> Result<Vec<Set>, AppError> {
+    if deck.len() > cards.limits.max_sets {
+        return Err(LimitExceeded::Sets(cards.limits.max_sets).into());
+    }
+    let mut sets = Vec::with_capacity(deck.len());
+    for deck_set in deck {
+        let mut set = Set::new(deck_set.name);
+        let entries = deck_set
+            .prompts
+            .into_iter()
+            .map(|card| (Suite::Prompt, card))
+            .chain(
+                deck_set
+                    .responses
+                    .into_iter()
+                    .map(|card| (Suite::Response, card)),
+            );
+        for (index, (suite, card)) in entries.enumerate() {
+            let (text, special) = match card {
+                JsonCard::Text(text) => (text, String::new()),
+                JsonCard::Card { text, special } => (text, special),
+            };
+            cards.push(&mut set, index as u64 + 1, suite, text, special)?;
+        }
+        sets.push(set);
+    }
+    Ok(sets)
+}
+
+/// One set per pack. A prompt's pick count becomes its special, "PICK 2",
+/// left out for the usual single pick. Official packs are tagged so.
+fn compact_sets(deck: CompactDeck, cards: &mut DeckCards) -> Result<Vec<Set>, AppError> {
+    if deck.packs.len() > cards.limits.max_sets {
+        return Err(LimitExceeded::Sets(cards.limits.max_sets).into());
+    }
+    let out_of_range = |pack: &CompactPack, list: &str, index: usize| AppError::Json {
+        location: format!("pack {:?}", pack.name),
+        message: format!("{list} card {index} does not exist"),
+    };
+    let mut sets = Vec::with_capacity(deck.packs.len());
+    for pack in &deck.packs {
+        let mut set = Set::new(pack.name.clone());
+        if pack.official {
+            set.tags.push("official".to_string());
+        }
+        let mut position = 0;
+        for &index in &pack.black {
+            let prompt = deck
+                .black
+                .get(index)
+                .ok_or_else(|| out_of_range(pack, "black", index))?;
+            let special = match prompt.pick {
+                0 | 1 => String::new(),
+                pick => format!("PICK {pick}"),
+            };
+            position += 1;
+            cards.push(
+                &mut set,
+                position,
+                Suite::Prompt,
+                prompt.text.clone(),
+                special,
+            )?;
+        }
+        for &index in &pack.white {
+            let response = deck
+                .white
+                .get(index)
+                .ok_or_else(|| out_of_range(pack, "white", index))?;
+            position += 1;
+            cards.push(
+                &mut set,
+                position,
+                Suite::Response,
+                response.clone(),
+                String::new(),
+            )?;
+        }
+        sets.push(set);
+    }
+    Ok(sets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = "\
+Master sheet,,,,,
+Set,Base Game,Special,Edition,US,UK
+Prompt,Why can't I sleep at night? ___.,,,x,
+Prompt,___ + ___ = ___.,,,x,x
+Response,A  windmill full of corpses.,,,,x
+Response,,,,,
+Bogus,Not a card.,,,,
+";
+
+    fn read_sheet(rules: CardRules, mode: ParseMode) -> (Result<Vec<Set>, AppError>, ParseReport) {
+        let mut reader = SetReader::new(
+            SHEET.as_bytes(),
+            ParseLimits::default(),
+            CardTypes::default(),
+        )
+        .with_rules(rules)
+        .with_mode(mode);
+        let sets = reader.by_ref().collect();
+        (sets, reader.into_report())
+    }
+
+    #[test]
+    fn set_columns_are_found_per_block() {
+        let record = csv::StringRecord::from(vec![
+            "Set",
+            "Base Game",
+            "Special",
+            "",
+            "Set",
+            "Expansion",
+            "Special",
+        ]);
+        let columns = parse_set_columns(&record, &ColumnProfile::default());
+        let found: Vec<_> = columns
+            .iter()
+            .map(|c| (c.suite, c.text, c.special))
+            .collect();
+        assert_eq!(found, [(0, 1, 2), (4, 5, 6)]);
+    }
+
+    #[test]
+    fn set_columns_need_a_text_header() {
+        let record = csv::StringRecord::from(vec!["Set", "", "Special"]);
+        assert!(parse_set_columns(&record, &ColumnProfile::default()).is_empty());
+    }
+
+    #[test]
+    fn reader_builds_cards_and_reports_skipped_rows() {
+        let (sets, report) = read_sheet(CardRules::default(), ParseMode::Lenient);
+        let sets = sets.unwrap();
+        assert_eq!(sets.len(), 1);
+        let set = &sets[0];
+        assert_eq!(set.name, "Base Game");
+        assert_eq!(set.editions.len(), 2);
+        let texts: Vec<&str> = set.cards.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "Why can't I sleep at night? ____.",
+                "____ + ____ = ____.",
+                "A windmill full of corpses.",
+            ]
+        );
+        assert_eq!(set.cards[1].pick, 3);
+        let editions: Vec<usize> = set.cards.iter().map(|c| c.editions.len()).collect();
+        assert_eq!(editions, [1, 2, 1]);
+        assert_eq!(report.rows_skipped, 2);
+        let kinds: Vec<WarningKind> = report.warnings.iter().map(|w| w.kind).collect();
+        assert_eq!(kinds, [WarningKind::EmptyText, WarningKind::UnknownSuite]);
+    }
+
+    #[test]
+    fn strict_reader_fails_on_the_first_bad_row() {
+        let (sets, _) = read_sheet(CardRules::default(), ParseMode::Strict);
+        let err = sets.unwrap_err().to_string();
+        assert!(err.contains("line 6"), "{err}");
+    }
+
+    #[test]
+    fn reader_screens_and_rates_by_its_rules() {
+        let rules = CardRules {
+            typographic_quotes: true,
+            rating_wordlist: Some(Arc::new(RatingWordlist::parse("explicit corpses").unwrap())),
+            screen_list: Some(Arc::new(ScreenList::parse("windmill").unwrap())),
+        };
+        let (sets, report) = read_sheet(rules, ParseMode::Lenient);
+        let cards = &sets.unwrap()[0].cards;
+        assert_eq!(cards[0].text, "Why can\u{2019}t I sleep at night? ____.");
+        assert_eq!(cards[0].rating, Some(Rating::Family));
+        assert_eq!(cards[2].rating, Some(Rating::Explicit));
+        assert!(cards[2].nsfw);
+        assert_eq!(cards[2].screened, ["windmill"]);
+        assert!(report
+            .warnings
+            .iter()
+            .any(|w| w.kind == WarningKind::Screened));
+    }
+
+    #[test]
+    fn json_deck_reads_sets_of_prompts_and_responses() {
+        let deck = r#"[{
+            "name": "Deck",
+            "prompts": ["Pick __ and __."],
+            "responses": ["One", { "text": "Two", "special": "" }, " "]
+        }]"#;
+        let (sets, report) = parse_json_deck(
+            deck.as_bytes(),
+            &ParseLimits::default(),
+            &CardRules::default(),
+        )
+        .unwrap();
+        assert_eq!(sets.len(), 1);
+        let cards = &sets[0].cards;
+        assert_eq!(cards.len(), 3);
+        assert_eq!(cards[0].suite, Suite::Prompt);
+        assert_eq!(cards[0].text, "Pick ____ and ____.");
+        assert_eq!(cards[0].pick, 2);
+        assert_eq!(report.rows_skipped, 1);
+    }
+
+    #[test]
+    fn json_deck_reads_the_compact_layout() {
+        let deck = r#"{
+            "white": ["A", "B"],
+            "black": [{ "text": "_ and _", "pick": 2 }],
+            "packs": [{ "name": "Pack", "official": true, "white": [1], "black": [0] }]
+        }"#;
+        let (sets, _) = parse_json_deck(
+            deck.as_bytes(),
+            &ParseLimits::default(),
+            &CardRules::default(),
+        )
+        .unwrap();
+        let set = &sets[0];
+        assert_eq!(set.tags, ["official"]);
+        assert_eq!(set.cards.len(), 2);
+        assert_eq!(set.cards[0].special, "PICK 2");
+        assert_eq!(set.cards[1].text, "B");
+    }
+
+    #[test]
+    fn json_deck_rejects_missing_cards() {
+        let deck = r#"{ "white": [], "black": [], "packs": [{ "name": "Pack", "white": [3] }] }"#;
+        let parsed = parse_json_deck(
+            deck.as_bytes(),
+            &ParseLimits::default(),
+            &CardRules::default(),
+        );
+        assert!(parsed.is_err());
+    }
+
+    /// Writes `bytes` to a fresh temporary file, transcodes it and returns
+    /// the encoding found along with what the file holds afterwards.
+    fn transcode(bytes: &[u8]) -> (Option<&'static Encoding>, String) {
+        let path = std::env::temp_dir().join(format!("{}.csv", Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        fs::write(path, bytes).unwrap();
+        let encoding = transcode_to_utf8(path).unwrap();
+        let text = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        (encoding, text)
+    }
+
+    #[test]
+    fn windows_1252_is_detected_and_converted() {
+        let (encoding, text) = transcode(b"Response,Caf\xe9 \x93au lait\x94\n");
+        assert_eq!(encoding, Some(WINDOWS_1252));
+        assert_eq!(text, "Response,Caf\u{e9} \u{201c}au lait\u{201d}\n");
+    }
+
+    #[test]
+    fn utf_16_is_detected_with_or_without_a_bom() {
+        let line = "Prompt,Why can't I sleep at night? ___.\n";
+        let le: Vec<u8> = line.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = line.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(transcode(&le), (Some(UTF_16LE), line.to_string()));
+        assert_eq!(transcode(&be), (Some(UTF_16BE), line.to_string()));
+        let marked = [&[0xff, 0xfe][..], &le].concat();
+        assert_eq!(transcode(&marked), (Some(UTF_16LE), line.to_string()));
+    }
+
+    #[test]
+    fn utf_8_is_left_alone_even_across_chunks() {
+        // An "é" cut in two by the end of the first chunk.
+        let text = format!("{}\u{e9}\n", "a".repeat(TRANSCODE_CHUNK - 1));
+        assert_eq!(transcode(text.as_bytes()), (None, text.clone()));
+        let marked = format!("\u{feff}{text}");
+        assert_eq!(transcode(marked.as_bytes()), (None, text));
+    }
+}
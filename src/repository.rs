@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Future},
+    sync::Mutex,
+};
+
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::FindOptions,
+};
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::model::{normalize_text, Card, Edition, Set, SetChanges, Suite};
+use crate::storage::{add_set, uuid_bson, Store};
+
+/// Where sets live. Handlers written against this instead of a collection
+/// can run on `MemoryRepository` in tests.
+pub trait SetRepository {
+    fn insert_set(&self, set: &Set) -> impl Future<Output = Result<(), AppError>> + Send;
+
+    /// Stores an imported set together with its cards, all or nothing,
+    /// refreshing a stored set with the same id or name.
+    fn add_set(&self, set: &Set) -> impl Future<Output = Result<SetChanges, AppError>> + Send;
+
+    fn find_set(&self, id: &Uuid) -> impl Future<Output = Result<Option<Set>, AppError>> + Send;
+
+    /// A page of sets ordered by name.
+    fn list_sets(
+        &self,
+        skip: u64,
+        limit: u64,
+    ) -> impl Future<Output = Result<Vec<Set>, AppError>> + Send;
+
+    fn count_sets(&self) -> impl Future<Output = Result<u64, AppError>> + Send;
+
+    fn set_editions(
+        &self,
+        set: &Uuid,
+    ) -> impl Future<Output = Result<Vec<Edition>, AppError>> + Send;
+}
+
+/// Where cards live, see `SetRepository`.
+pub trait CardRepository {
+    fn insert_cards(&self, cards: &[Card]) -> impl Future<Output = Result<(), AppError>> + Send;
+
+    fn find_card(&self, id: &Uuid) -> impl Future<Output = Result<Option<Card>, AppError>> + Send;
+
+    fn set_cards(&self, set: &Uuid) -> impl Future<Output = Result<Vec<Card>, AppError>> + Send;
+
+    fn count_cards(&self, set: &Uuid) -> impl Future<Output = Result<u64, AppError>> + Send;
+}
+
+impl SetRepository for Store {
+    async fn insert_set(&self, set: &Set) -> Result<(), AppError> {
+        self.sets().insert_one(set, None).await?;
+        Ok(())
+    }
+
+    async fn add_set(&self, set: &Set) -> Result<SetChanges, AppError> {
+        Ok(add_set(self.database(), set, self.retry()).await?)
+    }
+
+    async fn find_set(&self, id: &Uuid) -> Result<Option<Set>, AppError> {
+        Ok(self
+            .sets()
+            .find_one(doc! { "uuid": uuid_bson(id), "deleted_at": null }, None)
+            .await?)
+    }
+
+    async fn list_sets(&self, skip: u64, limit: u64) -> Result<Vec<Set>, AppError> {
+        let options = FindOptions::builder()
+            .sort(doc! { "name": 1 })
+            .skip(skip)
+            .limit(limit as i64)
+            .build();
+        Ok(self
+            .sets()
+            .find(doc! { "deleted_at": null }, options)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn count_sets(&self) -> Result<u64, AppError> {
+        Ok(self
+            .sets()
+            .count_documents(doc! { "deleted_at": null }, None)
+            .await?)
+    }
+
+    async fn set_editions(&self, set: &Uuid) -> Result<Vec<Edition>, AppError> {
+        Ok(self
+            .database()
+            .collection::<Edition>("editions")
+            .find(doc! { "set_uuid": uuid_bson(set) }, None)
+            .await?
+            .try_collect()
+            .await?)
+    }
+}
+
+impl CardRepository for Store {
+    async fn insert_cards(&self, cards: &[Card]) -> Result<(), AppError> {
+        if !cards.is_empty() {
+            self.cards().insert_many(cards, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn find_card(&self, id: &Uuid) -> Result<Option<Card>, AppError> {
+        Ok(self
+            .cards()
+            .find_one(doc! { "uuid": uuid_bson(id), "deleted_at": null }, None)
+            .await?)
+    }
+
+    async fn set_cards(&self, set: &Uuid) -> Result<Vec<Card>, AppError> {
+        Ok(self
+            .cards()
+            .find(
+                doc! { "set_uuid": uuid_bson(set), "deleted_at": null },
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn count_cards(&self, set: &Uuid) -> Result<u64, AppError> {
+        Ok(self
+            .cards()
+            .count_documents(
+                doc! { "set_uuid": uuid_bson(set), "deleted_at": null },
+                None,
+            )
+            .await?)
+    }
+}
+
+/// Keeps everything in process memory, for tests and tools that have no
+/// database to talk to.
+#[derive(Debug, Default)]
+pub struct MemoryRepository {
+    sets: Mutex<Vec<Set>>,
+    cards: Mutex<Vec<Card>>,
+    editions: Mutex<Vec<Edition>>,
+}
+
+impl SetRepository for MemoryRepository {
+    fn insert_set(&self, set: &Set) -> impl Future<Output = Result<(), AppError>> + Send {
+        // Like the sets collection, the repository does not hold the cards.
+        let mut set = set.clone();
+        set.cards.clear();
+        self.sets.lock().unwrap().push(set);
+        ready(Ok(()))
+    }
+
+    fn add_set(&self, set: &Set) -> impl Future<Output = Result<SetChanges, AppError>> + Send {
+        let mut sets = self.sets.lock().unwrap();
+        let mut cards = self.cards.lock().unwrap();
+        let mut editions = self.editions.lock().unwrap();
+        let replaced: Vec<Uuid> = sets
+            .iter()
+            .filter(|stored| stored.uuid == set.uuid || stored.name == set.name)
+            .map(|stored| stored.uuid)
+            .collect();
+        sets.retain(|stored| !replaced.contains(&stored.uuid));
+        editions.retain(|edition| !replaced.contains(&edition.set_uuid));
+        let (old, kept): (Vec<Card>, Vec<Card>) = cards
+            .drain(..)
+            .partition(|card| replaced.contains(&card.set_uuid));
+        *cards = kept;
+        // Like the Mongo backend, a refreshed set keeps its stored id, and
+        // cards of the same suite and text keep theirs, live cards being
+        // matched before trashed ones. Cards the set dropped go to the trash.
+        let mut stored = set.clone();
+        stored.uuid = replaced.first().copied().unwrap_or(set.uuid);
+        stored.deleted_at = None;
+        let mut changes = SetChanges {
+            uuid: stored.uuid,
+            ..SetChanges::default()
+        };
+        let mut live: HashMap<(Suite, String), Vec<Card>> = HashMap::new();
+        let mut trashed: HashMap<(Suite, String), Vec<Card>> = HashMap::new();
+        for card in old {
+            match card.deleted_at {
+                None => &mut live,
+                Some(_) => &mut trashed,
+            }
+            .entry((card.suite, normalize_text(&card.text)))
+            .or_default()
+            .push(card);
+        }
+        for mut card in stored.cards.drain(..) {
+            let key = (card.suite, normalize_text(&card.text));
+            let previous = live
+                .get_mut(&key)
+                .and_then(Vec::pop)
+                .or_else(|| trashed.get_mut(&key).and_then(Vec::pop));
+            match previous {
+                Some(previous) => {
+                    if previous.deleted_at.is_none()
+                        && previous.text == card.text
+                        && previous.special == card.special
+                    {
+                        changes.unchanged += 1;
+                    } else {
+                        changes.changed += 1;
+                    }
+                    card.uuid = previous.uuid;
+                }
+                None => changes.added += 1,
+            }
+            card.set_uuid = stored.uuid;
+            card.deleted_at = None;
+            cards.push(card);
+        }
+        changes.removed = live.values().map(Vec::len).sum();
+        let now = DateTime::now();
+        for mut card in live.into_values().flatten() {
+            card.deleted_at = Some(now);
+            cards.push(card);
+        }
+        cards.extend(trashed.into_values().flatten());
+        for mut edition in stored.editions.drain(..) {
+            edition.set_uuid = stored.uuid;
+            editions.push(edition);
+        }
+        sets.push(stored);
+        ready(Ok(changes))
+    }
+
+    fn find_set(&self, id: &Uuid) -> impl Future<Output = Result<Option<Set>, AppError>> + Send {
+        let sets = self.sets.lock().unwrap();
+        let found = sets
+            .iter()
+            .find(|set| set.uuid == *id && set.deleted_at.is_none());
+        ready(Ok(found.cloned()))
+    }
+
+    fn list_sets(
+        &self,
+        skip: u64,
+        limit: u64,
+    ) -> impl Future<Output = Result<Vec<Set>, AppError>> + Send {
+        let mut sets = self.sets.lock().unwrap().clone();
+        sets.retain(|set| set.deleted_at.is_none());
+        sets.sort_by(|a, b| a.name.cmp(&b.name));
+        let page = sets
+            .into_iter()
+            .skip(skip as usize)
+            .take(limit as usize)
+            .collect();
+        ready(Ok(page))
+    }
+
+    fn count_sets(&self) -> impl Future<Output = Result<u64, AppError>> + Send {
+        let sets = self.sets.lock().unwrap();
+        ready(Ok(
+            sets.iter().filter(|set| set.deleted_at.is_none()).count() as u64,
+        ))
+    }
+
+    fn set_editions(
+        &self,
+        set: &Uuid,
+    ) -> impl Future<Output = Result<Vec<Edition>, AppError>> + Send {
+        let editions = self.editions.lock().unwrap();
+        let found = editions
+            .iter()
+            .filter(|edition| edition.set_uuid == *set)
+            .cloned()
+            .collect();
+        ready(Ok(found))
+    }
+}
+
+impl CardRepository for MemoryRepository {
+    fn insert_cards(&self, cards: &[Card]) -> impl Future<Output = Result<(), AppError>> + Send {
+        self.cards.lock().unwrap().extend_from_slice(cards);
+        ready(Ok(()))
+    }
+
+    fn find_card(&self, id: &Uuid) -> impl Future<Output = Result<Option<Card>, AppError>> + Send {
+        let cards = self.cards.lock().unwrap();
+        let found = cards
+            .iter()
+            .find(|card| card.uuid == *id && card.deleted_at.is_none());
+        ready(Ok(found.cloned()))
+    }
+
+    fn set_cards(&self, set: &Uuid) -> impl Future<Output = Result<Vec<Card>, AppError>> + Send {
+        let cards = self.cards.lock().unwrap();
+        let found = cards
+            .iter()
+            .filter(|card| card.set_uuid == *set && card.deleted_at.is_none())
+            .cloned()
+            .collect();
+        ready(Ok(found))
+    }
+
+    fn count_cards(&self, set: &Uuid) -> impl Future<Output = Result<u64, AppError>> + Send {
+        let cards = self.cards.lock().unwrap();
+        ready(Ok(cards
+            .iter()
+            .filter(|card| card.set_uuid == *set && card.deleted_at.is_none())
+            .count() as u64))
+    }
+}
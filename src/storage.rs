@@ -0,0 +1,873 @@
+use std::{collections::HashMap, fmt, future::Future, time::Duration};
+
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, spec::BinarySubtype, Binary, Bson, DateTime, Document},
+    error::{BulkWriteFailure, ErrorKind, RETRYABLE_WRITE_ERROR, TRANSIENT_TRANSACTION_ERROR},
+    options::{
+        Acknowledgment, ClientOptions, FindOneOptions, IndexOptions, InsertManyOptions,
+        ReadConcern, WriteConcern,
+    },
+    Client, ClientSession, Collection, Database, IndexModel,
+};
+
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+use crate::config::{self, Config};
+use crate::model::{
+    normalize_text, Card, CardRevision, CardSnapshot, CardType, CardTypes, ColumnProfile, Edition,
+    RoundRules, Set, SetChanges, SetVersion, Suite,
+};
+
+/// Connection pool and consistency settings for the shared client, see the
+/// `mongo_*` settings of `Config`. Unset values leave the driver defaults
+/// in place.
+#[derive(Debug, Clone, Default)]
+pub struct MongoSettings {
+    pub max_pool_size: Option<u32>,
+    pub min_pool_size: Option<u32>,
+    pub connect_timeout: Option<Duration>,
+    pub server_selection_timeout: Option<Duration>,
+    pub read_concern: Option<String>,
+    pub write_concern: Option<String>,
+}
+
+impl MongoSettings {
+    pub fn from_config(config: &Config) -> Self {
+        MongoSettings {
+            max_pool_size: config.mongo_max_pool_size,
+            min_pool_size: config.mongo_min_pool_size,
+            connect_timeout: config.mongo_connect_timeout_ms.map(Duration::from_millis),
+            server_selection_timeout: config
+                .mongo_server_selection_timeout_ms
+                .map(Duration::from_millis),
+            read_concern: config.mongo_read_concern.clone(),
+            write_concern: config.mongo_write_concern.clone(),
+        }
+    }
+
+    pub fn apply(&self, options: &mut ClientOptions) {
+        if self.max_pool_size.is_some() {
+            options.max_pool_size = self.max_pool_size;
+        }
+        if self.min_pool_size.is_some() {
+            options.min_pool_size = self.min_pool_size;
+        }
+        if self.connect_timeout.is_some() {
+            options.connect_timeout = self.connect_timeout;
+        }
+        if self.server_selection_timeout.is_some() {
+            options.server_selection_timeout = self.server_selection_timeout;
+        }
+        if let Some(level) = &self.read_concern {
+            options.read_concern = Some(ReadConcern::custom(level.clone()));
+        }
+        if let Some(w) = &self.write_concern {
+            // A number means "acknowledged by that many nodes"; anything else
+            // is a named concern such as "majority" or a replica set tag.
+            let w = match w.parse::<u32>() {
+                Ok(nodes) => Acknowledgment::from(nodes),
+                Err(_) => Acknowledgment::from(w.clone()),
+            };
+            options.write_concern = Some(WriteConcern::builder().w(w).build());
+        }
+    }
+}
+
+static CLIENT: OnceCell<Client> = OnceCell::const_new();
+
+/// Returns the process-wide client, building it on first use so the pool is
+/// shared by every request instead of reconnecting each time.
+pub async fn client() -> Result<Client, mongodb::error::Error> {
+    CLIENT
+        .get_or_try_init(|| async {
+            let config = config::get();
+            let mut options = ClientOptions::parse(&config.mongo_uri).await?;
+            MongoSettings::from_config(config).apply(&mut options);
+            Client::with_options(options)
+        })
+        .await
+        .cloned()
+}
+
+/// The library this deployment serves, see `Config::database`.
+pub fn database_name() -> String {
+    config::get().database.clone()
+}
+
+pub async fn database() -> Result<Database, mongodb::error::Error> {
+    Ok(client().await?.database(&database_name()))
+}
+
+/// The shared client and the library it serves. The server builds one at
+/// startup and hands it to every handler as `web::Data<Store>`, so requests
+/// reuse the pool instead of each opening a connection.
+#[derive(Debug, Clone)]
+pub struct Store {
+    client: Client,
+    database: Database,
+    retry: RetryPolicy,
+}
+
+impl Store {
+    /// The library `config` names, with writes retried as it says.
+    pub async fn connect(config: &Config) -> Result<Self, mongodb::error::Error> {
+        let client = client().await?;
+        let database = client.database(&config.database);
+        Ok(Store {
+            client,
+            database,
+            retry: RetryPolicy::from_config(config),
+        })
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    pub fn retry(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// The same client working on another library, such as one an admin
+    /// named with `X-Database`.
+    pub fn with_database(&self, database: Database) -> Store {
+        Store {
+            client: self.client.clone(),
+            database,
+            retry: self.retry,
+        }
+    }
+
+    pub fn sets(&self) -> Collection<Set> {
+        self.database.collection("sets")
+    }
+
+    pub fn cards(&self) -> Collection<Card> {
+        self.database.collection("cards")
+    }
+}
+
+/// Libraries admins may point privileged routes at: the default one and
+/// those in `Config::databases`.
+pub fn allowed_databases(config: &Config) -> Vec<String> {
+    let mut names = vec![config.database.clone()];
+    for name in &config.databases {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+/// Indexes the library relies on, as (collection, fields, unique). Cards are
+/// looked up by `set_uuid` whenever a set is read, so that one matters as
+/// much as the ids. A user's vote on a card is unique, so two concurrent
+/// first votes cannot both be counted.
+pub const INDEXES: &[(&str, &[&str], bool)] = &[
+    ("sets", &["uuid"], true),
+    ("sets", &["listed_at"], false),
+    ("cards", &["uuid"], true),
+    ("cards", &["set_uuid"], false),
+    ("cards", &["suite"], false),
+    ("cards", &["editions"], false),
+    ("cards", &["pick"], false),
+    ("cards", &["listed_at"], false),
+    ("editions", &["uuid"], true),
+    ("leases", &["name"], true),
+    ("decks", &["uuid"], true),
+    ("card_revisions", &["card_uuid"], false),
+    ("card_votes", &["card_uuid", "user"], true),
+    ("card_stats", &["card_uuid"], true),
+    ("set_favorites", &["set_uuid", "user"], true),
+    ("audit_log", &["at"], false),
+    ("audit_log", &["entity_uuid"], false),
+    ("imports", &["uuid"], true),
+    ("import_events", &["job"], false),
+    ("schema_versions", &["version"], true),
+];
+
+/// Fields searched by word with `$text`, as (collection, field). Mongo
+/// allows one text index per collection.
+pub const TEXT_INDEXES: &[(&str, &str)] = &[("cards", "text")];
+
+pub fn index_model(fields: &[&str], unique: bool) -> IndexModel {
+    let mut keys = Document::new();
+    for field in fields {
+        keys.insert(*field, 1);
+    }
+    IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(unique).build())
+        .build()
+}
+
+/// The name Mongo gives an index on `fields` built by `index_model`.
+pub fn index_name(fields: &[&str]) -> String {
+    fields
+        .iter()
+        .map(|field| format!("{field}_1"))
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Creates any of `INDEXES` and `TEXT_INDEXES` that are missing. Existing indexes are left
+/// alone, so this is cheap to run at every start.
+pub async fn ensure_indexes(database: &Database) -> Result<(), mongodb::error::Error> {
+    for &(collection, fields, unique) in INDEXES {
+        database
+            .collection::<Document>(collection)
+            .create_index(index_model(fields, unique), None)
+            .await?;
+    }
+    for &(collection, field) in TEXT_INDEXES {
+        database
+            .collection::<Document>(collection)
+            .create_index(
+                IndexModel::builder().keys(doc! { field: "text" }).build(),
+                None,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// The fields to `$set` when a card's special changes, so its stored pick
+/// and draw keep up.
+pub fn special_fields(special: &str) -> Document {
+    let rules = RoundRules::from_special(special);
+    doc! {
+        "special": special,
+        "pick": i32::from(rules.pick),
+        "draw": i32::from(rules.draw),
+    }
+}
+
+/// How writes to the library are retried when Mongo fails in a way that
+/// may clear up by itself, such as a dropped connection or an election.
+/// Waits grow exponentially from `base_delay` up to `max_delay`, each cut
+/// to a random fraction so retrying writers don't move in step.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries in all, the first included.
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// See the `retry_*` settings of `Config`.
+    pub fn from_config(config: &Config) -> Self {
+        RetryPolicy {
+            attempts: config.retry_attempts.max(1),
+            base_delay: Duration::from_millis(config.retry_base_ms),
+            max_delay: Duration::from_millis(config.retry_max_ms),
+        }
+    }
+
+    /// Runs `write` until it succeeds, fails for good, or runs out of
+    /// attempts, returning the last error.
+    pub async fn run<T, F, Fut>(&self, mut write: F) -> Result<T, mongodb::error::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, mongodb::error::Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match write().await {
+                Err(err) if attempt < self.attempts && is_transient(&err) => {
+                    let ceiling = self
+                        .base_delay
+                        .saturating_mul(1 << (attempt - 1).min(16))
+                        .min(self.max_delay);
+                    let delay = ceiling.mul_f64(rand::random::<f64>());
+                    eprintln!("retrying a write in {delay:?} after: {err}");
+                    actix_web::rt::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether `err` may not happen again: network failures, a primary being
+/// elected, and whatever the server labels retryable. Duplicate keys,
+/// validation and other write errors are permanent.
+pub fn is_transient(err: &mongodb::error::Error) -> bool {
+    err.contains_label(RETRYABLE_WRITE_ERROR)
+        || err.contains_label(TRANSIENT_TRANSACTION_ERROR)
+        || matches!(
+            *err.kind,
+            ErrorKind::Io(_)
+                | ErrorKind::ConnectionPoolCleared { .. }
+                | ErrorKind::ServerSelection { .. }
+        )
+}
+
+/// Cards written per `insert_many`, so the biggest sets go in as a series
+/// of modest batches instead of one request near Mongo's size limits.
+pub const CARD_BATCH: usize = 1_000;
+
+/// The cards of a set the database refused, each by its position in the
+/// set with the reason. `add_set` fails with it as a custom error, see
+/// `Error::get_custom`.
+#[derive(Debug, Clone)]
+pub struct CardWriteFailure {
+    pub set: String,
+    pub cards: Vec<(usize, String)>,
+}
+
+impl fmt::Display for CardWriteFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} cards of set {:?} could not be written",
+            self.cards.len(),
+            self.set
+        )?;
+        // The first few say enough to find the problem in the sheet.
+        for (index, message) in self.cards.iter().take(10) {
+            write!(f, "; card {}: {message}", index + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Inserts a set's cards `CARD_BATCH` at a time. Each batch is unordered,
+/// so every card in a failing batch is tried and all the refused ones are
+/// reported together. A refusal aborts the transaction, so no later batch
+/// is sent.
+async fn insert_cards(
+    collection: &Collection<Card>,
+    set: &str,
+    cards: &[Card],
+    session: &mut ClientSession,
+) -> Result<(), mongodb::error::Error> {
+    let options = InsertManyOptions::builder().ordered(false).build();
+    for (batch, chunk) in cards.chunks(CARD_BATCH).enumerate() {
+        let Err(err) = collection
+            .insert_many_with_session(chunk, options.clone(), session)
+            .await
+        else {
+            continue;
+        };
+        let ErrorKind::BulkWrite(BulkWriteFailure {
+            write_errors: Some(errors),
+            ..
+        }) = &*err.kind
+        else {
+            return Err(err);
+        };
+        return Err(mongodb::error::Error::custom(CardWriteFailure {
+            set: set.to_string(),
+            cards: errors
+                .iter()
+                .map(|e| (batch * CARD_BATCH + e.index, e.message.clone()))
+                .collect(),
+        }));
+    }
+    Ok(())
+}
+
+/// Matches the binary form `Uuid` takes when serialized into a document.
+pub fn uuid_bson(id: &Uuid) -> Bson {
+    Bson::Binary(Binary {
+        subtype: BinarySubtype::Generic,
+        bytes: id.as_bytes().to_vec(),
+    })
+}
+
+/// Saves an imported set and its cards in one transaction. When the set is
+/// already stored, under the same id or name, incoming cards are matched to
+/// stored ones by suite and normalized text and keep their ids, so only the
+/// real delta is written and favorites, votes and stats on unchanged cards
+/// survive the refresh. On a standalone server, which has no transactions,
+/// a failed write is undone by `write_set_compensated` instead. Transient
+/// failures are retried as `retry` says.
+pub async fn add_set(
+    database: &Database,
+    set: &Set,
+    retry: RetryPolicy,
+) -> Result<SetChanges, mongodb::error::Error> {
+    let changes = retry.run(|| store_set(database, set)).await?;
+    // A refresh that changed nothing, as most scheduled syncs are, is not
+    // worth a version.
+    if changes.changed + changes.added + changes.removed > 0 {
+        snapshot_set(database, changes.uuid, "import").await?;
+    }
+    Ok(changes)
+}
+
+/// One attempt at `add_set`, everything written or nothing.
+async fn store_set(database: &Database, set: &Set) -> Result<SetChanges, mongodb::error::Error> {
+    let mut session = database
+        .collection::<Set>("sets")
+        .client()
+        .start_session(None)
+        .await?;
+    match session.start_transaction(None).await {
+        Ok(()) => match write_set(database, set, &mut session).await {
+            Ok(changes) => {
+                session.commit_transaction().await?;
+                Ok(changes)
+            }
+            Err(err) => {
+                // The write error is the one worth reporting.
+                let _ = session.abort_transaction().await;
+                Err(err)
+            }
+        },
+        Err(err) if matches!(*err.kind, ErrorKind::Transaction { .. }) => {
+            write_set_compensated(database, set, &mut session).await
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// `write_set` without a transaction: the stored cards and editions of the
+/// set are read first and, if any write fails, whatever was written is
+/// deleted and they are put back.
+async fn write_set_compensated(
+    database: &Database,
+    set: &Set,
+    session: &mut ClientSession,
+) -> Result<SetChanges, mongodb::error::Error> {
+    let sets: Collection<Set> = database.collection("sets");
+    let cards: Collection<Card> = database.collection("cards");
+    let editions: Collection<Edition> = database.collection("editions");
+    let existing = sets
+        .find_one(
+            doc! { "$or": [{ "uuid": uuid_bson(&set.uuid) }, { "name": &set.name }] },
+            None,
+        )
+        .await?;
+    let uuid = existing.as_ref().map_or(set.uuid, |stored| stored.uuid);
+    let filter = doc! { "set_uuid": uuid_bson(&uuid) };
+    let (previous_cards, previous_editions): (Vec<Card>, Vec<Edition>) = match existing {
+        Some(_) => (
+            cards
+                .find(filter.clone(), None)
+                .await?
+                .try_collect()
+                .await?,
+            editions
+                .find(filter.clone(), None)
+                .await?
+                .try_collect()
+                .await?,
+        ),
+        None => (Vec::new(), Vec::new()),
+    };
+    let err = match write_set(database, set, session).await {
+        Ok(changes) => return Ok(changes),
+        Err(err) => err,
+    };
+    let restored = async {
+        cards.delete_many(filter.clone(), None).await?;
+        editions.delete_many(filter.clone(), None).await?;
+        if existing.is_none() {
+            sets.delete_one(doc! { "uuid": uuid_bson(&uuid) }, None)
+                .await?;
+        }
+        insert_cards(&cards, &set.name, &previous_cards, session).await?;
+        if !previous_editions.is_empty() {
+            editions.insert_many(&previous_editions, None).await?;
+        }
+        Ok::<_, mongodb::error::Error>(())
+    }
+    .await;
+    if let Err(e) = restored {
+        eprintln!("could not undo the partial import of {:?}: {e}", set.name);
+    }
+    Err(err)
+}
+
+/// Works out what `add_set` would change from reads alone, so a dry run
+/// stores nothing and works on a standalone server too.
+pub async fn preview_set(
+    database: &Database,
+    set: &Set,
+) -> Result<SetChanges, mongodb::error::Error> {
+    let mut session = database
+        .collection::<Set>("sets")
+        .client()
+        .start_session(None)
+        .await?;
+    Ok(plan_set(database, set, &mut session).await?.changes)
+}
+
+/// The writes that bring the stored copy of a set in line with an
+/// imported one, see `plan_set`.
+struct SetPlan {
+    /// The stored set under the same id or name, if there is one.
+    existing: Option<Set>,
+    changes: SetChanges,
+    stale_editions: Vec<Bson>,
+    new_editions: Vec<Edition>,
+    /// Stored cards to rewrite, each with the incoming card replacing it.
+    updated: Vec<(Card, Card)>,
+    added: Vec<Card>,
+    /// Live cards the sheet no longer has, to go to the trash.
+    removed: Vec<Bson>,
+}
+
+/// Compares an imported set with the stored one, reading only.
+///
+/// Editions are matched by printing like cards are by text, so cards keep
+/// pointing at the ids already stored. Incoming cards are matched to live
+/// cards first and trashed ones only after, so a card deleted by hand stays
+/// in the trash unless the sheet still has it.
+async fn plan_set(
+    database: &Database,
+    set: &Set,
+    session: &mut ClientSession,
+) -> Result<SetPlan, mongodb::error::Error> {
+    let sets: Collection<Set> = database.collection("sets");
+    let cards: Collection<Card> = database.collection("cards");
+    let editions: Collection<Edition> = database.collection("editions");
+    let existing = sets
+        .find_one_with_session(
+            doc! { "$or": [{ "uuid": uuid_bson(&set.uuid) }, { "name": &set.name }] },
+            None,
+            session,
+        )
+        .await?;
+    let Some(stored_set) = &existing else {
+        return Ok(SetPlan {
+            existing,
+            changes: SetChanges {
+                uuid: set.uuid,
+                added: set.cards.len(),
+                ..SetChanges::default()
+            },
+            stale_editions: Vec::new(),
+            new_editions: set.editions.clone(),
+            updated: Vec::new(),
+            added: set.cards.clone(),
+            removed: Vec::new(),
+        });
+    };
+    let uuid = stored_set.uuid;
+
+    let mut stored_editions: Vec<Edition> = Vec::new();
+    let mut cursor = editions
+        .find_with_session(doc! { "set_uuid": uuid_bson(&uuid) }, None, session)
+        .await?;
+    while let Some(edition) = cursor.next(session).await.transpose()? {
+        stored_editions.push(edition);
+    }
+    let (edition_ids, new_editions) = match_editions(&stored_editions, &set.editions);
+    let stale_editions = stored_editions
+        .iter()
+        .filter(|stored| !edition_ids.values().any(|id| *id == stored.uuid))
+        .map(|stored| uuid_bson(&stored.uuid))
+        .collect();
+    let new_editions = new_editions
+        .into_iter()
+        .map(|mut edition| {
+            edition.set_uuid = uuid;
+            edition
+        })
+        .collect();
+
+    let mut stored: HashMap<(Suite, String), Vec<Card>> = HashMap::new();
+    let mut trashed: HashMap<(Suite, String), Vec<Card>> = HashMap::new();
+    let mut cursor = cards
+        .find_with_session(doc! { "set_uuid": uuid_bson(&uuid) }, None, session)
+        .await?;
+    while let Some(card) = cursor.next(session).await.transpose()? {
+        match card.deleted_at {
+            None => &mut stored,
+            Some(_) => &mut trashed,
+        }
+        .entry((card.suite, normalize_text(&card.text)))
+        .or_default()
+        .push(card);
+    }
+
+    let mut changes = SetChanges {
+        uuid,
+        ..SetChanges::default()
+    };
+    let mut updated = Vec::new();
+    let mut added = Vec::new();
+    for card in &set.cards {
+        let mut card = card.clone();
+        for edition in &mut card.editions {
+            *edition = edition_ids.get(edition).copied().unwrap_or(*edition);
+        }
+        card.set_uuid = uuid;
+        let key = (card.suite, normalize_text(&card.text));
+        let kept = stored
+            .get_mut(&key)
+            .and_then(Vec::pop)
+            .or_else(|| trashed.get_mut(&key).and_then(Vec::pop));
+        match kept {
+            Some(kept)
+                if kept.deleted_at.is_none()
+                    && kept.text == card.text
+                    && kept.special == card.special
+                    && kept.pick == card.pick
+                    && kept.editions == card.editions =>
+            {
+                changes.unchanged += 1;
+            }
+            Some(kept) => {
+                changes.changed += 1;
+                updated.push((kept, card));
+            }
+            None => added.push(card),
+        }
+    }
+    let removed: Vec<Bson> = stored
+        .into_values()
+        .flatten()
+        .map(|card| uuid_bson(&card.uuid))
+        .collect();
+    changes.removed = removed.len();
+    changes.added = added.len();
+    Ok(SetPlan {
+        existing,
+        changes,
+        stale_editions,
+        new_editions,
+        updated,
+        added,
+        removed,
+    })
+}
+
+/// The writes behind `add_set`.
+async fn write_set(
+    database: &Database,
+    set: &Set,
+    session: &mut ClientSession,
+) -> Result<SetChanges, mongodb::error::Error> {
+    let sets: Collection<Set> = database.collection("sets");
+    let cards: Collection<Card> = database.collection("cards");
+    let editions: Collection<Edition> = database.collection("editions");
+    let plan = plan_set(database, set, session).await?;
+    match &plan.existing {
+        None => {
+            sets.insert_one_with_session(set, None, session).await?;
+        }
+        // Importing a trashed set brings it back, with the trashed cards
+        // the sheet still has.
+        Some(existing) if existing.deleted_at.is_some() => {
+            sets.update_one_with_session(
+                doc! { "uuid": uuid_bson(&existing.uuid) },
+                doc! { "$unset": { "deleted_at": "" } },
+                None,
+                session,
+            )
+            .await?;
+        }
+        Some(_) => {}
+    }
+
+    if !plan.stale_editions.is_empty() {
+        editions
+            .delete_many_with_session(
+                doc! { "uuid": { "$in": plan.stale_editions } },
+                None,
+                session,
+            )
+            .await?;
+    }
+    if !plan.new_editions.is_empty() {
+        editions
+            .insert_many_with_session(&plan.new_editions, None, session)
+            .await?;
+    }
+
+    for (kept, card) in &plan.updated {
+        // A reworded card keeps its old wording as a revision, so a bad
+        // sheet can be undone card by card too.
+        if kept.text != card.text || kept.special != card.special {
+            record_revision_with_session(database, kept, "import", None, session).await?;
+        }
+        let editions: Vec<Bson> = card.editions.iter().map(uuid_bson).collect();
+        let mut fields = doc! {
+            "text": &card.text,
+            "special": &card.special,
+            "pick": i32::from(card.pick),
+            "draw": i32::from(card.draw),
+            "editions": editions,
+        };
+        // New wording is screened again, as the parser did for the sheet.
+        if kept.text != card.text {
+            fields.insert("screened", card.screened.clone());
+        }
+        let mut update = doc! { "$set": fields };
+        if kept.deleted_at.is_some() {
+            update.insert("$unset", doc! { "deleted_at": "" });
+        }
+        cards
+            .update_one_with_session(
+                doc! { "uuid": uuid_bson(&kept.uuid) },
+                update,
+                None,
+                session,
+            )
+            .await?;
+    }
+    // Cards the sheet dropped go to the trash, votes and all, so a bad
+    // sheet can be undone.
+    if !plan.removed.is_empty() {
+        cards
+            .update_many_with_session(
+                doc! { "uuid": { "$in": plan.removed } },
+                doc! { "$set": { "deleted_at": DateTime::now() } },
+                None,
+                session,
+            )
+            .await?;
+    }
+    insert_cards(&cards, &set.name, &plan.added, session).await?;
+    Ok(plan.changes)
+}
+
+/// Pairs incoming editions with the stored ones of the same printing.
+/// Returns the stored id for every incoming id, the new ones mapping to
+/// themselves, and the incoming editions that still need storing.
+pub fn match_editions(
+    stored: &[Edition],
+    incoming: &[Edition],
+) -> (HashMap<Uuid, Uuid>, Vec<Edition>) {
+    let mut ids = HashMap::new();
+    let mut new = Vec::new();
+    for edition in incoming {
+        match stored.iter().find(|s| s.same_printing(edition)) {
+            Some(kept) => {
+                ids.insert(edition.uuid, kept.uuid);
+            }
+            None => {
+                ids.insert(edition.uuid, edition.uuid);
+                new.push(edition.clone());
+            }
+        }
+    }
+    (ids, new)
+}
+
+/// Records the current cards of a set as its next version.
+pub async fn snapshot_set(
+    database: &Database,
+    set_uuid: Uuid,
+    reason: &str,
+) -> Result<SetVersion, mongodb::error::Error> {
+    let cards: Vec<Card> = database
+        .collection::<Card>("cards")
+        .find(
+            doc! { "set_uuid": uuid_bson(&set_uuid), "deleted_at": null },
+            None,
+        )
+        .await?
+        .try_collect()
+        .await?;
+    let versions: Collection<SetVersion> = database.collection("set_versions");
+    let latest = versions
+        .find_one(
+            doc! { "set_uuid": uuid_bson(&set_uuid) },
+            FindOneOptions::builder()
+                .sort(doc! { "version": -1 })
+                .build(),
+        )
+        .await?;
+    let version = SetVersion {
+        set_uuid,
+        version: latest.map_or(1, |v| v.version + 1),
+        reason: reason.to_string(),
+        created_at: DateTime::now(),
+        cards: cards.into_iter().map(CardSnapshot::from).collect(),
+    };
+    versions.insert_one(&version, None).await?;
+    Ok(version)
+}
+
+/// Keeps the wording `card` had before an edit as its next revision.
+pub async fn record_revision(
+    database: &Database,
+    card: &Card,
+    reason: &str,
+    editor: Option<&str>,
+) -> Result<CardRevision, mongodb::error::Error> {
+    let mut session = database
+        .collection::<CardRevision>("card_revisions")
+        .client()
+        .start_session(None)
+        .await?;
+    record_revision_with_session(database, card, reason, editor, &mut session).await
+}
+
+/// `record_revision` as part of the writes of `session`.
+async fn record_revision_with_session(
+    database: &Database,
+    card: &Card,
+    reason: &str,
+    editor: Option<&str>,
+    session: &mut ClientSession,
+) -> Result<CardRevision, mongodb::error::Error> {
+    let revisions: Collection<CardRevision> = database.collection("card_revisions");
+    let latest = revisions
+        .find_one_with_session(
+            doc! { "card_uuid": uuid_bson(&card.uuid) },
+            FindOneOptions::builder()
+                .sort(doc! { "revision": -1 })
+                .build(),
+            session,
+        )
+        .await?;
+    let revision = CardRevision {
+        card_uuid: card.uuid,
+        revision: latest.map_or(1, |r| r.revision + 1),
+        suite: card.suite,
+        text: card.text.clone(),
+        special: card.special.clone(),
+        reason: reason.to_string(),
+        editor: editor.map(str::to_string),
+        created_at: DateTime::now(),
+    };
+    revisions
+        .insert_one_with_session(&revision, None, session)
+        .await?;
+    Ok(revision)
+}
+
+impl CardTypes {
+    pub async fn load(database: &Database) -> Result<Self, mongodb::error::Error> {
+        let mut types = HashMap::new();
+        let mut cursor = database
+            .collection::<CardType>("card_types")
+            .find(None, None)
+            .await?;
+        while let Some(card_type) = cursor.try_next().await? {
+            types.insert(card_type.label.clone(), card_type);
+        }
+        Ok(CardTypes(types))
+    }
+}
+
+impl ColumnProfile {
+    /// The stored profile called `name`, the master sheet's layout if none.
+    pub async fn load(
+        database: &Database,
+        name: Option<&str>,
+    ) -> Result<Option<Self>, mongodb::error::Error> {
+        match name {
+            Some(name) => {
+                database
+                    .collection::<ColumnProfile>("mapping_profiles")
+                    .find_one(doc! { "name": name }, None)
+                    .await
+            }
+            None => Ok(Some(ColumnProfile::default())),
+        }
+    }
+}